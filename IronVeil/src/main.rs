@@ -19,32 +19,53 @@ use tui::{
 
 #[cfg(feature = "kernel")]
 mod os {
+    use nexis_abi::fs::{dirents, DT_DIR, O_DIRECTORY, O_RDONLY, STDOUT};
+    use nexis_abi::sys;
+
     #[inline]
     pub fn write(s: &str) {
-        unsafe {
-            core::arch::asm!(
-                "mov rax, 0",
-                "mov rdi, {ptr}",
-                "mov rsi, {len}",
-                "int 0x80",
-                ptr = in(reg) s.as_ptr(),
-                len = in(reg) s.len(),
-                out("rax") _,
-                options(nostack, preserves_flags)
-            );
-        }
+        let _ = sys::write(STDOUT, s.as_bytes());
     }
     #[allow(dead_code)]
     pub fn exit(code: i32) -> ! {
-        unsafe {
-            core::arch::asm!(
-                "mov rax, 1",
-                "mov rdi, {code}",
-                "int 0x80",
-                code = in(reg) code,
-                options(noreturn)
-            );
+        sys::exit(code)
+    }
+
+    /// Names in a kernel directory; subdirectories get a trailing `/`.
+    pub fn list_dir(path: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let fd = match sys::open(path, O_RDONLY | O_DIRECTORY, 0) {
+            Ok(fd) => fd,
+            Err(_) => return names,
+        };
+        let mut buf = vec![0u8; 4096];
+        while let Ok(n) = sys::getdents(fd, &mut buf) {
+            if n == 0 {
+                break;
+            }
+            for (kind, name) in dirents(&buf[..n]) {
+                let suffix = if kind == DT_DIR { "/" } else { "" };
+                names.push(format!("{}{}", name, suffix));
+            }
         }
+        let _ = sys::close(fd);
+        names
+    }
+
+    /// Up to `max` bytes from the start of a kernel file.
+    pub fn read_prefix(path: &str, max: usize) -> Option<Vec<u8>> {
+        let fd = sys::open(path, O_RDONLY, 0).ok()?;
+        let mut buf = vec![0u8; max];
+        let mut len = 0;
+        while len < max {
+            match sys::read(fd, &mut buf[len..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+        }
+        let _ = sys::close(fd);
+        buf.truncate(len);
+        Some(buf)
     }
 }
 
@@ -146,7 +167,7 @@ impl App {
         }
         #[cfg(feature = "kernel")]
         {
-            self.files.clear();
+            self.files = os::list_dir("/").into_iter().take(200).collect();
         }
     }
 
@@ -427,6 +448,30 @@ fn draw_sidebar<B: tui::backend::Backend>(f: &mut tui::Frame<B>, area: Rect, app
     f.render_widget(List::new(list_items), list_area);
 }
 
+/// Live kernel figures for the Dashboard, read from Nexis's /proc.
+#[cfg(feature = "kernel")]
+fn kernel_status() -> Vec<String> {
+    let read = |path: &str| os::read_prefix(path, 4096).map(|b| String::from_utf8_lossy(&b).into_owned());
+    let mut lines = Vec::new();
+    if let Some(up) = read("/proc/uptime") {
+        lines.push(format!("Kernel uptime: {} s", up.trim()));
+    }
+    if let Some(mem) = read("/proc/meminfo") {
+        let field = |key: &str| {
+            mem.lines()
+                .find_map(|l| l.strip_prefix(key))
+                .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        };
+        if let (Some(total), Some(free)) = (field("MemTotal:"), field("MemFree:")) {
+            lines.push(format!("Memory: {} / {} MiB free", free / 1024, total / 1024));
+        }
+        if let (Some(used), Some(heap)) = (field("HeapUsed:"), field("HeapTotal:")) {
+            lines.push(format!("Kernel heap: {} / {} KiB used", used, heap));
+        }
+    }
+    lines
+}
+
 fn sidebar_items(tab: Tab) -> &'static [&'static str] {
     match tab {
         Tab::Dashboard => &["Status refresh", "Show time"],
@@ -441,13 +486,15 @@ fn sidebar_items(tab: Tab) -> &'static [&'static str] {
 fn draw_main<B: tui::backend::Backend>(f: &mut tui::Frame<B>, area: Rect, app: &mut App) {
     match app.tab {
         Tab::Dashboard => {
-            let text = vec![
+            let mut text = vec![
                 Spans::from(Span::styled("IronVeil — User-space Shell", Style::default().add_modifier(Modifier::BOLD))),
                 Spans::from(format!("Time: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))),
                 Spans::from(format!("VPN: {}", if app.vpn_enabled { "ENABLED" } else { "DISABLED" })),
-                Spans::from(""),
-                Spans::from("Use ←/→ to change tabs, Tab/Shift+Tab to change actions, Enter to run."),
             ];
+            #[cfg(feature = "kernel")]
+            text.extend(kernel_status().into_iter().map(Spans::from));
+            text.push(Spans::from(""));
+            text.push(Spans::from("Use ←/→ to change tabs, Tab/Shift+Tab to change actions, Enter to run."));
             let p = Paragraph::new(text)
                 .block(Block::default().borders(Borders::ALL).title("Dashboard"))
                 .wrap(Wrap { trim: true });
@@ -489,7 +536,8 @@ fn draw_main<B: tui::backend::Backend>(f: &mut tui::Frame<B>, area: Rect, app: &
                 ListItem::new(Span::styled(name.clone(), st))
             }).collect();
 
-            let left = Block::default().borders(Borders::ALL).title("Home files");
+            let title = if cfg!(feature = "kernel") { "Nexis files (/)" } else { "Home files" };
+            let left = Block::default().borders(Borders::ALL).title(title);
             f.render_widget(left, chunks[0]);
             let list_area = Rect { x: chunks[0].x + 1, y: chunks[0].y + 1, width: chunks[0].width.saturating_sub(2), height: chunks[0].height.saturating_sub(2) };
             f.render_widget(List::new(list_items), list_area);
@@ -513,6 +561,15 @@ fn draw_main<B: tui::backend::Backend>(f: &mut tui::Frame<B>, area: Rect, app: &
                     let inner = Rect { x: chunks[1].x + 1, y: chunks[1].y + 1, width: chunks[1].width.saturating_sub(2), height: chunks[1].height.saturating_sub(2) };
                     f.render_widget(para, inner);
                 }
+                #[cfg(feature = "kernel")]
+                {
+                    let preview = os::read_prefix(&format!("/{}", name), 8192)
+                        .map(|b| String::from_utf8_lossy(&b).to_string())
+                        .unwrap_or_default();
+                    let para = Paragraph::new(preview).wrap(Wrap { trim: false });
+                    let inner = Rect { x: chunks[1].x + 1, y: chunks[1].y + 1, width: chunks[1].width.saturating_sub(2), height: chunks[1].height.saturating_sub(2) };
+                    f.render_widget(para, inner);
+                }
             }
         }
         Tab::Logs => {
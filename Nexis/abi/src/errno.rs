@@ -0,0 +1,36 @@
+// Nexis/abi/src/errno.rs
+//
+// Error numbers returned by system calls, negated, in rax. The values match
+// Linux so existing tooling and habits carry over.
+
+pub type Errno = usize;
+
+pub const EPERM: Errno = 1;
+pub const ENOENT: Errno = 2;
+pub const ESRCH: Errno = 3;
+pub const EINTR: Errno = 4;
+pub const EIO: Errno = 5;
+pub const E2BIG: Errno = 7;
+pub const ENOEXEC: Errno = 8;
+pub const EBADF: Errno = 9;
+pub const ECHILD: Errno = 10;
+pub const EAGAIN: Errno = 11;
+pub const ENOMEM: Errno = 12;
+pub const EFAULT: Errno = 14;
+pub const EBUSY: Errno = 16;
+pub const EEXIST: Errno = 17;
+pub const EXDEV: Errno = 18;
+pub const ENOTDIR: Errno = 20;
+pub const EISDIR: Errno = 21;
+pub const EINVAL: Errno = 22;
+pub const EMFILE: Errno = 24;
+pub const EFBIG: Errno = 27;
+pub const ENOSPC: Errno = 28;
+pub const ESPIPE: Errno = 29;
+pub const EROFS: Errno = 30;
+pub const EPIPE: Errno = 32;
+pub const ERANGE: Errno = 34;
+pub const ENAMETOOLONG: Errno = 36;
+pub const ENOSYS: Errno = 38;
+pub const ENOTEMPTY: Errno = 39;
+pub const ELOOP: Errno = 40;
@@ -0,0 +1,143 @@
+// Nexis/abi/src/fs.rs
+//
+// File API types: open flags, seek origins, `stat` and directory records.
+// Values follow Linux where there is an equivalent.
+
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_ACCMODE: u32 = 3;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+pub const O_DIRECTORY: u32 = 0o200000;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// File type bits of `Stat::mode`.
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFIFO: u32 = 0o010000;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stat {
+    pub ino: u64,
+    pub size: u64,
+    /// File type (`S_IF*`) and permission bits.
+    pub mode: u32,
+    pub nlink: u32,
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+/// `Dirent::kind` values.
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+/// Fixed part of a `getdents` record; the NUL-terminated name follows and
+/// `reclen` covers both, rounded up to 8 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Dirent {
+    pub ino: u64,
+    /// Position of the next record, for resuming a listing.
+    pub off: u64,
+    pub reclen: u16,
+    pub kind: u8,
+}
+
+pub const DIRENT_HEADER: usize = 19;
+
+/// Size of the record holding a name of `name_len` bytes.
+pub const fn dirent_reclen(name_len: usize) -> usize {
+    (DIRENT_HEADER + name_len + 1 + 7) & !7
+}
+
+/// Iterate over the records `getdents` wrote into `buf`, yielding
+/// (kind, name) pairs.
+pub fn dirents(buf: &[u8]) -> impl Iterator<Item = (u8, &str)> {
+    let mut off = 0;
+    core::iter::from_fn(move || {
+        let rec = buf.get(off..off + DIRENT_HEADER)?;
+        let reclen = u16::from_le_bytes([rec[16], rec[17]]) as usize;
+        let kind = rec[18];
+        let name = buf.get(off + DIRENT_HEADER..off + reclen)?;
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        off += reclen.max(DIRENT_HEADER + 1);
+        Some((kind, core::str::from_utf8(&name[..len]).unwrap_or("?")))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a record for `name` at `off` in `buf`; returns the next offset.
+    fn put(buf: &mut [u8], off: usize, kind: u8, name: &str) -> usize {
+        let reclen = dirent_reclen(name.len());
+        buf[off + 16..off + 18].copy_from_slice(&(reclen as u16).to_le_bytes());
+        buf[off + 18] = kind;
+        buf[off + DIRENT_HEADER..off + DIRENT_HEADER + name.len()].copy_from_slice(name.as_bytes());
+        off + reclen
+    }
+
+    #[test]
+    fn reclen_is_aligned() {
+        assert_eq!(dirent_reclen(0), 24);
+        assert_eq!(dirent_reclen(4), 24);
+        assert_eq!(dirent_reclen(5), 32);
+    }
+
+    #[test]
+    fn dirents_walks_records() {
+        let mut buf = [0u8; 128];
+        let end = put(&mut buf, 0, DT_DIR, ".");
+        let end = put(&mut buf, end, DT_REG, "hello.txt");
+        let mut it = dirents(&buf[..end]);
+        assert_eq!(it.next(), Some((DT_DIR, ".")));
+        assert_eq!(it.next(), Some((DT_REG, "hello.txt")));
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn dirents_stops_at_truncated_record() {
+        let mut buf = [0u8; 128];
+        let end = put(&mut buf, 0, DT_REG, "a");
+        let end2 = put(&mut buf, end, DT_REG, "truncated");
+        let mut it = dirents(&buf[..end2 - 1]);
+        assert_eq!(it.next(), Some((DT_REG, "a")));
+        assert_eq!(it.next(), None);
+        // a header cut short ends the listing too
+        assert_eq!(dirents(&buf[..DIRENT_HEADER - 1]).next(), None);
+    }
+
+    #[test]
+    fn dirents_stops_at_zero_reclen() {
+        let mut buf = [0u8; 64];
+        let end = put(&mut buf, 0, DT_REG, "a");
+        // the next record's reclen stays 0
+        let mut it = dirents(&buf[..end + DIRENT_HEADER + 8]);
+        assert_eq!(it.next(), Some((DT_REG, "a")));
+        assert_eq!(it.next(), None);
+    }
+}
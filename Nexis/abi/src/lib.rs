@@ -0,0 +1,69 @@
+// Nexis/abi/src/lib.rs
+//
+// The kernel/user contract: syscall numbers, error numbers and the
+// `int 0x80` calling convention. Shared by the kernel, `userland` and
+// IronVeil so the numbers are defined in exactly one place.
+//
+//   rax          syscall number
+//   rdi rsi rdx  arguments 1-3
+//   r10          argument 4
+//   rax          result: >= 0 on success, -errno on failure
+
+#![no_std]
+
+pub mod errno;
+pub mod fs;
+pub mod nr;
+pub mod process;
+#[cfg(target_arch = "x86_64")]
+pub mod raw;
+pub mod resource;
+#[cfg(target_arch = "x86_64")]
+pub mod sys;
+pub mod time;
+
+use errno::Errno;
+
+/// Largest errno; results in `-MAX_ERRNO..0` are errors, anything else is
+/// a value (this keeps large user addresses representable).
+pub const MAX_ERRNO: usize = 4095;
+
+/// Encode a syscall result the way it travels back in rax.
+#[inline]
+pub fn encode(r: Result<usize, Errno>) -> usize {
+    match r {
+        Ok(v) => v,
+        Err(e) => (-(e as isize)) as usize,
+    }
+}
+
+/// Split a raw rax value back into a result.
+#[inline]
+pub fn decode(ret: usize) -> Result<usize, Errno> {
+    if ret > usize::MAX - MAX_ERRNO {
+        Err(ret.wrapping_neg())
+    } else {
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errno_boundary() {
+        assert_eq!(decode(-1isize as usize), Err(1));
+        assert_eq!(decode(-4095isize as usize), Err(MAX_ERRNO));
+        assert_eq!(decode(-4096isize as usize), Ok(-4096isize as usize));
+        assert_eq!(decode(0), Ok(0));
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        for r in [Ok(0), Ok(usize::MAX - MAX_ERRNO), Err(errno::EPERM), Err(MAX_ERRNO)] {
+            assert_eq!(decode(encode(r)), r);
+        }
+        assert_eq!(encode(Err(errno::ENOENT)), -2isize as usize);
+    }
+}
@@ -0,0 +1,31 @@
+// Nexis/abi/src/nr.rs
+//
+// Syscall numbers. Append only: a number, once shipped, keeps its meaning.
+
+/// Write to the console; predates file descriptors.
+pub const SYS_CONSOLE_WRITE: usize = 0;
+pub const SYS_EXIT: usize = 1;
+pub const SYS_LIST_FILES: usize = 2;
+pub const SYS_READ_FILE: usize = 3;
+pub const SYS_GETPID: usize = 4;
+pub const SYS_OPEN: usize = 5;
+pub const SYS_CLOSE: usize = 6;
+pub const SYS_READ: usize = 7;
+pub const SYS_WRITE: usize = 8;
+pub const SYS_LSEEK: usize = 9;
+pub const SYS_STAT: usize = 10;
+pub const SYS_FSTAT: usize = 11;
+pub const SYS_DUP: usize = 12;
+pub const SYS_DUP2: usize = 13;
+pub const SYS_GETDENTS: usize = 14;
+pub const SYS_FORK: usize = 15;
+pub const SYS_EXECVE: usize = 16;
+pub const SYS_WAITPID: usize = 17;
+pub const SYS_SLEEP_MS: usize = 18;
+pub const SYS_NANOSLEEP: usize = 19;
+pub const SYS_NICE: usize = 20;
+pub const SYS_GETRUSAGE: usize = 21;
+pub const SYS_PIPE: usize = 22;
+
+/// One past the highest assigned number.
+pub const NR_SYSCALLS: usize = 23;
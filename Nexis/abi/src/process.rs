@@ -0,0 +1,85 @@
+// Nexis/abi/src/process.rs
+//
+// Process lifecycle: argument passing for `execve` and the status word
+// `waitpid` reports, encoded as on Linux.
+
+/// `waitpid` option: return 0 instead of blocking if no child has exited.
+pub const WNOHANG: usize = 1;
+
+/// `waitpid` pid meaning "any child".
+pub const WAIT_ANY: isize = -1;
+
+/// Most argv or envp entries `execve` accepts.
+pub const MAX_ARGS: usize = 32;
+
+/// Most bytes of argv and envp strings together.
+pub const MAX_ARG_BYTES: usize = 16 * 1024;
+
+/// One string of an `execve` argv/envp array. Arrays end with an entry
+/// whose `ptr` is 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StrRef {
+    pub ptr: usize,
+    pub len: usize,
+}
+
+/// Status of a process that called `exit(code)`.
+pub const fn exit_status(code: i32) -> i32 {
+    (code & 0xff) << 8
+}
+
+/// Status of a process the kernel killed with signal number `sig`.
+pub const fn killed_status(sig: i32) -> i32 {
+    sig & 0x7f
+}
+
+pub const fn exited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+
+pub const fn exit_code(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+
+/// Signal that killed the process, if it did not exit by itself.
+pub const fn term_signal(status: i32) -> Option<i32> {
+    match status & 0x7f {
+        0 => None,
+        sig => Some(sig),
+    }
+}
+
+/// Signal numbers used in `killed_status` (there is no signal delivery
+/// yet; a process that faults is simply terminated).
+pub const SIGILL: i32 = 4;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGSEGV: i32 = 11;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_round_trips() {
+        for code in [0, 1, 42, 255] {
+            let s = exit_status(code);
+            assert!(exited(s));
+            assert_eq!(exit_code(s), code);
+            assert_eq!(term_signal(s), None);
+        }
+        // only the low byte survives, as on Linux
+        assert_eq!(exit_code(exit_status(256 + 3)), 3);
+        assert_eq!(exit_code(exit_status(-1)), 255);
+    }
+
+    #[test]
+    fn killed_status_round_trips() {
+        for sig in [SIGILL, SIGFPE, SIGKILL, SIGSEGV] {
+            let s = killed_status(sig);
+            assert!(!exited(s));
+            assert_eq!(term_signal(s), Some(sig));
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// Nexis/abi/src/raw.rs
+//
+// Raw `int 0x80` entry points. The kernel preserves every register except
+// rax, so only the result is declared as an output.
+//
+// All of these are unsafe for the same reason: the kernel may read or write
+// user memory through the arguments, so any pointers passed must be valid
+// for the syscall being made.
+
+#![allow(clippy::missing_safety_doc)]
+
+use core::arch::asm;
+
+#[inline(always)]
+pub unsafe fn syscall0(nr: usize) -> usize {
+    let ret: usize;
+    asm!("int 0x80", inlateout("rax") nr => ret, options(nostack, preserves_flags));
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall1(nr: usize, a1: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "int 0x80",
+        inlateout("rax") nr => ret,
+        in("rdi") a1,
+        options(nostack, preserves_flags),
+    );
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall2(nr: usize, a1: usize, a2: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "int 0x80",
+        inlateout("rax") nr => ret,
+        in("rdi") a1,
+        in("rsi") a2,
+        options(nostack, preserves_flags),
+    );
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall3(nr: usize, a1: usize, a2: usize, a3: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "int 0x80",
+        inlateout("rax") nr => ret,
+        in("rdi") a1,
+        in("rsi") a2,
+        in("rdx") a3,
+        options(nostack, preserves_flags),
+    );
+    ret
+}
+
+#[inline(always)]
+pub unsafe fn syscall4(nr: usize, a1: usize, a2: usize, a3: usize, a4: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "int 0x80",
+        inlateout("rax") nr => ret,
+        in("rdi") a1,
+        in("rsi") a2,
+        in("rdx") a3,
+        in("r10") a4,
+        options(nostack, preserves_flags),
+    );
+    ret
+}
@@ -0,0 +1,30 @@
+// Nexis/abi/src/resource.rs
+//
+// Scheduling priority and CPU usage: `nice` and `getrusage`.
+
+use crate::time::Timeval;
+
+/// Highest priority (most CPU).
+pub const NICE_MIN: i32 = -20;
+/// Lowest priority.
+pub const NICE_MAX: i32 = 19;
+
+/// `nice` returns `NICE_BIAS - nice`, 1 to 40, so a negative nice value is
+/// never mistaken for an error.
+pub const NICE_BIAS: i32 = 20;
+
+/// `getrusage` target: the calling process.
+pub const RUSAGE_SELF: i32 = 0;
+/// `getrusage` target: all children the caller has waited for.
+pub const RUSAGE_CHILDREN: i32 = -1;
+
+/// CPU time used, as in POSIX `struct rusage` (the other fields are not
+/// tracked, so they are left out).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rusage {
+    /// Time spent running user code.
+    pub ru_utime: Timeval,
+    /// Time spent in the kernel on the process's behalf.
+    pub ru_stime: Timeval,
+}
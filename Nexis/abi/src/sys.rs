@@ -0,0 +1,165 @@
+// Nexis/abi/src/sys.rs
+//
+// Typed wrappers over the raw entry points, one per syscall.
+
+use crate::errno::{Errno, E2BIG};
+use crate::fs::Stat;
+use crate::nr::*;
+use crate::process::{StrRef, MAX_ARGS};
+use crate::resource::{Rusage, NICE_BIAS};
+use crate::time::Timespec;
+use crate::raw::*;
+use crate::decode;
+
+pub type Result<T> = core::result::Result<T, Errno>;
+
+/// Write straight to the console, without going through fd 1.
+pub fn console_write(buf: &[u8]) -> Result<usize> {
+    decode(unsafe { syscall2(SYS_CONSOLE_WRITE, buf.as_ptr() as usize, buf.len()) })
+}
+
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall1(SYS_EXIT, code as usize) };
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Fill `buf` with newline-separated file names; returns bytes written.
+pub fn list_files(buf: &mut [u8]) -> Result<usize> {
+    decode(unsafe { syscall2(SYS_LIST_FILES, buf.as_mut_ptr() as usize, buf.len()) })
+}
+
+/// Copy up to `buf.len()` bytes of file `name` into `buf`; returns bytes read.
+pub fn read_file(name: &str, buf: &mut [u8]) -> Result<usize> {
+    decode(unsafe {
+        syscall4(
+            SYS_READ_FILE,
+            name.as_ptr() as usize,
+            name.len(),
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+        )
+    })
+}
+
+pub fn getpid() -> usize {
+    unsafe { syscall0(SYS_GETPID) }
+}
+
+pub fn open(path: &str, flags: u32, mode: u32) -> Result<usize> {
+    decode(unsafe { syscall4(SYS_OPEN, path.as_ptr() as usize, path.len(), flags as usize, mode as usize) })
+}
+
+pub fn close(fd: usize) -> Result<usize> {
+    decode(unsafe { syscall1(SYS_CLOSE, fd) })
+}
+
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize> {
+    decode(unsafe { syscall3(SYS_READ, fd, buf.as_mut_ptr() as usize, buf.len()) })
+}
+
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize> {
+    decode(unsafe { syscall3(SYS_WRITE, fd, buf.as_ptr() as usize, buf.len()) })
+}
+
+/// Returns the new offset.
+pub fn lseek(fd: usize, offset: i64, whence: usize) -> Result<usize> {
+    decode(unsafe { syscall3(SYS_LSEEK, fd, offset as usize, whence) })
+}
+
+pub fn stat(path: &str) -> Result<Stat> {
+    let mut st = Stat::default();
+    let p = &mut st as *mut Stat as usize;
+    decode(unsafe { syscall3(SYS_STAT, path.as_ptr() as usize, path.len(), p) }).map(|_| st)
+}
+
+pub fn fstat(fd: usize) -> Result<Stat> {
+    let mut st = Stat::default();
+    decode(unsafe { syscall2(SYS_FSTAT, fd, &mut st as *mut Stat as usize) }).map(|_| st)
+}
+
+pub fn dup(fd: usize) -> Result<usize> {
+    decode(unsafe { syscall1(SYS_DUP, fd) })
+}
+
+pub fn dup2(old: usize, new: usize) -> Result<usize> {
+    decode(unsafe { syscall2(SYS_DUP2, old, new) })
+}
+
+/// Fill `buf` with directory records (see `fs::dirents`); 0 at the end.
+pub fn getdents(fd: usize, buf: &mut [u8]) -> Result<usize> {
+    decode(unsafe { syscall3(SYS_GETDENTS, fd, buf.as_mut_ptr() as usize, buf.len()) })
+}
+
+/// Returns the child's pid in the parent and 0 in the child.
+pub fn fork() -> Result<usize> {
+    decode(unsafe { syscall0(SYS_FORK) })
+}
+
+/// Zero-terminated `StrRef` array for `strs`, built on the stack.
+fn str_refs(strs: &[&str]) -> Result<[StrRef; MAX_ARGS + 1]> {
+    let mut refs = [StrRef::default(); MAX_ARGS + 1];
+    if strs.len() > MAX_ARGS {
+        return Err(E2BIG);
+    }
+    for (r, s) in refs.iter_mut().zip(strs) {
+        // slice pointers are never null, even for ""
+        *r = StrRef { ptr: s.as_ptr() as usize, len: s.len() };
+    }
+    Ok(refs)
+}
+
+/// Replace the calling program with the executable at `path`. Only returns
+/// on failure.
+pub fn execve(path: &str, argv: &[&str], envp: &[&str]) -> Result<usize> {
+    let argv = str_refs(argv)?;
+    let envp = str_refs(envp)?;
+    decode(unsafe {
+        syscall4(
+            SYS_EXECVE,
+            path.as_ptr() as usize,
+            path.len(),
+            argv.as_ptr() as usize,
+            envp.as_ptr() as usize,
+        )
+    })
+}
+
+/// Wait for child `pid` (or any child, with `WAIT_ANY`) to exit. Returns
+/// the child's pid and status, or pid 0 under `WNOHANG` if none has exited.
+pub fn waitpid(pid: isize, options: usize) -> Result<(usize, i32)> {
+    let mut status = 0i32;
+    let r = decode(unsafe { syscall3(SYS_WAITPID, pid as usize, &mut status as *mut i32 as usize, options) })?;
+    Ok((r, status))
+}
+
+/// Block for at least `ms` milliseconds.
+pub fn sleep_ms(ms: u64) -> Result<usize> {
+    decode(unsafe { syscall1(SYS_SLEEP_MS, ms as usize) })
+}
+
+/// Block for at least `req`. Sleeps are never interrupted, so there is no
+/// remaining time to report.
+pub fn nanosleep(req: &Timespec) -> Result<usize> {
+    decode(unsafe { syscall2(SYS_NANOSLEEP, req as *const Timespec as usize, 0) })
+}
+
+/// Add `inc` to the caller's nice value (the result is clamped to
+/// `NICE_MIN..=NICE_MAX`) and return the new value.
+pub fn nice(inc: i32) -> Result<i32> {
+    decode(unsafe { syscall1(SYS_NICE, inc as isize as usize) }).map(|r| NICE_BIAS - r as i32)
+}
+
+/// CPU time used by the caller (`RUSAGE_SELF`) or by its reaped children
+/// (`RUSAGE_CHILDREN`).
+pub fn getrusage(who: i32) -> Result<Rusage> {
+    let mut ru = Rusage::default();
+    decode(unsafe { syscall2(SYS_GETRUSAGE, who as isize as usize, &mut ru as *mut Rusage as usize) }).map(|_| ru)
+}
+
+/// Create a pipe; returns (read fd, write fd).
+pub fn pipe() -> Result<(usize, usize)> {
+    let mut fds = [0usize; 2];
+    decode(unsafe { syscall1(SYS_PIPE, fds.as_mut_ptr() as usize) }).map(|_| (fds[0], fds[1]))
+}
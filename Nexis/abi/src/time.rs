@@ -0,0 +1,79 @@
+// Nexis/abi/src/time.rs
+//
+// Time values passed to and from the kernel.
+
+pub const NANOS_PER_SEC: i64 = 1_000_000_000;
+pub const MICROS_PER_SEC: i64 = 1_000_000;
+
+/// A duration, as in POSIX `struct timespec`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    /// 0 to 999 999 999.
+    pub tv_nsec: i64,
+}
+
+impl Timespec {
+    pub const fn from_millis(ms: u64) -> Self {
+        Timespec { tv_sec: (ms / 1000) as i64, tv_nsec: (ms % 1000) as i64 * 1_000_000 }
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        self.tv_sec >= 0 && self.tv_nsec >= 0 && self.tv_nsec < NANOS_PER_SEC
+    }
+
+    /// Whole milliseconds, rounded up so a sleep is never cut short.
+    pub const fn as_millis_ceil(&self) -> u64 {
+        (self.tv_sec as u64).saturating_mul(1000).saturating_add((self.tv_nsec as u64).div_ceil(1_000_000))
+    }
+}
+
+/// A duration in microseconds, as in POSIX `struct timeval`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    /// 0 to 999 999.
+    pub tv_usec: i64,
+}
+
+impl Timeval {
+    pub const fn from_micros(us: u64) -> Self {
+        Timeval { tv_sec: (us / 1_000_000) as i64, tv_usec: (us % 1_000_000) as i64 }
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.tv_sec as u64 * 1000 + self.tv_usec as u64 / 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timespec_millis() {
+        let t = Timespec::from_millis(1234);
+        assert_eq!(t, Timespec { tv_sec: 1, tv_nsec: 234_000_000 });
+        assert!(t.is_valid());
+        assert_eq!(t.as_millis_ceil(), 1234);
+        assert_eq!(Timespec { tv_sec: 0, tv_nsec: 1 }.as_millis_ceil(), 1);
+        assert_eq!(Timespec { tv_sec: i64::MAX, tv_nsec: 0 }.as_millis_ceil(), u64::MAX);
+    }
+
+    #[test]
+    fn timespec_validity() {
+        assert!(!Timespec { tv_sec: -1, tv_nsec: 0 }.is_valid());
+        assert!(!Timespec { tv_sec: 0, tv_nsec: -1 }.is_valid());
+        assert!(!Timespec { tv_sec: 0, tv_nsec: NANOS_PER_SEC }.is_valid());
+        assert!(Timespec { tv_sec: 0, tv_nsec: NANOS_PER_SEC - 1 }.is_valid());
+    }
+
+    #[test]
+    fn timeval_micros() {
+        let t = Timeval::from_micros(2_500_999);
+        assert_eq!(t, Timeval { tv_sec: 2, tv_usec: 500_999 });
+        assert_eq!(t.as_millis(), 2500);
+    }
+}
@@ -1,5 +1,8 @@
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Compile assembly (switch.S)
@@ -11,9 +14,107 @@ fn main() {
 
     // Ensure linker script is passed to the linker
     println!("cargo:rerun-if-changed=linker.ld");
-    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let linker = PathBuf::from(manifest_dir).join("linker.ld");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let linker = manifest_dir.join("linker.ld");
     println!("cargo:rustc-link-arg=-T{}", linker.display());
 
-    // Optional: allow embedding other artifacts later
-}
\ No newline at end of file
+    // Embed the userland ELF (built separately, see userland/.cargo/config.toml).
+    // A missing binary is not fatal: the kernel just boots without it.
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let userland_bins = manifest_dir.join("userland/target/x86_64-unknown-none/release");
+    println!("cargo:rerun-if-env-changed=NEXIS_USERLAND");
+    let userland = env::var("NEXIS_USERLAND")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| userland_bins.join("userland"));
+    println!("cargo:rerun-if-changed={}", userland.display());
+    let embedded = out_dir.join("userland.elf");
+    if userland.exists() {
+        fs::copy(&userland, &embedded).expect("copy userland binary");
+    } else {
+        println!("cargo:warning=userland binary not found at {}", userland.display());
+        fs::write(&embedded, []).expect("write empty userland image");
+    }
+
+    // Initial ramdisk: a prebuilt ustar/cpio archive from NEXIS_INITRD, or
+    // the initrd/ directory packed as ustar together with whichever userland
+    // programs have been built. Unpacked into / by fs_init.
+    println!("cargo:rerun-if-env-changed=NEXIS_INITRD");
+    let initrd_out = out_dir.join("initrd.img");
+    match env::var("NEXIS_INITRD") {
+        Ok(archive) => {
+            println!("cargo:rerun-if-changed={}", archive);
+            fs::copy(&archive, &initrd_out).expect("copy NEXIS_INITRD archive");
+        }
+        Err(_) => {
+            let dir = manifest_dir.join("initrd");
+            println!("cargo:rerun-if-changed={}", dir.display());
+            let mut tar = Vec::new();
+            if dir.is_dir() {
+                pack_dir(&dir, "", &mut tar).expect("pack initrd directory");
+            }
+            for (bin, path) in USER_PROGRAMS {
+                let bin = userland_bins.join(bin);
+                println!("cargo:rerun-if-changed={}", bin.display());
+                if let Ok(data) = fs::read(&bin) {
+                    pack_file(path, 0o755, &data, &mut tar);
+                }
+            }
+            if !tar.is_empty() {
+                tar.resize(tar.len() + 1024, 0); // end-of-archive marker
+            }
+            fs::write(&initrd_out, tar).expect("write initrd image");
+        }
+    }
+}
+
+/// Userland binaries installed into the initrd: (cargo bin name, path).
+const USER_PROGRAMS: [(&str, &str); 3] = [("init", "sbin/init"), ("sh", "bin/sh"), ("userland", "bin/demo")];
+
+/// Append `dir` to a ustar archive, directories before their contents.
+fn pack_dir(dir: &Path, prefix: &str, tar: &mut Vec<u8>) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let meta = entry.metadata()?;
+        let mode = meta.permissions().mode() & 0o7777;
+        if meta.is_dir() {
+            tar_header(tar, &format!("{}/", name), mode, 0, b'5');
+            pack_dir(&path, &format!("{}/", name), tar)?;
+        } else if meta.is_file() {
+            pack_file(&name, mode, &fs::read(&path)?, tar);
+        }
+    }
+    Ok(())
+}
+
+fn pack_file(name: &str, mode: u32, data: &[u8], tar: &mut Vec<u8>) {
+    tar_header(tar, name, mode, data.len(), b'0');
+    tar.extend_from_slice(data);
+    tar.resize((tar.len() + 511) & !511, 0);
+}
+
+fn tar_header(tar: &mut Vec<u8>, name: &str, mode: u32, size: usize, kind: u8) {
+    assert!(name.len() < 100, "initrd path too long for ustar: {}", name);
+    let mut h = [0u8; 512];
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    let octal = |h: &mut [u8; 512], off: usize, width: usize, v: u64| {
+        let s = format!("{:0w$o}", v, w = width - 1);
+        h[off..off + width - 1].copy_from_slice(s.as_bytes());
+    };
+    octal(&mut h, 100, 8, mode as u64);
+    octal(&mut h, 108, 8, 0); // uid
+    octal(&mut h, 116, 8, 0); // gid
+    octal(&mut h, 124, 12, size as u64);
+    octal(&mut h, 136, 12, 0); // mtime
+    h[156] = kind;
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+    h[148..156].fill(b' ');
+    let sum: u32 = h.iter().map(|&b| b as u32).sum();
+    let s = format!("{:06o}\0 ", sum);
+    h[148..156].copy_from_slice(s.as_bytes());
+    tar.extend_from_slice(&h);
+}
@@ -0,0 +1,316 @@
+// Nexis/src/ata.rs
+//
+// Legacy ATA (IDE) disks in PIO mode on the two ISA-compatible channels.
+// Drives are found with IDENTIFY and registered as hda/hdb (primary
+// master/slave) and hdc/hdd (secondary). Requests use 28-bit LBA when they
+// fit and 48-bit LBA otherwise; every sector's completion is signalled by
+// IRQ14 (primary) or IRQ15 (secondary).
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::block::{self, BlockDevice, BlockError, BlockResult, SECTOR_SIZE};
+use crate::waitqueue::WaitQueue;
+
+// task-file registers, relative to the channel's base port
+const REG_DATA: u16 = 0;
+const REG_SECCOUNT: u16 = 2;
+const REG_LBA0: u16 = 3;
+const REG_LBA1: u16 = 4;
+const REG_LBA2: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+const CMD_READ: u8 = 0x20;
+const CMD_READ_EXT: u8 = 0x24;
+const CMD_WRITE: u8 = 0x30;
+const CMD_WRITE_EXT: u8 = 0x34;
+const CMD_FLUSH: u8 = 0xE7;
+const CMD_FLUSH_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+/// Highest sector 28-bit LBA can address, plus one.
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Sectors per command; 256 is what a zero count means in 28-bit mode.
+const MAX_SECTORS: usize = 256;
+
+const TIMEOUT_MS: u64 = 5000;
+
+struct Channel {
+    base: u16,
+    ctrl: u16,
+    /// Serialises the two drives sharing the task-file registers.
+    lock: Mutex<()>,
+    irq_seen: AtomicBool,
+    irq_wait: WaitQueue,
+}
+
+impl Channel {
+    const fn new(base: u16, ctrl: u16) -> Self {
+        Channel { base, ctrl, lock: Mutex::new(()), irq_seen: AtomicBool::new(false), irq_wait: WaitQueue::new() }
+    }
+
+    fn read(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.base + reg).read() }
+    }
+
+    fn write(&self, reg: u16, v: u8) {
+        unsafe { Port::<u8>::new(self.base + reg).write(v) }
+    }
+
+    /// Status without acknowledging a pending interrupt.
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.ctrl).read() }
+    }
+
+    /// The 400ns a drive needs after selection: four alternate-status reads.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    /// Spin until BSY clears (polling; used where no IRQ is raised).
+    fn wait_not_busy(&self) -> BlockResult<u8> {
+        let deadline = crate::pit::ticks() + crate::pit::ms_to_ticks(TIMEOUT_MS);
+        loop {
+            let s = self.alt_status();
+            if s & STATUS_BSY == 0 {
+                return Ok(s);
+            }
+            if s == 0xFF || crate::pit::ticks() > deadline {
+                return Err(BlockError::Io);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Sleep until the drive's interrupt, then check the result.
+    fn wait_irq(&self) -> BlockResult<()> {
+        let deadline = crate::pit::ticks() + crate::pit::ms_to_ticks(TIMEOUT_MS);
+        self.irq_wait
+            .wait_timeout(deadline, || self.irq_seen.swap(false, Ordering::AcqRel).then_some(()))
+            .ok_or(BlockError::Io)?;
+        let s = self.wait_not_busy()?;
+        if s & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    /// Wait for the drive to ask for data (before the first write sector).
+    fn wait_drq(&self) -> BlockResult<()> {
+        let s = self.wait_not_busy()?;
+        if s & (STATUS_ERR | STATUS_DF) != 0 || s & STATUS_DRQ == 0 {
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    fn read_words(&self, buf: &mut [u8]) {
+        let mut port = Port::<u16>::new(self.base + REG_DATA);
+        for pair in buf.chunks_exact_mut(2) {
+            pair.copy_from_slice(&unsafe { port.read() }.to_le_bytes());
+        }
+    }
+
+    fn write_words(&self, buf: &[u8]) {
+        let mut port = Port::<u16>::new(self.base + REG_DATA);
+        for pair in buf.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([pair[0], pair[1]])) };
+        }
+    }
+}
+
+static CHANNELS: [Channel; 2] = [Channel::new(0x1F0, 0x3F6), Channel::new(0x170, 0x376)];
+
+/// Called from the IRQ14/IRQ15 handlers. Reading the status register
+/// acknowledges the interrupt at the drive.
+pub fn handle_irq(channel: usize) {
+    let ch = &CHANNELS[channel];
+    ch.read(REG_STATUS);
+    ch.irq_seen.store(true, Ordering::Release);
+    ch.irq_wait.wake_all();
+}
+
+pub struct AtaDrive {
+    name: String,
+    channel: &'static Channel,
+    slave: bool,
+    sectors: u64,
+    lba48: bool,
+    model: String,
+}
+
+impl AtaDrive {
+    /// Load the task file for a transfer of `count` (1..=256) sectors.
+    fn setup(&self, lba: u64, count: usize, cmd28: u8, cmd48: u8) -> BlockResult<()> {
+        let ch = self.channel;
+        let slave = (self.slave as u8) << 4;
+        let end = lba + count as u64;
+        if end <= LBA28_LIMIT {
+            ch.write(REG_DRIVE, 0xE0 | slave | ((lba >> 24) & 0x0F) as u8);
+            ch.delay();
+            ch.wait_not_busy()?;
+            ch.write(REG_SECCOUNT, count as u8); // 256 wraps to 0, as intended
+            ch.write(REG_LBA0, lba as u8);
+            ch.write(REG_LBA1, (lba >> 8) as u8);
+            ch.write(REG_LBA2, (lba >> 16) as u8);
+            ch.irq_seen.store(false, Ordering::Release);
+            ch.write(REG_COMMAND, cmd28);
+        } else if self.lba48 {
+            ch.write(REG_DRIVE, 0x40 | slave);
+            ch.delay();
+            ch.wait_not_busy()?;
+            // high bytes first, then low bytes, through the same registers
+            ch.write(REG_SECCOUNT, (count >> 8) as u8);
+            ch.write(REG_LBA0, (lba >> 24) as u8);
+            ch.write(REG_LBA1, (lba >> 32) as u8);
+            ch.write(REG_LBA2, (lba >> 40) as u8);
+            ch.write(REG_SECCOUNT, count as u8);
+            ch.write(REG_LBA0, lba as u8);
+            ch.write(REG_LBA1, (lba >> 8) as u8);
+            ch.write(REG_LBA2, (lba >> 16) as u8);
+            ch.irq_seen.store(false, Ordering::Release);
+            ch.write(REG_COMMAND, cmd48);
+        } else {
+            return Err(BlockError::OutOfRange);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        block::check_request(self, lba, buf.len())?;
+        let _g = self.channel.lock.lock();
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let at = lba + (i * MAX_SECTORS) as u64;
+            self.setup(at, chunk.len() / SECTOR_SIZE, CMD_READ, CMD_READ_EXT)?;
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                self.channel.wait_irq()?;
+                self.channel.read_words(sector);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        block::check_request(self, lba, buf.len())?;
+        let _g = self.channel.lock.lock();
+        for (i, chunk) in buf.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let at = lba + (i * MAX_SECTORS) as u64;
+            self.setup(at, chunk.len() / SECTOR_SIZE, CMD_WRITE, CMD_WRITE_EXT)?;
+            // no interrupt before the first sector, one after each
+            self.channel.wait_drq()?;
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                self.channel.write_words(sector);
+                self.channel.wait_irq()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        let ch = self.channel;
+        let _g = ch.lock.lock();
+        ch.write(REG_DRIVE, 0xE0 | (self.slave as u8) << 4);
+        ch.delay();
+        ch.wait_not_busy()?;
+        ch.irq_seen.store(false, Ordering::Release);
+        ch.write(REG_COMMAND, if self.lba48 { CMD_FLUSH_EXT } else { CMD_FLUSH });
+        ch.wait_irq()
+    }
+}
+
+/// IDENTIFY string fields hold two characters per word, high byte first.
+fn ata_string(words: &[u8]) -> String {
+    let mut s = String::new();
+    for pair in words.chunks_exact(2) {
+        s.push(pair[1] as char);
+        s.push(pair[0] as char);
+    }
+    String::from(s.trim())
+}
+
+/// Run IDENTIFY on one position; `None` if there is no ATA disk there.
+fn identify(channel: &'static Channel, slave: bool, name: String) -> Option<AtaDrive> {
+    let ch = channel;
+    ch.write(REG_DRIVE, 0xA0 | (slave as u8) << 4);
+    ch.delay();
+    ch.write(REG_SECCOUNT, 0);
+    ch.write(REG_LBA0, 0);
+    ch.write(REG_LBA1, 0);
+    ch.write(REG_LBA2, 0);
+    ch.write(REG_COMMAND, CMD_IDENTIFY);
+    let s = ch.alt_status();
+    if s == 0 || s == 0xFF {
+        return None; // nothing attached, or a floating bus
+    }
+    ch.wait_not_busy().ok()?;
+    if ch.read(REG_LBA1) != 0 || ch.read(REG_LBA2) != 0 {
+        return None; // ATAPI or SATA signature, not a plain ATA disk
+    }
+    let s = ch.wait_not_busy().ok()?;
+    if s & STATUS_ERR != 0 || s & STATUS_DRQ == 0 {
+        return None;
+    }
+    let mut id = [0u8; SECTOR_SIZE];
+    ch.read_words(&mut id);
+    ch.read(REG_STATUS); // clear the IRQ IDENTIFY raised
+
+    let word = |i: usize| u16::from_le_bytes([id[i * 2], id[i * 2 + 1]]);
+    let lba48 = word(83) & (1 << 10) != 0;
+    let sectors = if lba48 {
+        (0..4).fold(0u64, |acc, i| acc | (word(100 + i) as u64) << (16 * i))
+    } else {
+        word(60) as u64 | (word(61) as u64) << 16
+    };
+    if sectors == 0 {
+        return None; // CHS-only drive
+    }
+    Some(AtaDrive { name, channel, slave, sectors, lba48, model: ata_string(&id[54..94]) })
+}
+
+/// Probe both channels and register every disk found.
+pub fn init() {
+    const NAMES: [&str; 4] = ["hda", "hdb", "hdc", "hdd"];
+    for (c, channel) in CHANNELS.iter().enumerate() {
+        if channel.alt_status() == 0xFF {
+            continue; // no controller on this channel
+        }
+        unsafe { Port::<u8>::new(channel.ctrl).write(0) }; // nIEN off: interrupts on
+        for slave in [false, true] {
+            let name = String::from(NAMES[c * 2 + slave as usize]);
+            if let Some(drive) = identify(channel, slave, name) {
+                crate::vga::vprintln!(
+                    "{}: ATA {} MiB, LBA{}, \"{}\"",
+                    drive.name,
+                    drive.sectors * SECTOR_SIZE as u64 / (1024 * 1024),
+                    if drive.lba48 { 48 } else { 28 },
+                    drive.model
+                );
+                block::register(Arc::new(drive));
+            }
+        }
+    }
+    crate::interrupts::unmask_irq(14);
+    crate::interrupts::unmask_irq(15);
+}
@@ -0,0 +1,359 @@
+// Nexis/src/block.rs
+//
+// Block layer: the `BlockDevice` trait drivers implement, and `Disk`, which
+// puts a write-back buffer cache in front of a device. Filesystems talk to
+// a `Disk`, never to a driver directly.
+//
+// The cache holds single sectors and evicts the least recently used one
+// when full, writing it back first if dirty. Misses are read in runs of
+// consecutive sectors, and `sync` coalesces dirty sectors the same way, so
+// a driver sees few, large requests.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// Sectors cached per disk (512 KiB).
+const CACHE_SECTORS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// Request past the end of the device.
+    OutOfRange,
+    /// Buffer is not a whole number of sectors.
+    BadLength,
+    ReadOnly,
+    /// The device reported an error.
+    Io,
+}
+
+impl BlockError {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BlockError::OutOfRange => "out of range",
+            BlockError::BadLength => "bad length",
+            BlockError::ReadOnly => "read-only device",
+            BlockError::Io => "I/O error",
+        }
+    }
+}
+
+pub type BlockResult<T> = Result<T, BlockError>;
+
+impl From<BlockError> for crate::fs::FsError {
+    fn from(e: BlockError) -> Self {
+        match e {
+            BlockError::ReadOnly => crate::fs::FsError::ReadOnly,
+            _ => crate::fs::FsError::Io,
+        }
+    }
+}
+
+/// A sector-addressed device. Buffers are always a whole number of
+/// sectors; requests may be of any length.
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+    fn sector_count(&self) -> u64;
+    fn read_only(&self) -> bool {
+        false
+    }
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()>;
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> BlockResult<()>;
+    /// Make completed writes durable (drain the device's own cache).
+    fn flush(&self) -> BlockResult<()> {
+        Ok(())
+    }
+}
+
+/// Check a request of `len` bytes at `lba` against `dev`.
+pub fn check_request(dev: &dyn BlockDevice, lba: u64, len: usize) -> BlockResult<()> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::BadLength);
+    }
+    let count = (len / SECTOR_SIZE) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= dev.sector_count() => Ok(()),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+struct Buffer {
+    data: Box<[u8; SECTOR_SIZE]>,
+    dirty: bool,
+    stamp: u64,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CacheStats {
+    pub cached: usize,
+    pub dirty: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64,
+}
+
+struct Cache {
+    buffers: BTreeMap<u64, Buffer>,
+    /// stamp -> lba, oldest first
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    writebacks: u64,
+}
+
+impl Cache {
+    fn new() -> Self {
+        Cache {
+            buffers: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            writebacks: 0,
+        }
+    }
+
+    fn touch(&mut self, lba: u64) {
+        self.clock += 1;
+        let stamp = self.clock;
+        if let Some(b) = self.buffers.get_mut(&lba) {
+            self.lru.remove(&b.stamp);
+            b.stamp = stamp;
+            self.lru.insert(stamp, lba);
+        }
+    }
+
+    fn insert(&mut self, lba: u64, data: &[u8], dirty: bool) {
+        self.clock += 1;
+        let stamp = self.clock;
+        match self.buffers.get_mut(&lba) {
+            Some(b) => {
+                b.data.copy_from_slice(data);
+                b.dirty |= dirty;
+                self.lru.remove(&b.stamp);
+                b.stamp = stamp;
+            }
+            None => {
+                let mut buf = Box::new([0u8; SECTOR_SIZE]);
+                buf.copy_from_slice(data);
+                self.buffers.insert(lba, Buffer { data: buf, dirty, stamp });
+            }
+        }
+        self.lru.insert(stamp, lba);
+    }
+
+    /// Drop least recently used sectors until at most `CACHE_SECTORS` remain.
+    fn shrink(&mut self, dev: &dyn BlockDevice) -> BlockResult<()> {
+        while self.buffers.len() > CACHE_SECTORS {
+            let (stamp, lba) = match self.lru.iter().next() {
+                Some((&s, &l)) => (s, l),
+                None => break,
+            };
+            if let Some(b) = self.buffers.get(&lba) {
+                if b.dirty {
+                    dev.write_sectors(lba, &b.data[..])?;
+                    self.writebacks += 1;
+                }
+            }
+            self.lru.remove(&stamp);
+            self.buffers.remove(&lba);
+        }
+        Ok(())
+    }
+}
+
+/// A block device with its buffer cache.
+pub struct Disk {
+    dev: Arc<dyn BlockDevice>,
+    cache: Mutex<Cache>,
+}
+
+impl Disk {
+    pub fn new(dev: Arc<dyn BlockDevice>) -> Self {
+        Disk { dev, cache: Mutex::new(Cache::new()) }
+    }
+
+    pub fn name(&self) -> &str {
+        self.dev.name()
+    }
+
+    pub fn sector_count(&self) -> u64 {
+        self.dev.sector_count()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.sector_count() * SECTOR_SIZE as u64
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.dev.read_only()
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.dev
+    }
+
+    /// Read whole sectors starting at `lba`.
+    pub fn read(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        check_request(&*self.dev, lba, buf.len())?;
+        let mut cache = self.cache.lock();
+        let count = buf.len() / SECTOR_SIZE;
+        let mut i = 0;
+        while i < count {
+            let sector = lba + i as u64;
+            if let Some(b) = cache.buffers.get(&sector) {
+                buf[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE].copy_from_slice(&b.data[..]);
+                cache.hits += 1;
+                cache.touch(sector);
+                i += 1;
+                continue;
+            }
+            // read the whole run of missing sectors in one request
+            let mut run = 1;
+            while i + run < count && !cache.buffers.contains_key(&(sector + run as u64)) {
+                run += 1;
+            }
+            let chunk = &mut buf[i * SECTOR_SIZE..(i + run) * SECTOR_SIZE];
+            self.dev.read_sectors(sector, chunk)?;
+            cache.misses += run as u64;
+            for (j, data) in chunk.chunks(SECTOR_SIZE).enumerate() {
+                cache.insert(sector + j as u64, data, false);
+            }
+            i += run;
+        }
+        cache.shrink(&*self.dev)
+    }
+
+    /// Write whole sectors starting at `lba`. The data stays in the cache
+    /// until it is evicted or `sync` is called.
+    pub fn write(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        check_request(&*self.dev, lba, buf.len())?;
+        if self.dev.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        let mut cache = self.cache.lock();
+        for (i, data) in buf.chunks(SECTOR_SIZE).enumerate() {
+            cache.insert(lba + i as u64, data, true);
+        }
+        cache.shrink(&*self.dev)
+    }
+
+    /// Read `buf.len()` bytes at byte `offset`.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> BlockResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let lba = pos / SECTOR_SIZE as u64;
+            let in_sector = (pos % SECTOR_SIZE as u64) as usize;
+            let left = buf.len() - done;
+            if in_sector == 0 && left >= SECTOR_SIZE {
+                // aligned middle part goes straight into `buf`
+                let n = left / SECTOR_SIZE * SECTOR_SIZE;
+                self.read(lba, &mut buf[done..done + n])?;
+                done += n;
+                continue;
+            }
+            let n = (SECTOR_SIZE - in_sector).min(left);
+            self.read(lba, &mut sector)?;
+            buf[done..done + n].copy_from_slice(&sector[in_sector..in_sector + n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` at byte `offset`, reading partial sectors first.
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> BlockResult<()> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let lba = pos / SECTOR_SIZE as u64;
+            let in_sector = (pos % SECTOR_SIZE as u64) as usize;
+            let left = buf.len() - done;
+            if in_sector == 0 && left >= SECTOR_SIZE {
+                let n = left / SECTOR_SIZE * SECTOR_SIZE;
+                self.write(lba, &buf[done..done + n])?;
+                done += n;
+                continue;
+            }
+            let n = (SECTOR_SIZE - in_sector).min(left);
+            self.read(lba, &mut sector)?;
+            sector[in_sector..in_sector + n].copy_from_slice(&buf[done..done + n]);
+            self.write(lba, &sector)?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Write every dirty sector back, in runs, then flush the device.
+    pub fn sync(&self) -> BlockResult<()> {
+        let mut cache = self.cache.lock();
+        let dirty: Vec<u64> = cache.buffers.iter().filter(|(_, b)| b.dirty).map(|(&l, _)| l).collect();
+        let mut i = 0;
+        while i < dirty.len() {
+            let mut run = 1;
+            while i + run < dirty.len() && dirty[i + run] == dirty[i] + run as u64 {
+                run += 1;
+            }
+            let mut data = Vec::with_capacity(run * SECTOR_SIZE);
+            for lba in &dirty[i..i + run] {
+                data.extend_from_slice(&cache.buffers[lba].data[..]);
+            }
+            self.dev.write_sectors(dirty[i], &data)?;
+            for lba in &dirty[i..i + run] {
+                if let Some(b) = cache.buffers.get_mut(lba) {
+                    b.dirty = false;
+                }
+            }
+            cache.writebacks += run as u64;
+            i += run;
+        }
+        self.dev.flush()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.cache.lock();
+        CacheStats {
+            cached: cache.buffers.len(),
+            dirty: cache.buffers.values().filter(|b| b.dirty).count(),
+            hits: cache.hits,
+            misses: cache.misses,
+            writebacks: cache.writebacks,
+        }
+    }
+}
+
+static DISKS: Mutex<Vec<Arc<Disk>>> = Mutex::new(Vec::new());
+
+/// Put `dev` behind a cache and make it visible by name.
+pub fn register(dev: Arc<dyn BlockDevice>) -> Arc<Disk> {
+    let disk = Arc::new(Disk::new(dev));
+    DISKS.lock().push(disk.clone());
+    disk
+}
+
+pub fn get(name: &str) -> Option<Arc<Disk>> {
+    DISKS.lock().iter().find(|d| d.name() == name).cloned()
+}
+
+pub fn disks() -> Vec<Arc<Disk>> {
+    DISKS.lock().clone()
+}
+
+/// Write back every disk's cache. Returns the names of disks that failed.
+pub fn sync_all() -> Vec<(String, BlockError)> {
+    let mut failed = Vec::new();
+    for disk in disks() {
+        if let Err(e) = disk.sync() {
+            failed.push((String::from(disk.name()), e));
+        }
+    }
+    failed
+}
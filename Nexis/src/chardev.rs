@@ -0,0 +1,216 @@
+// Nexis/src/chardev.rs
+//
+// Character devices: byte streams with no size and no offset. Drivers
+// implement `CharDevice` and `register` it under a name; devfs (fs/devfs.rs)
+// shows every registered device as /dev/<name>, so user processes reach it
+// through the ordinary open/read/write syscalls.
+//
+// The devices every boot has live here: the console (VGA + keyboard, with
+// output mirrored to serial), the first serial port, and the memory
+// devices null, zero and random.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::fs::FsResult;
+use crate::kb::XorShift64;
+
+pub trait CharDevice: Send + Sync {
+    fn name(&self) -> &str;
+    /// Read what is available into `buf`, blocking until at least one byte
+    /// is (or end of file, for devices that have one).
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize>;
+    fn write(&self, buf: &[u8]) -> FsResult<usize>;
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn CharDevice>>> = Mutex::new(Vec::new());
+
+/// Make `dev` visible as /dev/<name>. A later device with the same name
+/// replaces the earlier one.
+pub fn register(dev: Arc<dyn CharDevice>) {
+    let mut devices = DEVICES.lock();
+    devices.retain(|d| d.name() != dev.name());
+    devices.push(dev);
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn CharDevice>> {
+    DEVICES.lock().iter().find(|d| d.name() == name).cloned()
+}
+
+pub fn devices() -> Vec<Arc<dyn CharDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// Longest line `console_read` returns, newline excluded.
+const LINE_MAX: usize = 255;
+
+/// Read one line from the keyboard, echoed, with its newline.
+pub fn console_read(buf: &mut [u8]) -> usize {
+    let room = match buf.len().checked_sub(1) {
+        Some(r) => r.min(LINE_MAX),
+        None => return 0,
+    };
+    let n = crate::kb::Kb::read_line(&mut buf[..room]);
+    buf[n] = b'\n';
+    n + 1
+}
+
+/// Write to the screen and the serial port.
+pub fn console_write(buf: &[u8]) -> usize {
+    crate::vga::vprint!("{}", String::from_utf8_lossy(buf));
+    buf.len()
+}
+
+/// console and tty0: keyboard in, screen out.
+struct Console {
+    name: &'static str,
+}
+
+impl CharDevice for Console {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        Ok(console_read(buf))
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        Ok(console_write(buf))
+    }
+}
+
+const COM1: u16 = 0x3F8;
+/// Line status register: bit 0 is set when a received byte is waiting.
+const COM1_LSR: u16 = COM1 + 5;
+
+/// ttyS0, COM1. There is no serial IRQ yet, so readers sleep a tick at a
+/// time between polls.
+struct Serial;
+
+impl Serial {
+    fn try_receive() -> Option<u8> {
+        unsafe {
+            if Port::<u8>::new(COM1_LSR).read() & 1 == 0 {
+                return None;
+            }
+            Some(Port::<u8>::new(COM1).read())
+        }
+    }
+}
+
+impl CharDevice for Serial {
+    fn name(&self) -> &str {
+        "ttyS0"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut n = 0;
+        while n == 0 {
+            while n < buf.len() {
+                match Self::try_receive() {
+                    Some(b) => {
+                        buf[n] = b;
+                        n += 1;
+                    }
+                    None => break,
+                }
+            }
+            if n == 0 {
+                crate::waitqueue::sleep_ticks(1);
+            }
+        }
+        Ok(n)
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        let _ = crate::vga::SERIAL1.lock().write_str(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+}
+
+/// Discards writes; reads are at end of file.
+struct Null;
+
+impl CharDevice for Null {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> FsResult<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// Endless zeros; discards writes.
+struct Zero;
+
+impl CharDevice for Zero {
+    fn name(&self) -> &str {
+        "zero"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// Pseudo-random bytes. Not cryptographically strong: an xorshift seeded
+/// from the time stamp counter. Writes stir the written bytes in.
+struct Random {
+    rng: Mutex<XorShift64>,
+}
+
+impl CharDevice for Random {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        let mut rng = self.rng.lock();
+        for chunk in buf.chunks_mut(8) {
+            let bytes = rng.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        let mut rng = self.rng.lock();
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let state = rng.next_u64() ^ u64::from_le_bytes(bytes);
+            // xorshift must never reach an all-zero state
+            *rng = XorShift64::new(state | 1);
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Register the built-in devices.
+pub fn init() {
+    let seed = unsafe { core::arch::x86_64::_rdtsc() } ^ crate::pit::ticks().rotate_left(32);
+    register(Arc::new(Console { name: "console" }));
+    register(Arc::new(Console { name: "tty0" }));
+    register(Arc::new(Serial));
+    register(Arc::new(Null));
+    register(Arc::new(Zero));
+    register(Arc::new(Random { rng: Mutex::new(XorShift64::new(seed | 1)) }));
+}
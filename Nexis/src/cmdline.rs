@@ -0,0 +1,99 @@
+// Nexis/src/cmdline.rs
+//
+// Kernel command line: space-separated `key=value` pairs. The bootloader we
+// use does not pass one, so it is read at boot from QEMU's fw_cfg device,
+// from the file `opt/nexis/cmdline`:
+//
+//     qemu-system-x86_64 ... -fw_cfg name=opt/nexis/cmdline,string="hz=250"
+//
+// Without that file (or outside QEMU) the default set when the image was
+// built applies: `NEXIS_CMDLINE="hz=250" cargo bootimage`.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+/// fw_cfg file holding the boot-time command line.
+const FW_CFG_FILE: &str = "opt/nexis/cmdline";
+const FW_CFG_SELECTOR: u16 = 0x510;
+const FW_CFG_DATA: u16 = 0x511;
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+/// Longest command line taken from fw_cfg.
+const MAX_LEN: usize = 4096;
+
+const BUILD_CMDLINE: &str = match option_env!("NEXIS_CMDLINE") {
+    Some(s) => s,
+    None => "",
+};
+
+static CMDLINE: Once<String> = Once::new();
+
+fn fw_cfg_select(key: u16) {
+    unsafe { Port::<u16>::new(FW_CFG_SELECTOR).write(key) }
+}
+
+fn fw_cfg_read(buf: &mut [u8]) {
+    let mut port = Port::<u8>::new(FW_CFG_DATA);
+    for b in buf.iter_mut() {
+        *b = unsafe { port.read() };
+    }
+}
+
+/// Contents of the fw_cfg file `name`, if we run under QEMU and it was
+/// given one.
+fn fw_cfg_file(name: &str) -> Option<Vec<u8>> {
+    let mut sig = [0u8; 4];
+    fw_cfg_select(FW_CFG_SIGNATURE);
+    fw_cfg_read(&mut sig);
+    if sig != *b"QEMU" {
+        return None;
+    }
+    // big-endian count, then (size, select, reserved, name[56]) per file
+    let mut count = [0u8; 4];
+    fw_cfg_select(FW_CFG_FILE_DIR);
+    fw_cfg_read(&mut count);
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut entry = [0u8; 64];
+        fw_cfg_read(&mut entry);
+        let file = &entry[8..];
+        let len = file.iter().position(|&b| b == 0).unwrap_or(file.len());
+        if &file[..len] == name.as_bytes() {
+            let size = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+            let mut data = vec![0u8; size.min(MAX_LEN)];
+            fw_cfg_select(u16::from_be_bytes([entry[4], entry[5]]));
+            fw_cfg_read(&mut data);
+            return Some(data);
+        }
+    }
+    None
+}
+
+/// Fetch the boot-time command line. Needs the heap; before this runs,
+/// the build-time default is used.
+pub fn init() {
+    CMDLINE.call_once(|| match fw_cfg_file(FW_CFG_FILE) {
+        Some(data) => String::from(String::from_utf8_lossy(&data).trim_end_matches('\0')),
+        None => String::from(BUILD_CMDLINE),
+    });
+}
+
+/// The whole command line.
+pub fn raw() -> &'static str {
+    CMDLINE.get().map_or(BUILD_CMDLINE, |s| s.as_str())
+}
+
+/// Value of `key=value`; a bare `key` yields `Some("")`.
+pub fn get(key: &str) -> Option<&'static str> {
+    raw().split_whitespace().find_map(|arg| match arg.split_once('=') {
+        Some((k, v)) if k == key => Some(v),
+        None if arg == key => Some(""),
+        _ => None,
+    })
+}
+
+pub fn get_u32(key: &str) -> Option<u32> {
+    get(key).and_then(|v| v.parse().ok())
+}
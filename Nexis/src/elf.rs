@@ -0,0 +1,116 @@
+// Nexis/src/elf.rs
+//
+// Minimal ELF64 reader: just enough to find the entry point and the
+// PT_LOAD segments of a statically linked x86_64 executable.
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    NotElf64,
+    NotExecutable,
+    WrongMachine,
+    BadProgramHeader,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub filesz: usize,
+    pub memsz: usize,
+}
+
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    pub entry: usize,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+}
+
+fn u16_at(d: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([d[off], d[off + 1]])
+}
+
+fn u32_at(d: &[u8], off: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&d[off..off + 4]);
+    u32::from_le_bytes(b)
+}
+
+fn u64_at(d: &[u8], off: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&d[off..off + 8]);
+    u64::from_le_bytes(b)
+}
+
+impl<'a> ElfFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if &data[0..4] != b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+        // ELFCLASS64, little endian
+        if data[4] != 2 || data[5] != 1 {
+            return Err(ElfError::NotElf64);
+        }
+        if u16_at(data, 16) != ET_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+        if u16_at(data, 18) != EM_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+
+        let elf = ElfFile {
+            data,
+            entry: u64_at(data, 24) as usize,
+            phoff: u64_at(data, 32) as usize,
+            phentsize: u16_at(data, 54) as usize,
+            phnum: u16_at(data, 56) as usize,
+        };
+        let table_end = elf
+            .phnum
+            .checked_mul(elf.phentsize)
+            .and_then(|n| n.checked_add(elf.phoff));
+        match table_end {
+            Some(end) if end <= data.len() && elf.phentsize >= PHDR_SIZE => Ok(elf),
+            _ => Err(ElfError::BadProgramHeader),
+        }
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.phnum).map(move |i| {
+            let off = self.phoff + i * self.phentsize;
+            let d = self.data;
+            ProgramHeader {
+                p_type: u32_at(d, off),
+                flags: u32_at(d, off + 4),
+                offset: u64_at(d, off + 8) as usize,
+                vaddr: u64_at(d, off + 16) as usize,
+                filesz: u64_at(d, off + 32) as usize,
+                memsz: u64_at(d, off + 40) as usize,
+            }
+        })
+    }
+
+    /// File bytes of a segment, or `None` if the header points outside the image.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Option<&'a [u8]> {
+        let end = ph.offset.checked_add(ph.filesz)?;
+        self.data.get(ph.offset..end)
+    }
+}
@@ -0,0 +1,9 @@
+// Nexis/src/errno.rs
+//
+// Error numbers are part of the user ABI and live in `nexis-abi`; this
+// module adds the kernel-side result type.
+
+pub use nexis_abi::encode;
+pub use nexis_abi::errno::*;
+
+pub type SysResult = Result<usize, Errno>;
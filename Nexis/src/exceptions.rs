@@ -0,0 +1,328 @@
+// Nexis/src/exceptions.rs
+//
+// CPU exception entry. Every vector 0..32 goes through an assembly stub that
+// saves the general purpose registers into a `TrapFrame`, so the crash
+// screen can show the full register state rather than just the iret frame.
+
+use core::arch::global_asm;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::registers::control::{Cr2, Cr3};
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::VirtAddr;
+
+use crate::vga::{SERIAL1, VGA_WRITER};
+
+/// Register state pushed by the exception stubs, lowest address first.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    // pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    #[inline]
+    pub fn from_user(&self) -> bool {
+        self.cs & 3 == 3
+    }
+}
+
+global_asm!(
+    r#"
+    .macro EXC_NOERR n
+    .global exc_stub_\n
+    exc_stub_\n:
+        push 0
+        push \n
+        jmp exc_common
+    .endm
+
+    .macro EXC_ERR n
+    .global exc_stub_\n
+    exc_stub_\n:
+        push \n
+        jmp exc_common
+    .endm
+
+    .section .text
+    EXC_NOERR 0
+    EXC_NOERR 1
+    EXC_NOERR 2
+    EXC_NOERR 3
+    EXC_NOERR 4
+    EXC_NOERR 5
+    EXC_NOERR 6
+    EXC_NOERR 7
+    EXC_ERR   8
+    EXC_NOERR 9
+    EXC_ERR   10
+    EXC_ERR   11
+    EXC_ERR   12
+    EXC_ERR   13
+    EXC_ERR   14
+    EXC_NOERR 15
+    EXC_NOERR 16
+    EXC_ERR   17
+    EXC_NOERR 18
+    EXC_NOERR 19
+    EXC_NOERR 20
+    EXC_ERR   21
+    EXC_NOERR 22
+    EXC_NOERR 23
+    EXC_NOERR 24
+    EXC_NOERR 25
+    EXC_NOERR 26
+    EXC_NOERR 27
+    EXC_NOERR 28
+    EXC_ERR   29
+    EXC_ERR   30
+    EXC_NOERR 31
+
+    exc_common:
+        push rax
+        push rbx
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push rbp
+        push r8
+        push r9
+        push r10
+        push r11
+        push r12
+        push r13
+        push r14
+        push r15
+        mov rdi, rsp
+        cld
+        call exception_dispatch
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rbp
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rbx
+        pop rax
+        add rsp, 16
+        iretq
+
+    .section .rodata
+    .balign 8
+    .global exc_stub_table
+    exc_stub_table:
+    .irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+        .quad exc_stub_\n
+    .endr
+    .section .text
+    "#
+);
+
+extern "C" {
+    static exc_stub_table: [u64; 32];
+}
+
+const NAMES: [&str; 32] = [
+    "Divide Error (#DE)",
+    "Debug (#DB)",
+    "Non-Maskable Interrupt",
+    "Breakpoint (#BP)",
+    "Overflow (#OF)",
+    "Bound Range Exceeded (#BR)",
+    "Invalid Opcode (#UD)",
+    "Device Not Available (#NM)",
+    "Double Fault (#DF)",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS (#TS)",
+    "Segment Not Present (#NP)",
+    "Stack-Segment Fault (#SS)",
+    "General Protection Fault (#GP)",
+    "Page Fault (#PF)",
+    "Reserved",
+    "x87 Floating-Point (#MF)",
+    "Alignment Check (#AC)",
+    "Machine Check (#MC)",
+    "SIMD Floating-Point (#XM)",
+    "Virtualization (#VE)",
+    "Control Protection (#CP)",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection (#HV)",
+    "VMM Communication (#VC)",
+    "Security Exception (#SX)",
+    "Reserved",
+];
+
+pub const VEC_BREAKPOINT: u64 = 3;
+pub const VEC_DOUBLE_FAULT: u64 = 8;
+pub const VEC_PAGE_FAULT: u64 = 14;
+
+/// Page fault error code bits: protection violation (page present) on a write.
+const PF_PRESENT_WRITE: u64 = 0b11;
+
+/// The signal a Unix kernel would send for a user fault on `vector`; it
+/// ends up in the exit status `waitpid` reports.
+fn fault_signal(vector: u64) -> i32 {
+    use nexis_abi::process::{SIGFPE, SIGILL, SIGKILL, SIGSEGV};
+    match vector {
+        0 | 16 | 19 => SIGFPE,
+        6 => SIGILL,
+        11 | 12 | 13 | 14 | 17 => SIGSEGV,
+        _ => SIGKILL,
+    }
+}
+
+/// Point IDT vectors 0..32 at the assembly stubs.
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    macro_rules! stub {
+        ($entry:expr, $vec:expr) => {
+            unsafe { $entry.set_handler_addr(VirtAddr::new(exc_stub_table[$vec])) }
+        };
+    }
+    stub!(idt.divide_error, 0);
+    stub!(idt.debug, 1);
+    stub!(idt.non_maskable_interrupt, 2);
+    stub!(idt.breakpoint, 3);
+    stub!(idt.overflow, 4);
+    stub!(idt.bound_range_exceeded, 5);
+    stub!(idt.invalid_opcode, 6);
+    stub!(idt.device_not_available, 7);
+    let df = stub!(idt.double_fault, 8);
+    unsafe { df.set_stack_index(crate::gdt::DOUBLE_FAULT_IST_INDEX) };
+    stub!(idt.invalid_tss, 10);
+    stub!(idt.segment_not_present, 11);
+    stub!(idt.stack_segment_fault, 12);
+    stub!(idt.general_protection_fault, 13);
+    stub!(idt.page_fault, 14);
+    stub!(idt.x87_floating_point, 16);
+    stub!(idt.alignment_check, 17);
+    stub!(idt.machine_check, 18);
+    stub!(idt.simd_floating_point, 19);
+    stub!(idt.virtualization, 20);
+    stub!(idt.cp_protection_exception, 21);
+    stub!(idt.hv_injection_exception, 28);
+    stub!(idt.vmm_communication_exception, 29);
+    stub!(idt.security_exception, 30);
+}
+
+#[no_mangle]
+extern "C" fn exception_dispatch(frame: &mut TrapFrame) {
+    if frame.vector == VEC_BREAKPOINT {
+        crate::vga::vprintln!("[exc] breakpoint at {:#x}", frame.rip);
+        return;
+    }
+
+    if frame.vector == VEC_PAGE_FAULT && frame.from_user() && frame.error_code & PF_PRESENT_WRITE == PF_PRESENT_WRITE {
+        // a write to a page shared with a forked relative
+        let vmm = crate::vmm::kernel_vmm();
+        if let Ok(true) = vmm.break_cow(crate::vmm::AddressSpace::current(), Cr2::read().as_u64() as usize) {
+            return;
+        }
+    }
+
+    if frame.from_user() && frame.vector != VEC_DOUBLE_FAULT {
+        kill_user(frame);
+        return;
+    }
+
+    crash_screen(frame);
+}
+
+/// A user process faulted: report it and take the process down, not the kernel.
+fn kill_user(frame: &TrapFrame) {
+    let name = NAMES[frame.vector as usize & 31];
+    match crate::process::current_pid() {
+        Some(pid) => {
+            crate::vga::vprintln!(
+                "[exc] pid {} killed: {} at rip={:#x} err={:#x}",
+                pid, name, frame.rip, frame.error_code
+            );
+            if frame.vector == VEC_PAGE_FAULT {
+                crate::vga::vprintln!("[exc]   fault address {:#x}", Cr2::read().as_u64());
+            }
+            crate::process::exit(pid, nexis_abi::process::killed_status(fault_signal(frame.vector)));
+        }
+        None => crash_screen(frame),
+    }
+}
+
+/// Print everything we know about a fatal kernel exception and halt.
+fn crash_screen(frame: &TrapFrame) -> ! {
+    interrupts::disable();
+    // The fault may have happened while a printer held its lock.
+    unsafe {
+        VGA_WRITER.force_unlock();
+        SERIAL1.force_unlock();
+    }
+    VGA_WRITER.lock().set_color(0x4f);
+    VGA_WRITER.lock().clear_screen();
+
+    let vector = frame.vector as usize;
+    crate::vga::vprintln!("*** KERNEL EXCEPTION {} : {} ***", vector, NAMES[vector & 31]);
+    crate::vga::vprintln!("error code {:#x}", frame.error_code);
+    if frame.vector == VEC_PAGE_FAULT {
+        let e = frame.error_code;
+        crate::vga::vprintln!(
+            "fault addr {:#018x} ({} {} {}{})",
+            Cr2::read().as_u64(),
+            if e & 1 != 0 { "protection" } else { "not-present" },
+            if e & 2 != 0 { "write" } else { "read" },
+            if e & 4 != 0 { "user" } else { "kernel" },
+            if e & 16 != 0 { " ifetch" } else { "" },
+        );
+    }
+    crate::vga::vprintln!(
+        "rip {:#018x} cs {:#06x} rflags {:#018x}",
+        frame.rip, frame.cs, frame.rflags
+    );
+    crate::vga::vprintln!("rsp {:#018x} ss {:#06x}", frame.rsp, frame.ss);
+    crate::vga::vprintln!("rax {:#018x} rbx {:#018x} rcx {:#018x}", frame.rax, frame.rbx, frame.rcx);
+    crate::vga::vprintln!("rdx {:#018x} rsi {:#018x} rdi {:#018x}", frame.rdx, frame.rsi, frame.rdi);
+    crate::vga::vprintln!("rbp {:#018x} r8  {:#018x} r9  {:#018x}", frame.rbp, frame.r8, frame.r9);
+    crate::vga::vprintln!("r10 {:#018x} r11 {:#018x} r12 {:#018x}", frame.r10, frame.r11, frame.r12);
+    crate::vga::vprintln!("r13 {:#018x} r14 {:#018x} r15 {:#018x}", frame.r13, frame.r14, frame.r15);
+    let (cr3, _) = Cr3::read();
+    crate::vga::vprintln!("cr3 {:#018x}", cr3.start_address().as_u64());
+    match crate::process::try_current() {
+        Some(p) => crate::vga::vprintln!("process: pid {} '{}'", p.pid, p.name_str()),
+        None => crate::vga::vprintln!("process: <kernel>"),
+    }
+    crate::vga::vprintln!("System halted.");
+
+    loop {
+        hlt();
+    }
+}
@@ -0,0 +1,308 @@
+// Nexis/src/fd.rs
+//
+// Per-process file descriptor tables. A descriptor points at an `OpenFile`,
+// which holds the file offset; `dup` shares the `OpenFile`, so duplicated
+// descriptors move through the file together, as on Unix.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::errno::{Errno, EBADF, EEXIST, EFBIG, EINVAL, EISDIR, EMFILE, ENOTDIR, ESPIPE, ESRCH};
+use crate::fs::vfs::{self, FileType, FsError, InodeRef, MAX_FILE_SIZE};
+use crate::pipe::{self, PipeEnd};
+use crate::process::Pid;
+use nexis_abi::fs::*;
+
+/// Descriptors per process.
+pub const MAX_FDS: usize = 64;
+
+pub enum FileKind {
+    /// Keyboard in, VGA/serial out.
+    Console,
+    Inode { inode: InodeRef, path: String },
+    Pipe(PipeEnd),
+}
+
+pub struct OpenFile {
+    pub kind: FileKind,
+    pub flags: u32,
+    offset: Mutex<u64>,
+}
+
+impl OpenFile {
+    pub fn console(flags: u32) -> Arc<Self> {
+        Arc::new(OpenFile { kind: FileKind::Console, flags, offset: Mutex::new(0) })
+    }
+
+    /// A new pipe, as (read end, write end).
+    pub fn pipe() -> (Arc<Self>, Arc<Self>) {
+        let (r, w) = pipe::new();
+        let open = |end, flags| Arc::new(OpenFile { kind: FileKind::Pipe(end), flags, offset: Mutex::new(0) });
+        (open(r, O_RDONLY), open(w, O_WRONLY))
+    }
+
+    fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if !self.readable() {
+            return Err(EBADF);
+        }
+        match &self.kind {
+            FileKind::Console => Ok(crate::chardev::console_read(buf)),
+            FileKind::Pipe(end) => Ok(end.read(buf)),
+            FileKind::Inode { inode, .. } => {
+                let mut off = self.offset.lock();
+                let n = inode.read_at(*off, buf).map_err(FsError::errno)?;
+                *off += n as u64;
+                Ok(n)
+            }
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if !self.writable() {
+            return Err(EBADF);
+        }
+        match &self.kind {
+            FileKind::Console => Ok(crate::chardev::console_write(buf)),
+            FileKind::Pipe(end) => end.write(buf),
+            FileKind::Inode { inode, .. } => {
+                let mut off = self.offset.lock();
+                if self.flags & O_APPEND != 0 {
+                    *off = inode.metadata().size;
+                }
+                // short write up to the size limit, EFBIG at it
+                let room = MAX_FILE_SIZE.saturating_sub(*off);
+                if room == 0 && !buf.is_empty() {
+                    return Err(EFBIG);
+                }
+                let buf = &buf[..buf.len().min(room as usize)];
+                let n = inode.write_at(*off, buf).map_err(FsError::errno)?;
+                *off += n as u64;
+                Ok(n)
+            }
+        }
+    }
+
+    pub fn seek(&self, offset: i64, whence: usize) -> Result<u64, Errno> {
+        let inode = match &self.kind {
+            FileKind::Console | FileKind::Pipe(_) => return Err(ESPIPE),
+            FileKind::Inode { inode, .. } => inode,
+        };
+        let mut off = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *off as i64,
+            SEEK_END => inode.metadata().size as i64,
+            _ => return Err(EINVAL),
+        };
+        let new = base
+            .checked_add(offset)
+            .filter(|&n| n >= 0 && n as u64 <= MAX_FILE_SIZE)
+            .ok_or(EINVAL)?;
+        *off = new as u64;
+        Ok(*off)
+    }
+
+    pub fn stat(&self) -> Stat {
+        match &self.kind {
+            FileKind::Console => Stat { ino: 0, size: 0, mode: S_IFCHR | 0o620, nlink: 1 },
+            FileKind::Inode { inode, .. } => stat_of(inode),
+            FileKind::Pipe(end) => Stat { ino: 0, size: end.buffered() as u64, mode: S_IFIFO | 0o600, nlink: 1 },
+        }
+    }
+
+    /// Write `getdents` records for the entries from the current position
+    /// on into `out`; the offset counts entries, not bytes.
+    pub fn getdents(&self, out: &mut [u8]) -> Result<usize, Errno> {
+        let inode = match &self.kind {
+            FileKind::Inode { inode, .. } => inode,
+            FileKind::Console | FileKind::Pipe(_) => return Err(ENOTDIR),
+        };
+        let entries = inode.readdir().map_err(FsError::errno)?;
+        let mut off = self.offset.lock();
+        let mut written = 0;
+        for (i, e) in entries.iter().enumerate().skip(*off as usize) {
+            let reclen = dirent_reclen(e.name.len());
+            if written + reclen > out.len() {
+                if written == 0 {
+                    return Err(EINVAL); // buffer too small for one record
+                }
+                break;
+            }
+            let rec = &mut out[written..written + reclen];
+            rec.fill(0);
+            rec[0..8].copy_from_slice(&e.ino.to_le_bytes());
+            rec[8..16].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+            rec[16..18].copy_from_slice(&(reclen as u16).to_le_bytes());
+            rec[18] = dirent_type(e.kind);
+            rec[DIRENT_HEADER..DIRENT_HEADER + e.name.len()].copy_from_slice(e.name.as_bytes());
+            written += reclen;
+            *off = i as u64 + 1;
+        }
+        Ok(written)
+    }
+}
+
+fn dirent_type(kind: FileType) -> u8 {
+    match kind {
+        FileType::File => DT_REG,
+        FileType::Dir => DT_DIR,
+        FileType::Symlink => DT_LNK,
+        FileType::CharDevice => DT_CHR,
+        FileType::BlockDevice => DT_BLK,
+    }
+}
+
+pub fn stat_of(inode: &InodeRef) -> Stat {
+    let m = inode.metadata();
+    let kind = match m.kind {
+        FileType::File => S_IFREG,
+        FileType::Dir => S_IFDIR,
+        FileType::Symlink => S_IFLNK,
+        FileType::CharDevice => S_IFCHR,
+        FileType::BlockDevice => S_IFBLK,
+    };
+    Stat { ino: m.ino, size: m.size, mode: kind | m.mode as u32, nlink: m.nlink }
+}
+
+/// Open `path` with `O_*` flags, creating it with `mode` if asked to.
+pub fn open(path: &str, flags: u32, mode: u32) -> Result<Arc<OpenFile>, Errno> {
+    let dentry = match vfs::resolve(path) {
+        Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(EEXIST),
+        Ok(d) => d,
+        Err(FsError::NotFound) if flags & O_CREAT != 0 => {
+            vfs::create(path, FileType::File, (mode & 0o7777) as u16).map_err(FsError::errno)?;
+            vfs::resolve(path).map_err(FsError::errno)?
+        }
+        Err(e) => return Err(e.errno()),
+    };
+    let inode = dentry.inode;
+    let kind = inode.metadata().kind;
+    if kind == FileType::Dir && flags & O_ACCMODE != O_RDONLY {
+        return Err(EISDIR);
+    }
+    if kind != FileType::Dir && flags & O_DIRECTORY != 0 {
+        return Err(ENOTDIR);
+    }
+    if flags & O_TRUNC != 0 && flags & O_ACCMODE != O_RDONLY {
+        inode.truncate(0).map_err(FsError::errno)?;
+    }
+    let kind = FileKind::Inode { inode, path: dentry.path };
+    Ok(Arc::new(OpenFile { kind, flags, offset: Mutex::new(0) }))
+}
+
+#[derive(Clone)]
+pub struct FdTable {
+    fds: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FdTable {
+    /// A table with 0, 1 and 2 on the console.
+    pub fn with_console() -> Self {
+        let mut t = FdTable { fds: Vec::new() };
+        t.fds.push(Some(OpenFile::console(O_RDONLY)));
+        t.fds.push(Some(OpenFile::console(O_WRONLY)));
+        t.fds.push(Some(OpenFile::console(O_WRONLY)));
+        t
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        self.fds.get(fd).cloned().flatten().ok_or(EBADF)
+    }
+
+    /// Install `file` in the lowest free descriptor.
+    pub fn insert(&mut self, file: Arc<OpenFile>) -> Result<usize, Errno> {
+        match self.fds.iter().position(|f| f.is_none()) {
+            Some(fd) => {
+                self.fds[fd] = Some(file);
+                Ok(fd)
+            }
+            None if self.fds.len() < MAX_FDS => {
+                self.fds.push(Some(file));
+                Ok(self.fds.len() - 1)
+            }
+            None => Err(EMFILE),
+        }
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<(), Errno> {
+        match self.fds.get_mut(fd) {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(EBADF),
+        }
+    }
+
+    /// Make `new` refer to the same open file as `old`, closing `new` first.
+    pub fn dup2(&mut self, old: usize, new: usize) -> Result<usize, Errno> {
+        let file = self.get(old)?;
+        if new >= MAX_FDS {
+            return Err(EBADF);
+        }
+        if new >= self.fds.len() {
+            self.fds.resize(new + 1, None);
+        }
+        self.fds[new] = Some(file);
+        Ok(new)
+    }
+
+    /// (fd, open file) for every open descriptor.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Arc<OpenFile>)> {
+        self.fds.iter().enumerate().filter_map(|(i, f)| f.as_ref().map(|f| (i, f)))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TABLES: Mutex<BTreeMap<Pid, FdTable>> = Mutex::new(BTreeMap::new());
+}
+
+/// Give a new process its table.
+pub fn init_process(pid: Pid) {
+    TABLES.lock().insert(pid, FdTable::with_console());
+}
+
+/// Give `child` a copy of `parent`'s table. The copies share open files,
+/// and with them file offsets.
+pub fn fork(parent: Pid, child: Pid) {
+    let mut tables = TABLES.lock();
+    if let Some(table) = tables.get(&parent).cloned() {
+        tables.insert(child, table);
+    }
+}
+
+/// Drop a process's table, closing everything it had open.
+pub fn release(pid: Pid) {
+    let table = TABLES.lock().remove(&pid);
+    drop(table); // inodes are released outside the lock
+}
+
+/// Number of descriptors `pid` has open.
+pub fn open_count(pid: Pid) -> usize {
+    TABLES.lock().get(&pid).map_or(0, |t| t.iter().count())
+}
+
+/// Run `f` on the calling process's table.
+pub fn with_current<R>(f: impl FnOnce(&mut FdTable) -> Result<R, Errno>) -> Result<R, Errno> {
+    let pid = crate::process::current_pid().ok_or(ESRCH)?;
+    let mut tables = TABLES.lock();
+    let table = tables.get_mut(&pid).ok_or(ESRCH)?;
+    f(table)
+}
+
+/// Look up a descriptor of the calling process. The lock is released before
+/// the caller does any I/O on the returned file.
+pub fn current_file(fd: usize) -> Result<Arc<OpenFile>, Errno> {
+    with_current(|t| t.get(fd))
+}
@@ -1,51 +1,98 @@
 // Nexis/src/fs.rs
 #![no_std]
+//
+// Filesystem entry points for the shell and syscalls, on top of the VFS in
+// fs/vfs.rs. The root is a tmpfs, so everything starts out in RAM; devices
+// appear under /dev and kernel state under /proc.
 
-use core::ptr;
+pub mod devfs;
+pub mod ext2;
+pub mod fat;
+pub mod procfs;
+pub mod tmpfs;
+pub mod vfs;
 
-const DEMO_FILES: [&str; 2] = ["readme.txt", "hello.txt"];
-const DEMO_CONTENTS: [&str; 2] = [
-    "This is a demo file system.\n",
-    "Hello from Nexis FS layer!\n",
-];
+use alloc::sync::Arc;
+
+pub use vfs::{DirEntry, FileType, FsError, FsResult};
 
-pub fn fs_init() {}
+/// Files every boot starts with.
+const DEMO_FILES: [(&str, &[u8]); 2] = [
+    ("/readme.txt", b"This is a demo file system.\n"),
+    ("/hello.txt", b"Hello from Nexis FS layer!\n"),
+];
 
-pub fn list_files_syscall(out_buf: *mut u8, out_buf_len: usize) -> usize {
-    if out_buf.is_null() || out_buf_len == 0 {
-        return 0;
+/// Mount a fresh tmpfs as `/`, populate it from the built-in files and the
+/// initrd, and mount devfs on `/dev` and procfs on `/proc`.
+pub fn fs_init() {
+    if let Err(e) = vfs::mount("/", Arc::new(tmpfs::TmpFs::new())) {
+        crate::vga::vprintln!("fs: cannot mount root: {}", e.as_str());
+        return;
     }
-    let mut written = 0;
-    for name in DEMO_FILES {
-        let line = format!("{}\n", name);
-        let bytes = line.as_bytes();
-        for &b in bytes {
-            if written >= out_buf_len {
-                return written;
-            }
-            unsafe { ptr::write(out_buf.add(written), b); }
-            written += 1;
+    for (name, data) in DEMO_FILES.iter() {
+        if let Err(e) = vfs::write_file(name, data, false) {
+            crate::vga::vprintln!("fs: {}: {}", name, e.as_str());
         }
     }
-    written
+    crate::initrd::load();
+    mount_pseudo("/dev", Arc::new(devfs::DevFs));
+    mount_pseudo("/proc", Arc::new(procfs::ProcFs));
 }
 
-pub fn read_file_syscall(filename_ptr: *const u8, filename_len: usize, out_buf: *mut u8) -> usize {
-    if filename_ptr.is_null() || out_buf.is_null() {
-        return 0;
+/// Mount a filesystem that needs no device, creating its mount point.
+fn mount_pseudo(path: &str, fs: Arc<dyn vfs::FileSystem>) {
+    match vfs::mkdir(path) {
+        Ok(()) | Err(FsError::Exists) => {}
+        Err(e) => crate::vga::vprintln!("fs: {}: {}", path, e.as_str()),
     }
-    let name = unsafe {
-        let slice = core::slice::from_raw_parts(filename_ptr, filename_len);
-        core::str::from_utf8(slice).unwrap_or("")
+    if let Err(e) = vfs::mount(path, fs) {
+        crate::vga::vprintln!("fs: cannot mount {}: {}", path, e.as_str());
+    }
+}
+
+/// Mount block device `dev` at `path`. With no `fstype`, every disk
+/// filesystem is tried in turn.
+pub fn mount_device(dev: &str, path: &str, fstype: Option<&str>) -> FsResult<&'static str> {
+    let disk = crate::block::get(dev).ok_or(FsError::NotFound)?;
+    let fs: Arc<dyn vfs::FileSystem> = match fstype {
+        Some("fat") | Some("vfat") => Arc::new(fat::FatFs::new(disk)?),
+        Some("ext2") => Arc::new(ext2::Ext2Fs::new(disk)?),
+        Some(_) => return Err(FsError::WrongFs),
+        None => match ext2::Ext2Fs::new(disk.clone()) {
+            Ok(fs) => Arc::new(fs),
+            Err(FsError::WrongFs) => Arc::new(fat::FatFs::new(disk)?),
+            Err(e) => return Err(e),
+        },
     };
-    for (i, fname) in DEMO_FILES.iter().enumerate() {
-        if *fname == name {
-            let data = DEMO_CONTENTS[i].as_bytes();
-            for (j, &b) in data.iter().enumerate() {
-                unsafe { ptr::write(out_buf.add(j), b); }
+    let name = fs.name();
+    vfs::mount(path, fs)?;
+    Ok(name)
+}
+
+/// `ls` for the shell: one entry per line, directories marked with `/` and
+/// symlinks with `@`.
+pub fn list_files(path: &str) {
+    match vfs::list_dir(path) {
+        Ok(entries) => {
+            for e in entries {
+                let suffix = match e.kind {
+                    FileType::Dir => "/",
+                    FileType::Symlink => "@",
+                    _ => "",
+                };
+                crate::vga::vprintln!("{}{}", e.name, suffix);
             }
-            return data.len();
         }
+        Err(e) => crate::vga::vprintln!("fs: {}: {}", path, e.as_str()),
+    }
+}
+
+pub fn print_file(path: &str) {
+    match vfs::read_file(path) {
+        Ok(data) => match core::str::from_utf8(&data) {
+            Ok(text) => crate::vga::vprint!("{}", text),
+            Err(_) => crate::vga::vprintln!("fs: {}: binary file ({} bytes)", path, data.len()),
+        },
+        Err(e) => crate::vga::vprintln!("fs: {}: {}", path, e.as_str()),
     }
-    0
-}
\ No newline at end of file
+}
@@ -0,0 +1,103 @@
+// Nexis/src/fs/devfs.rs
+//
+// /dev: one character-device node per device in the chardev registry.
+// The directory is read straight from the registry, so a driver that
+// registers later shows up without anything being created here. Device
+// nodes ignore file offsets; reads and writes go to the driver as-is.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+
+use super::vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, InodeRef, Metadata};
+use crate::chardev::{self, CharDevice};
+
+const ROOT_INO: u64 = 1;
+
+/// Device inode numbers follow registration order.
+fn ino_of(name: &str) -> u64 {
+    let index = chardev::devices().iter().position(|d| d.name() == name).unwrap_or(0);
+    ROOT_INO + 1 + index as u64
+}
+
+struct DevRoot;
+
+impl Inode for DevRoot {
+    fn metadata(&self) -> Metadata {
+        Metadata { ino: ROOT_INO, kind: FileType::Dir, size: 0, mode: 0o755, nlink: 2 }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<InodeRef> {
+        let dev = chardev::get(name).ok_or(FsError::NotFound)?;
+        Ok(Arc::new(DevNode { ino: ino_of(name), dev }))
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        Ok(chardev::devices()
+            .iter()
+            .enumerate()
+            .map(|(i, d)| DirEntry {
+                name: String::from(d.name()),
+                ino: ROOT_INO + 1 + i as u64,
+                kind: FileType::CharDevice,
+            })
+            .collect())
+    }
+
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> FsResult<InodeRef> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+}
+
+struct DevNode {
+    ino: u64,
+    dev: Arc<dyn CharDevice>,
+}
+
+impl Inode for DevNode {
+    fn metadata(&self) -> Metadata {
+        Metadata { ino: self.ino, kind: FileType::CharDevice, size: 0, mode: 0o666, nlink: 1 }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        self.dev.read(buf)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.dev.write(buf)
+    }
+
+    /// `O_TRUNC` on a device is not an error; there is nothing to cut.
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+pub struct DevFs;
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> InodeRef {
+        Arc::new(DevRoot)
+    }
+}
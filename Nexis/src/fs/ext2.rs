@@ -0,0 +1,366 @@
+// Nexis/src/fs/ext2.rs
+//
+// Read-only ext2 (also readable: ext3 without a journal to replay). Blocks
+// are read straight through the disk's buffer cache; file blocks are found
+// by walking the direct, single, double and triple indirect pointers of
+// the inode, and a zero pointer is a hole that reads as zeros.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+use super::vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, InodeRef, Metadata, PATH_MAX};
+use crate::block::Disk;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const MAGIC: u16 = 0xEF53;
+const ROOT_INO: u32 = 2;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Incompatible features we can read: only the directory file-type byte.
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+
+const S_IFMT: u16 = 0o170000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFREG: u16 = 0o100000;
+const S_IFLNK: u16 = 0o120000;
+const S_IFCHR: u16 = 0o020000;
+const S_IFBLK: u16 = 0o060000;
+
+const DIRECT_BLOCKS: usize = 12;
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+struct Volume {
+    disk: Arc<Disk>,
+    block_size: u64,
+    inodes_per_group: u32,
+    inode_size: u64,
+    inodes_count: u32,
+    /// Inode table block of each group.
+    inode_tables: Vec<u32>,
+    filetype: bool,
+}
+
+impl Volume {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        Ok(self.disk.read_at(offset, buf)?)
+    }
+
+    fn read_inode(self: &Arc<Self>, ino: u32) -> FsResult<Arc<Ext2Inode>> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(FsError::Io);
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as u64;
+        let table = *self.inode_tables.get(group).ok_or(FsError::Io)? as u64;
+        let mut raw = [0u8; 128];
+        self.read(table * self.block_size + index * self.inode_size, &mut raw)?;
+
+        let mode = le16(&raw, 0);
+        let mut size = le32(&raw, 4) as u64;
+        if mode & S_IFMT == S_IFREG {
+            size |= (le32(&raw, 108) as u64) << 32; // i_size_high
+        }
+        let mut blocks = [0u32; 15];
+        for (i, b) in blocks.iter_mut().enumerate() {
+            *b = le32(&raw, 40 + i * 4);
+        }
+        let mut inline = [0u8; 60];
+        inline.copy_from_slice(&raw[40..100]);
+        Ok(Arc::new(Ext2Inode {
+            vol: self.clone(),
+            ino,
+            mode,
+            size,
+            links: le16(&raw, 26),
+            sectors: le32(&raw, 28),
+            blocks,
+            inline,
+        }))
+    }
+
+    /// Entry `index` of the indirect block `block` (0 if it is a hole).
+    fn indirect(&self, block: u32, index: u64) -> FsResult<u32> {
+        if block == 0 {
+            return Ok(0);
+        }
+        let mut b = [0u8; 4];
+        self.read(block as u64 * self.block_size + index * 4, &mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+}
+
+pub struct Ext2Inode {
+    vol: Arc<Volume>,
+    ino: u32,
+    mode: u16,
+    size: u64,
+    links: u16,
+    /// i_blocks, in 512-byte units; 0 for a fast symlink.
+    sectors: u32,
+    blocks: [u32; 15],
+    /// i_block as raw bytes, for fast symlinks.
+    inline: [u8; 60],
+}
+
+impl Ext2Inode {
+    fn kind(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFDIR => FileType::Dir,
+            S_IFLNK => FileType::Symlink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            _ => FileType::File,
+        }
+    }
+
+    /// Physical block holding logical block `n`, or 0 for a hole.
+    fn map_block(&self, n: u64) -> FsResult<u32> {
+        let per = self.vol.block_size / 4;
+        let vol = &self.vol;
+        if n < DIRECT_BLOCKS as u64 {
+            return Ok(self.blocks[n as usize]);
+        }
+        let n = n - DIRECT_BLOCKS as u64;
+        if n < per {
+            return vol.indirect(self.blocks[12], n);
+        }
+        let n = n - per;
+        if n < per * per {
+            let l1 = vol.indirect(self.blocks[13], n / per)?;
+            return vol.indirect(l1, n % per);
+        }
+        let n = n - per * per;
+        if n < per * per * per {
+            let l1 = vol.indirect(self.blocks[14], n / (per * per))?;
+            let l2 = vol.indirect(l1, n / per % per)?;
+            return vol.indirect(l2, n % per);
+        }
+        Err(FsError::Io)
+    }
+
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let n = buf.len().min((self.size - offset) as usize);
+        let bs = self.vol.block_size;
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done as u64;
+            let in_block = pos % bs;
+            let chunk = ((bs - in_block) as usize).min(n - done);
+            let dst = &mut buf[done..done + chunk];
+            match self.map_block(pos / bs)? {
+                0 => dst.fill(0),
+                b => self.vol.read(b as u64 * bs + in_block, dst)?,
+            }
+            done += chunk;
+        }
+        Ok(n)
+    }
+
+    /// (inode, file type byte, name) for every entry, `.`/`..` included.
+    fn entries(&self) -> FsResult<Vec<(u32, u8, String)>> {
+        if self.kind() != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        // a block at a time: the size comes from disk and may be anything
+        let bs = self.vol.block_size;
+        let mut buf = vec![0u8; bs as usize];
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < self.size {
+            let n = self.read_data(pos, &mut buf)?;
+            let block = &buf[..n];
+            pos += bs;
+            let mut at = 0;
+            while at + 8 <= block.len() {
+                let ino = le32(block, at);
+                let rec_len = le16(block, at + 4) as usize;
+                let name_len = block[at + 6] as usize;
+                if rec_len < 8 || at + rec_len > block.len() || 8 + name_len > rec_len {
+                    return Err(FsError::Io); // corrupt directory block
+                }
+                if ino != 0 {
+                    let kind = if self.vol.filetype { block[at + 7] } else { 0 };
+                    let name = String::from_utf8_lossy(&block[at + 8..at + 8 + name_len]).into_owned();
+                    out.push((ino, kind, name));
+                }
+                at += rec_len;
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn dirent_kind(t: u8) -> Option<FileType> {
+    match t {
+        1 => Some(FileType::File),
+        2 => Some(FileType::Dir),
+        3 => Some(FileType::CharDevice),
+        4 => Some(FileType::BlockDevice),
+        7 => Some(FileType::Symlink),
+        5 | 6 => Some(FileType::File), // fifos and sockets have no type of their own here
+        _ => None,
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: self.ino as u64,
+            kind: self.kind(),
+            size: self.size,
+            mode: self.mode & 0o7777,
+            nlink: self.links as u32,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        match self.kind() {
+            FileType::File => self.read_data(offset, buf),
+            FileType::Dir => Err(FsError::IsDir),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<InodeRef> {
+        let (ino, _, _) = self.entries()?.into_iter().find(|(_, _, n)| n == name).ok_or(FsError::NotFound)?;
+        Ok(self.vol.read_inode(ino)?)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let mut out = Vec::new();
+        for (ino, t, name) in self.entries()? {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let kind = match dirent_kind(t) {
+                Some(k) => k,
+                None => self.vol.read_inode(ino)?.kind(),
+            };
+            out.push(DirEntry { name, ino: ino as u64, kind });
+        }
+        Ok(out)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> FsResult<InodeRef> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readlink(&self) -> FsResult<String> {
+        if self.kind() != FileType::Symlink {
+            return Err(FsError::InvalidPath);
+        }
+        // short targets live in i_block itself ("fast" symlinks)
+        if self.sectors == 0 && self.size <= self.inline.len() as u64 {
+            let target = &self.inline[..self.size as usize];
+            return Ok(String::from_utf8_lossy(target).into_owned());
+        }
+        if self.size > PATH_MAX as u64 {
+            return Err(FsError::Io); // corrupt inode
+        }
+        let mut target = vec![0u8; self.size as usize];
+        let n = self.read_data(0, &mut target)?;
+        Ok(String::from_utf8_lossy(&target[..n]).into_owned())
+    }
+}
+
+pub struct Ext2Fs {
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2Fs {
+    /// Mount the ext2 volume on `disk`, read-only.
+    pub fn new(disk: Arc<Disk>) -> FsResult<Self> {
+        let mut sb = [0u8; 1024];
+        disk.read_at(SUPERBLOCK_OFFSET, &mut sb)?;
+        if le16(&sb, 56) != MAGIC {
+            return Err(FsError::WrongFs);
+        }
+        let log = le32(&sb, 24);
+        if log > 6 {
+            return Err(FsError::WrongFs);
+        }
+        let block_size = 1024u64 << log;
+        let blocks_count = le32(&sb, 4);
+        let first_data_block = le32(&sb, 20);
+        let blocks_per_group = le32(&sb, 32);
+        let inodes_per_group = le32(&sb, 40);
+        let rev = le32(&sb, 76);
+        let inode_size = if rev == 0 { 128 } else { le16(&sb, 88) as u64 };
+        let incompat = if rev == 0 { 0 } else { le32(&sb, 96) };
+        if blocks_per_group == 0 || inodes_per_group == 0 || inode_size < 128 {
+            return Err(FsError::WrongFs);
+        }
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            // extents, 64-bit, journal recovery pending, ...: ext4 territory
+            return Err(FsError::NotSupported);
+        }
+
+        let data_blocks = blocks_count.checked_sub(first_data_block).filter(|&n| n > 0).ok_or(FsError::WrongFs)?;
+        let groups = data_blocks.div_ceil(blocks_per_group) as u64;
+        // the descriptor table must fit on the disk before we allocate it
+        if (first_data_block as u64 + 1) * block_size + groups * 32 > disk.size() {
+            return Err(FsError::WrongFs);
+        }
+        let mut gdt = vec![0u8; groups as usize * 32];
+        disk.read_at((first_data_block as u64 + 1) * block_size, &mut gdt)?;
+        let inode_tables = gdt.chunks_exact(32).map(|d| le32(d, 8)).collect();
+
+        let vol = Arc::new(Volume {
+            disk,
+            block_size,
+            inodes_per_group,
+            inode_size,
+            inodes_count: le32(&sb, 0),
+            inode_tables,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+        });
+        let root = vol.read_inode(ROOT_INO)?;
+        if root.kind() != FileType::Dir {
+            return Err(FsError::Io);
+        }
+        Ok(Ext2Fs { root })
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+}
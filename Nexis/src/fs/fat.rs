@@ -0,0 +1,1064 @@
+// Nexis/src/fs/fat.rs
+//
+// FAT12/16/32 on a block device, read-write, with long file names.
+//
+// FAT has no inodes: a file is its directory entry, which holds the first
+// cluster and the size. An inode here remembers where its short entry
+// lives on disk and rewrites it when the file changes; the entry's disk
+// offset doubles as the inode number. Live inodes are kept in a per-volume
+// table so every lookup of the same entry shares one `FatInode`.
+//
+// All operations on a volume run under its `state` lock. Clusters of an
+// entry removed while still open are freed when its last reference goes
+// away; `Drop` only queues them, and the next operation frees them.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use spin::Mutex;
+
+use super::vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, InodeRef, Metadata};
+use crate::block::Disk;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LFN: u8 = 0x0F;
+
+/// NT case flags in byte 12 of a short entry.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+const ENTRY_SIZE: usize = 32;
+const ENTRY_FREE: u8 = 0xE5;
+const ENTRY_END: u8 = 0x00;
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// 1980-01-01, the FAT epoch; there is no clock to stamp files with.
+const DEFAULT_DATE: u16 = 0x0021;
+
+/// (disk offset, length) of one piece of a directory.
+type Extent = (u64, usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatKind {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatKind {
+    fn end_of_chain(self) -> u32 {
+        match self {
+            FatKind::Fat12 => 0xFFF,
+            FatKind::Fat16 => 0xFFFF,
+            FatKind::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    fn is_eoc(self, v: u32) -> bool {
+        v >= self.end_of_chain() - 7
+    }
+}
+
+/// Where everything is, from the boot sector. Offsets are in bytes from the
+/// start of the device.
+#[derive(Clone, Copy, Debug)]
+struct Geometry {
+    kind: FatKind,
+    cluster_size: u32,
+    fat_offset: u64,
+    fat_size: u64,
+    num_fats: u32,
+    /// FAT12/16 fixed root directory.
+    root_offset: u64,
+    root_entries: u32,
+    /// FAT32 root directory cluster.
+    root_cluster: u32,
+    data_offset: u64,
+    clusters: u32,
+    fsinfo_offset: Option<u64>,
+}
+
+fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+impl Geometry {
+    fn parse(bs: &[u8]) -> FsResult<Self> {
+        if bs[510] != 0x55 || bs[511] != 0xAA {
+            return Err(FsError::WrongFs);
+        }
+        let bps = le16(bs, 11) as u64;
+        let spc = bs[13] as u64;
+        let reserved = le16(bs, 14) as u64;
+        let num_fats = bs[16] as u64;
+        let root_entries = le16(bs, 17) as u64;
+        let total = match le16(bs, 19) {
+            0 => le32(bs, 32) as u64,
+            n => n as u64,
+        };
+        let fat_sectors = match le16(bs, 22) {
+            0 => le32(bs, 36) as u64,
+            n => n as u64,
+        };
+        if !bps.is_power_of_two() || !(512..=4096).contains(&bps) || !spc.is_power_of_two()
+            || reserved == 0 || num_fats == 0 || fat_sectors == 0
+        {
+            return Err(FsError::WrongFs);
+        }
+        let root_sectors = (root_entries * ENTRY_SIZE as u64).div_ceil(bps);
+        let data_start = reserved + num_fats * fat_sectors + root_sectors;
+        if total <= data_start {
+            return Err(FsError::WrongFs);
+        }
+        // at least one data cluster, and no more than FAT32 can number
+        let clusters = (total - data_start) / spc;
+        if clusters == 0 || clusters > 0x0FFF_FFF5 {
+            return Err(FsError::WrongFs);
+        }
+        let clusters = clusters as u32;
+        // the cluster count alone decides the FAT type
+        let kind = if clusters < 4085 {
+            FatKind::Fat12
+        } else if clusters < 65525 {
+            FatKind::Fat16
+        } else {
+            FatKind::Fat32
+        };
+        let (root_cluster, fsinfo_offset) = match kind {
+            FatKind::Fat32 => {
+                let fsinfo = le16(bs, 48) as u64;
+                let off = if fsinfo != 0 && fsinfo < reserved { Some(fsinfo * bps) } else { None };
+                (le32(bs, 44), off)
+            }
+            _ => (0, None),
+        };
+        Ok(Geometry {
+            kind,
+            cluster_size: (bps * spc) as u32,
+            fat_offset: reserved * bps,
+            fat_size: fat_sectors * bps,
+            num_fats: num_fats as u32,
+            root_offset: (reserved + num_fats * fat_sectors) * bps,
+            root_entries: root_entries as u32,
+            root_cluster,
+            data_offset: data_start * bps,
+            clusters,
+            fsinfo_offset,
+        })
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster as u64 - 2) * self.cluster_size as u64
+    }
+
+    fn valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.clusters + 2
+    }
+}
+
+/// A parsed directory entry: the short entry plus any long name before it.
+#[derive(Clone, Debug)]
+struct Slot {
+    name: String,
+    short: [u8; 11],
+    attr: u8,
+    cluster: u32,
+    size: u32,
+    /// Index of the short entry in the directory.
+    index: usize,
+    /// Index of the first long-name entry (== `index` without one).
+    first: usize,
+}
+
+impl Slot {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+/// Checksum of a short name, stored in each of its long-name entries.
+fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// "README  TXT" -> "README.TXT", honouring the NT lower-case flags.
+fn short_display(short: &[u8; 11], case: u8) -> String {
+    let mut base: Vec<u8> = short[..8].to_vec();
+    if base[0] == 0x05 {
+        base[0] = 0xE5; // 0xE5 as a first character is stored as 0x05
+    }
+    let part = |bytes: &[u8], lower: bool| {
+        let mut s = String::new();
+        for &b in bytes.iter().take_while(|&&b| b != b' ') {
+            let c = b as char;
+            s.push(if lower { c.to_ascii_lowercase() } else { c });
+        }
+        s
+    };
+    let mut name = part(&base, case & CASE_LOWER_BASE != 0);
+    let ext = part(&short[8..], case & CASE_LOWER_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// Walk raw directory bytes and collect the live entries (not `.`/`..`,
+/// not volume labels).
+fn parse_dir(raw: &[u8]) -> Vec<Slot> {
+    let mut slots = Vec::new();
+    let mut lfn: Vec<(u8, [u16; LFN_CHARS])> = Vec::new();
+    let mut lfn_first = 0;
+    let mut lfn_sum = 0;
+    for (i, e) in raw.chunks_exact(ENTRY_SIZE).enumerate() {
+        match e[0] {
+            ENTRY_END => break,
+            ENTRY_FREE => {
+                lfn.clear();
+                continue;
+            }
+            _ => {}
+        }
+        let attr = e[11];
+        if attr & 0x3F == ATTR_LFN {
+            if e[0] & LFN_LAST != 0 {
+                lfn.clear();
+                lfn_first = i;
+                lfn_sum = e[13];
+            }
+            let mut chars = [0u16; LFN_CHARS];
+            let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+            for (c, &o) in chars.iter_mut().zip(offsets.iter()) {
+                *c = le16(e, o);
+            }
+            lfn.push((e[0] & 0x1F, chars));
+            continue;
+        }
+        let mut short = [0u8; 11];
+        short.copy_from_slice(&e[..11]);
+        if attr & ATTR_VOLUME_ID != 0 || short[0] == b'.' {
+            lfn.clear();
+            continue;
+        }
+        // a long name only counts if it is complete and matches this entry
+        let complete = !lfn.is_empty()
+            && lfn_sum == lfn_checksum(&short)
+            && lfn.iter().rev().enumerate().all(|(n, &(seq, _))| seq as usize == n + 1);
+        let (name, first) = if complete {
+            let units: Vec<u16> = lfn
+                .iter()
+                .rev()
+                .flat_map(|(_, c)| c.iter().copied())
+                .take_while(|&c| c != 0)
+                .collect();
+            (String::from_utf16_lossy(&units), lfn_first)
+        } else {
+            (short_display(&short, e[12]), i)
+        };
+        lfn.clear();
+        slots.push(Slot {
+            name,
+            short,
+            attr,
+            cluster: (le16(e, 20) as u32) << 16 | le16(e, 26) as u32,
+            size: le32(e, 28),
+            index: i,
+            first,
+        });
+    }
+    slots
+}
+
+fn valid_short_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+/// Short entry for `name`: the 8.3 form, case flags, and whether a long
+/// name is needed too. `taken` reports short names already in use.
+fn make_short_name(name: &str, taken: &dyn Fn(&[u8; 11]) -> bool) -> ([u8; 11], u8, bool) {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+    let fits = |part: &str, max: usize| {
+        !part.is_empty() && part.len() <= max && part.bytes().all(valid_short_char)
+    };
+    let one_case = |part: &str| {
+        !part.bytes().any(|b| b.is_ascii_lowercase()) || !part.bytes().any(|b| b.is_ascii_uppercase())
+    };
+    let mut short = [b' '; 11];
+    if fits(base, 8) && (ext.is_empty() || fits(ext, 3)) {
+        for (d, b) in short.iter_mut().zip(base.bytes()) {
+            *d = b.to_ascii_uppercase();
+        }
+        for (d, b) in short[8..].iter_mut().zip(ext.bytes()) {
+            *d = b.to_ascii_uppercase();
+        }
+        if one_case(base) && one_case(ext) && !taken(&short) {
+            let mut case = 0;
+            if base.bytes().any(|b| b.is_ascii_lowercase()) {
+                case |= CASE_LOWER_BASE;
+            }
+            if ext.bytes().any(|b| b.is_ascii_lowercase()) {
+                case |= CASE_LOWER_EXT;
+            }
+            return (short, case, false);
+        }
+    }
+
+    // lossy basis name plus a numeric tail ("LONGFI~1.TXT")
+    let clean = |part: &str, max: usize| -> Vec<u8> {
+        part.bytes()
+            .filter(|&b| b != b' ' && b != b'.')
+            .map(|b| if valid_short_char(b) { b.to_ascii_uppercase() } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let basis = clean(base, 8);
+    let basis = if basis.is_empty() { vec![b'_'] } else { basis };
+    let ext = clean(ext, 3);
+    for n in 1u32.. {
+        let tail = alloc::format!("~{}", n);
+        let keep = basis.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&basis[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken(&short) {
+            return (short, 0, true);
+        }
+    }
+    unreachable!()
+}
+
+/// Long-name entries for `name`, in on-disk order (last part first).
+fn make_lfn_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    if !units.len().is_multiple_of(LFN_CHARS) {
+        units.push(0);
+    }
+    units.resize(count * LFN_CHARS, 0xFFFF);
+    let sum = lfn_checksum(short);
+    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    (0..count)
+        .rev()
+        .map(|n| {
+            let mut e = [0u8; ENTRY_SIZE];
+            e[0] = (n + 1) as u8 | if n + 1 == count { LFN_LAST } else { 0 };
+            e[11] = ATTR_LFN;
+            e[13] = sum;
+            for (k, &o) in offsets.iter().enumerate() {
+                e[o..o + 2].copy_from_slice(&units[n * LFN_CHARS + k].to_le_bytes());
+            }
+            e
+        })
+        .collect()
+}
+
+fn make_short_entry(short: &[u8; 11], attr: u8, case: u8, cluster: u32, size: u32) -> [u8; ENTRY_SIZE] {
+    let mut e = [0u8; ENTRY_SIZE];
+    e[..11].copy_from_slice(short);
+    e[11] = attr;
+    e[12] = case;
+    e[16..18].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    e[18..20].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    e[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    e[24..26].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    e[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    e[28..32].copy_from_slice(&size.to_le_bytes());
+    e
+}
+
+struct State {
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    /// Free clusters, if known (FAT32 FSInfo).
+    free_count: Option<u32>,
+    inodes: BTreeMap<u64, Weak<FatInode>>,
+}
+
+struct Volume {
+    disk: Arc<Disk>,
+    geo: Geometry,
+    state: Mutex<State>,
+    /// First clusters of removed entries whose inodes were still open.
+    orphans: Mutex<Vec<u32>>,
+}
+
+/// Inode number of the root directory; everything else uses the disk
+/// offset of its short entry, which is never below the FAT.
+const ROOT_INO: u64 = 1;
+
+impl Volume {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        Ok(self.disk.read_at(offset, buf)?)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> FsResult<()> {
+        Ok(self.disk.write_at(offset, buf)?)
+    }
+
+    fn fat_get(&self, cluster: u32) -> FsResult<u32> {
+        let g = &self.geo;
+        Ok(match g.kind {
+            FatKind::Fat12 => {
+                let mut b = [0u8; 2];
+                self.read(g.fat_offset + (cluster + cluster / 2) as u64, &mut b)?;
+                let v = u16::from_le_bytes(b) as u32;
+                if cluster & 1 == 0 { v & 0xFFF } else { v >> 4 }
+            }
+            FatKind::Fat16 => {
+                let mut b = [0u8; 2];
+                self.read(g.fat_offset + cluster as u64 * 2, &mut b)?;
+                u16::from_le_bytes(b) as u32
+            }
+            FatKind::Fat32 => {
+                let mut b = [0u8; 4];
+                self.read(g.fat_offset + cluster as u64 * 4, &mut b)?;
+                u32::from_le_bytes(b) & 0x0FFF_FFFF
+            }
+        })
+    }
+
+    /// Set a FAT entry in every copy of the FAT.
+    fn fat_set(&self, cluster: u32, value: u32) -> FsResult<()> {
+        let g = &self.geo;
+        for copy in 0..g.num_fats as u64 {
+            let base = g.fat_offset + copy * g.fat_size;
+            match g.kind {
+                FatKind::Fat12 => {
+                    let at = base + (cluster + cluster / 2) as u64;
+                    let mut b = [0u8; 2];
+                    self.read(at, &mut b)?;
+                    let old = u16::from_le_bytes(b);
+                    let v = value as u16 & 0xFFF;
+                    let new = if cluster & 1 == 0 { (old & 0xF000) | v } else { (old & 0x000F) | v << 4 };
+                    self.write(at, &new.to_le_bytes())?;
+                }
+                FatKind::Fat16 => self.write(base + cluster as u64 * 2, &(value as u16).to_le_bytes())?,
+                FatKind::Fat32 => {
+                    let at = base + cluster as u64 * 4;
+                    let mut b = [0u8; 4];
+                    self.read(at, &mut b)?;
+                    let v = (u32::from_le_bytes(b) & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                    self.write(at, &v.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every cluster of the chain starting at `first`.
+    fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut c = first;
+        while self.geo.valid_cluster(c) {
+            if chain.len() > self.geo.clusters as usize {
+                return Err(FsError::Io); // loop in the FAT
+            }
+            chain.push(c);
+            c = self.fat_get(c)?;
+        }
+        if c != 0 && !self.geo.kind.is_eoc(c) && !chain.is_empty() {
+            return Err(FsError::Io); // bad or free cluster inside a chain
+        }
+        Ok(chain)
+    }
+
+    /// Allocate a zeroed cluster, linked after `prev` if given.
+    fn alloc_cluster(&self, st: &mut State, prev: Option<u32>) -> FsResult<u32> {
+        let total = self.geo.clusters;
+        let start = st.next_free.clamp(2, total + 1);
+        let mut found = None;
+        for i in 0..total {
+            let c = 2 + (start - 2 + i) % total;
+            if self.fat_get(c)? == 0 {
+                found = Some(c);
+                break;
+            }
+        }
+        let c = found.ok_or(FsError::NoSpace)?;
+        self.fat_set(c, self.geo.kind.end_of_chain())?;
+        if let Some(p) = prev {
+            self.fat_set(p, c)?;
+        }
+        let zero = vec![0u8; self.geo.cluster_size as usize];
+        self.write(self.geo.cluster_offset(c), &zero)?;
+        st.next_free = c + 1;
+        if let Some(n) = st.free_count.as_mut() {
+            *n = n.saturating_sub(1);
+        }
+        Ok(c)
+    }
+
+    /// Free `first` and everything after it.
+    fn free_chain(&self, st: &mut State, first: u32) -> FsResult<()> {
+        for c in self.chain(first)? {
+            self.fat_set(c, 0)?;
+            if let Some(n) = st.free_count.as_mut() {
+                *n += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Free the clusters of files that were removed while open.
+    fn reap(&self, st: &mut State) -> FsResult<()> {
+        let orphans = core::mem::take(&mut *self.orphans.lock());
+        for first in orphans {
+            self.free_chain(st, first)?;
+        }
+        Ok(())
+    }
+
+    /// The pieces of a directory, in order.
+    fn dir_extents(&self, first: u32) -> FsResult<Vec<Extent>> {
+        if first == 0 {
+            let len = self.geo.root_entries as usize * ENTRY_SIZE;
+            return Ok(vec![(self.geo.root_offset, len)]);
+        }
+        let size = self.geo.cluster_size as usize;
+        Ok(self.chain(first)?.into_iter().map(|c| (self.geo.cluster_offset(c), size)).collect())
+    }
+
+    fn read_dir_raw(&self, first: u32) -> FsResult<(Vec<u8>, Vec<Extent>)> {
+        let extents = self.dir_extents(first)?;
+        let mut raw = vec![0u8; extents.iter().map(|e| e.1).sum()];
+        let mut at = 0;
+        for &(off, len) in &extents {
+            self.read(off, &mut raw[at..at + len])?;
+            at += len;
+        }
+        Ok((raw, extents))
+    }
+
+    fn entry_offset(extents: &[Extent], index: usize) -> u64 {
+        let mut pos = index * ENTRY_SIZE;
+        for &(off, len) in extents {
+            if pos < len {
+                return off + pos as u64;
+            }
+            pos -= len;
+        }
+        unreachable!("directory entry index past the end")
+    }
+
+    fn read_dir(&self, first: u32) -> FsResult<Vec<Slot>> {
+        Ok(parse_dir(&self.read_dir_raw(first)?.0))
+    }
+
+    fn find(&self, first: u32, name: &str) -> FsResult<(Slot, Vec<Extent>)> {
+        let (raw, extents) = self.read_dir_raw(first)?;
+        let slot = parse_dir(&raw)
+            .into_iter()
+            .find(|s| s.name.eq_ignore_ascii_case(name) || short_display(&s.short, 0).eq_ignore_ascii_case(name))
+            .ok_or(FsError::NotFound)?;
+        Ok((slot, extents))
+    }
+
+    /// Write a new entry (long name plus short entry) into directory
+    /// `first`, growing it if needed. Returns the short entry's offset.
+    fn add_entry(&self, st: &mut State, first: u32, name: &str, attr: u8, cluster: u32, size: u32) -> FsResult<u64> {
+        let (raw, _) = self.read_dir_raw(first)?;
+        let existing = parse_dir(&raw);
+        let taken = |s: &[u8; 11]| existing.iter().any(|e| &e.short == s);
+        let (short, case, needs_lfn) = make_short_name(name, &taken);
+        let mut entries = if needs_lfn { make_lfn_entries(name, &short) } else { Vec::new() };
+        entries.push(make_short_entry(&short, attr, case, cluster, size));
+
+        loop {
+            let (raw, extents) = self.read_dir_raw(first)?;
+            let mut run = 0;
+            let mut start = None;
+            for (i, e) in raw.chunks_exact(ENTRY_SIZE).enumerate() {
+                if e[0] == ENTRY_FREE || e[0] == ENTRY_END {
+                    run += 1;
+                    if run == entries.len() {
+                        start = Some(i + 1 - run);
+                        break;
+                    }
+                } else {
+                    run = 0;
+                }
+            }
+            if let Some(start) = start {
+                for (k, e) in entries.iter().enumerate() {
+                    self.write(Self::entry_offset(&extents, start + k), e)?;
+                }
+                return Ok(Self::entry_offset(&extents, start + entries.len() - 1));
+            }
+            if first == 0 {
+                return Err(FsError::NoSpace); // the FAT12/16 root cannot grow
+            }
+            let last = *self.chain(first)?.last().ok_or(FsError::Io)?;
+            self.alloc_cluster(st, Some(last))?;
+        }
+    }
+
+    /// Mark a slot and its long name as deleted.
+    fn remove_entry(&self, slot: &Slot, extents: &[Extent]) -> FsResult<()> {
+        for i in slot.first..=slot.index {
+            self.write(Self::entry_offset(extents, i), &[ENTRY_FREE])?;
+        }
+        Ok(())
+    }
+
+    /// Update the first cluster and size stored in the entry at `offset`.
+    fn update_entry(&self, offset: u64, cluster: u32, size: u32) -> FsResult<()> {
+        let mut e = [0u8; ENTRY_SIZE];
+        self.read(offset, &mut e)?;
+        e[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        e[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        e[28..32].copy_from_slice(&size.to_le_bytes());
+        self.write(offset, &e)
+    }
+
+    /// Cluster number a directory's `..` entry uses for `dir`: 0 for root.
+    fn dotdot_cluster(&self, dir: u32) -> u32 {
+        if dir == self.geo.root_cluster { 0 } else { dir }
+    }
+}
+
+struct Loc {
+    /// Disk offset of the short entry (0 for the root).
+    entry: u64,
+    first: u32,
+    size: u32,
+    /// Cached cluster chain for files.
+    chain: Option<Vec<u32>>,
+    unlinked: bool,
+}
+
+pub struct FatInode {
+    vol: Arc<Volume>,
+    kind: FileType,
+    read_only: bool,
+    loc: Mutex<Loc>,
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let loc = self.loc.get_mut();
+        if loc.unlinked && loc.first != 0 {
+            self.vol.orphans.lock().push(loc.first);
+        }
+    }
+}
+
+impl FatInode {
+    fn ino(loc: &Loc) -> u64 {
+        if loc.entry == 0 { ROOT_INO } else { loc.entry }
+    }
+
+    /// Shared inode for `slot`, whose short entry is at `offset`.
+    fn get(vol: &Arc<Volume>, st: &mut State, slot: &Slot, offset: u64) -> Arc<FatInode> {
+        if let Some(i) = st.inodes.get(&offset).and_then(Weak::upgrade) {
+            return i;
+        }
+        let inode = Arc::new(FatInode {
+            vol: vol.clone(),
+            kind: if slot.is_dir() { FileType::Dir } else { FileType::File },
+            read_only: slot.attr & ATTR_READ_ONLY != 0,
+            loc: Mutex::new(Loc { entry: offset, first: slot.cluster, size: slot.size, chain: None, unlinked: false }),
+        });
+        st.inodes.insert(offset, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn dir_cluster(&self) -> FsResult<u32> {
+        if self.kind != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        Ok(self.loc.lock().first)
+    }
+
+    fn chain(&self, loc: &mut Loc) -> FsResult<Vec<u32>> {
+        if loc.chain.is_none() {
+            loc.chain = Some(self.vol.chain(loc.first)?);
+        }
+        Ok(loc.chain.clone().unwrap_or_default())
+    }
+
+    /// Grow or shrink the cluster chain to hold `size` bytes.
+    fn resize_chain(&self, st: &mut State, loc: &mut Loc, size: u64) -> FsResult<()> {
+        let csize = self.vol.geo.cluster_size as u64;
+        let want = size.div_ceil(csize) as usize;
+        let mut chain = self.chain(loc)?;
+        if want < chain.len() {
+            if want == 0 {
+                self.vol.free_chain(st, chain[0])?;
+                loc.first = 0;
+            } else {
+                self.vol.free_chain(st, chain[want])?;
+                self.vol.fat_set(chain[want - 1], self.vol.geo.kind.end_of_chain())?;
+            }
+            chain.truncate(want);
+        }
+        while chain.len() < want {
+            let prev = chain.last().copied();
+            let c = match self.vol.alloc_cluster(st, prev) {
+                Ok(c) => c,
+                Err(e) => {
+                    loc.chain = Some(chain);
+                    return Err(e);
+                }
+            };
+            if prev.is_none() {
+                loc.first = c;
+            }
+            chain.push(c);
+        }
+        loc.chain = Some(chain);
+        Ok(())
+    }
+
+    /// Clear what lies between the old end of file `old` and `new` in the
+    /// old last cluster; clusters past it were zeroed when allocated. Without
+    /// this, growing the file would expose stale disk contents.
+    fn zero_tail(&self, loc: &mut Loc, old: u64, new: u64) -> FsResult<()> {
+        let csize = self.vol.geo.cluster_size as u64;
+        if old.is_multiple_of(csize) {
+            return Ok(());
+        }
+        let chain = self.chain(loc)?;
+        let cluster = chain[(old / csize) as usize];
+        let len = (csize - old % csize).min(new - old) as usize;
+        self.vol.write(self.vol.geo.cluster_offset(cluster) + old % csize, &vec![0u8; len])
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        if self.read_only || self.vol.disk.read_only() {
+            return Err(FsError::ReadOnly);
+        }
+        Ok(())
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let loc = self.loc.lock();
+        let mode = match (self.kind, self.read_only) {
+            (FileType::Dir, _) => 0o755,
+            (_, true) => 0o444,
+            _ => 0o644,
+        };
+        Metadata {
+            ino: Self::ino(&loc),
+            kind: self.kind,
+            size: if self.kind == FileType::Dir { 0 } else { loc.size as u64 },
+            mode,
+            nlink: 1,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        if self.kind == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        let _st = self.vol.state.lock();
+        let mut loc = self.loc.lock();
+        let size = loc.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let n = buf.len().min((size - offset) as usize);
+        let chain = self.chain(&mut loc)?;
+        let csize = self.vol.geo.cluster_size as u64;
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done as u64;
+            let cluster = *chain.get((pos / csize) as usize).ok_or(FsError::Io)?;
+            let in_cluster = pos % csize;
+            let chunk = ((csize - in_cluster) as usize).min(n - done);
+            self.vol.read(self.vol.geo.cluster_offset(cluster) + in_cluster, &mut buf[done..done + chunk])?;
+            done += chunk;
+        }
+        Ok(n)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        if self.kind == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        self.check_writable()?;
+        let end = offset.checked_add(buf.len() as u64).filter(|&e| e <= u32::MAX as u64);
+        let end = end.ok_or(FsError::NoSpace)?; // FAT files stop at 4 GiB
+        let mut st = self.vol.state.lock();
+        self.vol.reap(&mut st)?;
+        let mut loc = self.loc.lock();
+        if end > loc.size as u64 {
+            self.resize_chain(&mut st, &mut loc, end)?;
+        }
+        let old = loc.size as u64;
+        if offset > old {
+            self.zero_tail(&mut loc, old, offset)?;
+        }
+        let chain = self.chain(&mut loc)?;
+        let csize = self.vol.geo.cluster_size as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let cluster = chain[(pos / csize) as usize];
+            let in_cluster = pos % csize;
+            let chunk = ((csize - in_cluster) as usize).min(buf.len() - done);
+            self.vol.write(self.vol.geo.cluster_offset(cluster) + in_cluster, &buf[done..done + chunk])?;
+            done += chunk;
+        }
+        loc.size = loc.size.max(end as u32);
+        if !loc.unlinked {
+            self.vol.update_entry(loc.entry, loc.first, loc.size)?;
+        }
+        Ok(done)
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        if self.kind == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        self.check_writable()?;
+        if size > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let mut st = self.vol.state.lock();
+        self.vol.reap(&mut st)?;
+        let mut loc = self.loc.lock();
+        self.resize_chain(&mut st, &mut loc, size)?;
+        let old = loc.size as u64;
+        if size > old {
+            self.zero_tail(&mut loc, old, size)?;
+        }
+        loc.size = size as u32;
+        if !loc.unlinked {
+            self.vol.update_entry(loc.entry, loc.first, loc.size)?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<InodeRef> {
+        let first = self.dir_cluster()?;
+        let mut st = self.vol.state.lock();
+        let (slot, extents) = self.vol.find(first, name)?;
+        let offset = Volume::entry_offset(&extents, slot.index);
+        Ok(FatInode::get(&self.vol, &mut st, &slot, offset))
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let first = self.dir_cluster()?;
+        let _st = self.vol.state.lock();
+        let (raw, extents) = self.vol.read_dir_raw(first)?;
+        Ok(parse_dir(&raw)
+            .into_iter()
+            .map(|s| DirEntry {
+                ino: Volume::entry_offset(&extents, s.index),
+                kind: if s.is_dir() { FileType::Dir } else { FileType::File },
+                name: s.name,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: FileType, _mode: u16) -> FsResult<InodeRef> {
+        let first = self.dir_cluster()?;
+        if kind != FileType::File && kind != FileType::Dir {
+            return Err(FsError::NotSupported);
+        }
+        self.check_writable()?;
+        let mut st = self.vol.state.lock();
+        self.vol.reap(&mut st)?;
+        match self.vol.find(first, name) {
+            Ok(_) => return Err(FsError::Exists),
+            Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let (attr, cluster) = if kind == FileType::Dir {
+            // a new directory starts with its "." and ".." entries
+            let c = self.vol.alloc_cluster(&mut st, None)?;
+            let mut dots = [0u8; 2 * ENTRY_SIZE];
+            let parent = self.vol.dotdot_cluster(first);
+            dots[..ENTRY_SIZE].copy_from_slice(&make_short_entry(b".          ", ATTR_DIRECTORY, 0, c, 0));
+            dots[ENTRY_SIZE..].copy_from_slice(&make_short_entry(b"..         ", ATTR_DIRECTORY, 0, parent, 0));
+            self.vol.write(self.vol.geo.cluster_offset(c), &dots)?;
+            (ATTR_DIRECTORY, c)
+        } else {
+            (ATTR_ARCHIVE, 0)
+        };
+        let offset = match self.vol.add_entry(&mut st, first, name, attr, cluster, 0) {
+            Ok(o) => o,
+            Err(e) => {
+                if cluster != 0 {
+                    self.vol.free_chain(&mut st, cluster)?;
+                }
+                return Err(e);
+            }
+        };
+        let (slot, _) = self.vol.find(first, name)?;
+        Ok(FatInode::get(&self.vol, &mut st, &slot, offset))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        let first = self.dir_cluster()?;
+        self.check_writable()?;
+        let mut st = self.vol.state.lock();
+        self.vol.reap(&mut st)?;
+        let (slot, extents) = self.vol.find(first, name)?;
+        if slot.is_dir() && slot.cluster != 0 && !self.vol.read_dir(slot.cluster)?.is_empty() {
+            return Err(FsError::NotEmpty);
+        }
+        self.vol.remove_entry(&slot, &extents)?;
+        let offset = Volume::entry_offset(&extents, slot.index);
+        let open = st.inodes.remove(&offset).and_then(|w| w.upgrade());
+        match open {
+            // freed when the last reference is dropped (queued by Drop)
+            Some(inode) => inode.loc.lock().unlinked = true,
+            None if slot.cluster != 0 => self.vol.free_chain(&mut st, slot.cluster)?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &InodeRef, new_name: &str) -> FsResult<()> {
+        let target = new_dir.as_any().downcast_ref::<FatInode>().ok_or(FsError::CrossDevice)?;
+        if !Arc::ptr_eq(&self.vol, &target.vol) {
+            return Err(FsError::CrossDevice);
+        }
+        let from = self.dir_cluster()?;
+        let to = target.dir_cluster()?;
+        self.check_writable()?;
+        let vol = &self.vol;
+        let mut st = vol.state.lock();
+        vol.reap(&mut st)?;
+
+        let (src, src_extents) = vol.find(from, old_name)?;
+        let src_offset = Volume::entry_offset(&src_extents, src.index);
+        match vol.find(to, new_name) {
+            Ok((dst, dst_extents)) => {
+                let dst_offset = Volume::entry_offset(&dst_extents, dst.index);
+                if dst_offset != src_offset {
+                    match (src.is_dir(), dst.is_dir()) {
+                        (true, true) if !vol.read_dir(dst.cluster)?.is_empty() => return Err(FsError::NotEmpty),
+                        (true, false) => return Err(FsError::NotDir),
+                        (false, true) => return Err(FsError::IsDir),
+                        _ => {}
+                    }
+                    vol.remove_entry(&dst, &dst_extents)?;
+                    match st.inodes.remove(&dst_offset).and_then(|w| w.upgrade()) {
+                        Some(inode) => inode.loc.lock().unlinked = true,
+                        None if dst.cluster != 0 => vol.free_chain(&mut st, dst.cluster)?,
+                        None => {}
+                    }
+                }
+            }
+            Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        vol.remove_entry(&src, &src_extents)?;
+        let new_offset = vol.add_entry(&mut st, to, new_name, src.attr, src.cluster, src.size)?;
+        if src.is_dir() && from != to {
+            // point the moved directory's ".." at its new parent
+            let dotdot = vol.geo.cluster_offset(src.cluster) + ENTRY_SIZE as u64;
+            let mut e = [0u8; ENTRY_SIZE];
+            vol.read(dotdot, &mut e)?;
+            let parent = vol.dotdot_cluster(to);
+            e[20..22].copy_from_slice(&((parent >> 16) as u16).to_le_bytes());
+            e[26..28].copy_from_slice(&(parent as u16).to_le_bytes());
+            vol.write(dotdot, &e)?;
+        }
+        if let Some(inode) = st.inodes.remove(&src_offset).and_then(|w| w.upgrade()) {
+            inode.loc.lock().entry = new_offset;
+            st.inodes.insert(new_offset, Arc::downgrade(&inode));
+        }
+        Ok(())
+    }
+}
+
+pub struct FatFs {
+    root: Arc<FatInode>,
+}
+
+impl FatFs {
+    /// Mount the FAT volume on `disk`.
+    pub fn new(disk: Arc<Disk>) -> FsResult<Self> {
+        let mut bs = [0u8; 512];
+        disk.read_at(0, &mut bs)?;
+        let mut geo = Geometry::parse(&bs)?;
+        if (geo.data_offset + geo.clusters as u64 * geo.cluster_size as u64) > disk.size() {
+            return Err(FsError::WrongFs);
+        }
+        let mut state = State { next_free: 2, free_count: None, inodes: BTreeMap::new() };
+        if let Some(off) = geo.fsinfo_offset {
+            let mut info = [0u8; 512];
+            disk.read_at(off, &mut info)?;
+            if le32(&info, 0) == 0x4161_5252 && le32(&info, 484) == 0x6141_7272 {
+                let free = le32(&info, 488);
+                if free <= geo.clusters {
+                    state.free_count = Some(free);
+                }
+                let hint = le32(&info, 492);
+                if geo.valid_cluster(hint) {
+                    state.next_free = hint;
+                }
+            } else {
+                geo.fsinfo_offset = None; // not a real FSInfo sector; leave it alone
+            }
+        }
+        let root_first = if geo.kind == FatKind::Fat32 { geo.root_cluster } else { 0 };
+        let vol = Arc::new(Volume { disk, geo, state: Mutex::new(state), orphans: Mutex::new(Vec::new()) });
+        let root = Arc::new(FatInode {
+            vol,
+            kind: FileType::Dir,
+            read_only: false,
+            loc: Mutex::new(Loc { entry: 0, first: root_first, size: 0, chain: None, unlinked: false }),
+        });
+        Ok(FatFs { root })
+    }
+
+    pub fn kind(&self) -> FatKind {
+        self.root.vol.geo.kind
+    }
+}
+
+impl FileSystem for FatFs {
+    fn name(&self) -> &'static str {
+        match self.kind() {
+            FatKind::Fat12 => "fat12",
+            FatKind::Fat16 => "fat16",
+            FatKind::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        let vol = &self.root.vol;
+        let mut st = vol.state.lock();
+        vol.reap(&mut st)?;
+        if vol.disk.read_only() {
+            return Ok(());
+        }
+        if let Some(off) = vol.geo.fsinfo_offset {
+            let mut info = [0u8; 8];
+            info[..4].copy_from_slice(&st.free_count.unwrap_or(u32::MAX).to_le_bytes());
+            info[4..].copy_from_slice(&st.next_free.to_le_bytes());
+            vol.write(off + 488, &info)?;
+        }
+        Ok(vol.disk.sync()?)
+    }
+}
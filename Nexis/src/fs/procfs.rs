@@ -0,0 +1,285 @@
+// Nexis/src/fs/procfs.rs
+//
+// Synthetic filesystem exposing kernel state, mounted at /proc. Nothing is
+// stored: every read regenerates the file's text from the live counters,
+// so a file read in several pieces may straddle an update. Directories
+// for processes come and go with the process table.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt::Write;
+
+use super::vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, InodeRef, Metadata};
+use crate::process::{Pid, ProcState, Process, PROC_TABLE};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    Meminfo,
+    Uptime,
+    Interrupts,
+    Syscalls,
+    /// `self`, a symlink to the caller's pid directory.
+    SelfLink,
+    PidDir(Pid),
+    Status(Pid),
+}
+
+/// Files in the root besides the pid directories.
+const ROOT_FILES: [(&str, Node); 5] = [
+    ("meminfo", Node::Meminfo),
+    ("uptime", Node::Uptime),
+    ("interrupts", Node::Interrupts),
+    ("syscalls", Node::Syscalls),
+    ("self", Node::SelfLink),
+];
+
+/// A process, live or zombie, copied out of the table.
+fn process(pid: Pid) -> Option<Process> {
+    PROC_TABLE
+        .lock()
+        .procs
+        .iter()
+        .find(|p| p.pid == pid && p.state != ProcState::Finished)
+        .copied()
+}
+
+fn pids() -> Vec<Pid> {
+    PROC_TABLE
+        .lock()
+        .procs
+        .iter()
+        .filter(|p| p.state != ProcState::Finished)
+        .map(|p| p.pid)
+        .collect()
+}
+
+fn state_name(state: ProcState) -> &'static str {
+    match state {
+        ProcState::Runnable | ProcState::Running => "R (running)",
+        ProcState::Sleeping(_) => "S (sleeping)",
+        ProcState::Zombie => "Z (zombie)",
+        ProcState::Finished => "X (dead)",
+    }
+}
+
+fn meminfo() -> String {
+    let pmm = crate::vmm::kernel_vmm().pmm();
+    let kb = crate::memory::FRAME_SIZE / 1024;
+    let heap = crate::heap::stats();
+    let mut s = String::new();
+    let _ = writeln!(s, "MemTotal:    {:>8} kB", pmm.total_frames() * kb);
+    let _ = writeln!(s, "MemFree:     {:>8} kB", pmm.free_frames() * kb);
+    let _ = writeln!(s, "FramesTotal: {:>8}", pmm.total_frames());
+    let _ = writeln!(s, "FramesFree:  {:>8}", pmm.free_frames());
+    let _ = writeln!(s, "HeapTotal:   {:>8} kB", heap.heap_size / 1024);
+    let _ = writeln!(s, "HeapUsed:    {:>8} kB", heap.in_use / 1024);
+    let _ = writeln!(s, "HeapPeak:    {:>8} kB", heap.peak / 1024);
+    let _ = writeln!(s, "HeapFree:    {:>8} kB", heap.free / 1024);
+    s
+}
+
+fn uptime() -> String {
+    let ms = crate::pit::uptime_ms();
+    format!("{}.{:02}\n", ms / 1000, ms % 1000 / 10)
+}
+
+fn interrupts() -> String {
+    let mut s = String::from(" IRQ       COUNT  NAME\n");
+    for (irq, name, count) in crate::interrupts::irq_stats() {
+        let _ = writeln!(s, "{:>4}  {:>10}  {}", irq, count, name);
+    }
+    s
+}
+
+fn syscalls() -> String {
+    let mut s = String::from(" NR  NAME         ARGS       CALLS\n");
+    for (nr, call, count) in crate::syscall::stats() {
+        let _ = writeln!(s, "{:>3}  {:<12} {:>4}  {:>10}", nr, call.name, call.argc, count);
+    }
+    let _ = writeln!(s, "unknown: {}", crate::syscall::unknown_calls());
+    s
+}
+
+fn status(pid: Pid) -> FsResult<String> {
+    let p = process(pid).ok_or(FsError::NotFound)?;
+    let mut s = String::new();
+    let _ = writeln!(s, "Name:   {}", p.name_str());
+    let _ = writeln!(s, "State:  {}", state_name(p.state));
+    let _ = writeln!(s, "Pid:    {}", p.pid);
+    let _ = writeln!(s, "PPid:   {}", p.parent.unwrap_or(0));
+    let _ = writeln!(s, "Stack:  {} kB", p.stack_size / 1024);
+    let _ = writeln!(s, "FDs:    {}", crate::fd::open_count(pid));
+    if let Some(t) = crate::scheduler::task(p.slot).filter(|_| p.is_live()) {
+        let _ = writeln!(s, "Sched:  {}", t.policy.name());
+        let _ = writeln!(s, "Nice:   {}", t.nice);
+    }
+    let ms = |ticks| crate::pit::ticks_to_us(ticks) / 1000;
+    let times = p.cpu_times();
+    let _ = writeln!(s, "Utime:  {} ms", ms(times.user));
+    let _ = writeln!(s, "Stime:  {} ms", ms(times.system));
+    Ok(s)
+}
+
+pub struct ProcInode {
+    node: Node,
+}
+
+fn inode(node: Node) -> InodeRef {
+    Arc::new(ProcInode { node })
+}
+
+impl ProcInode {
+    fn kind(&self) -> FileType {
+        match self.node {
+            Node::Root | Node::PidDir(_) => FileType::Dir,
+            Node::SelfLink => FileType::Symlink,
+            _ => FileType::File,
+        }
+    }
+
+    fn ino(&self) -> u64 {
+        match self.node {
+            Node::Root => 1,
+            Node::Meminfo => 2,
+            Node::Uptime => 3,
+            Node::Interrupts => 4,
+            Node::Syscalls => 5,
+            Node::SelfLink => 6,
+            Node::PidDir(pid) => (pid as u64) << 8,
+            Node::Status(pid) => (pid as u64) << 8 | 1,
+        }
+    }
+
+    fn contents(&self) -> FsResult<String> {
+        match self.node {
+            Node::Meminfo => Ok(meminfo()),
+            Node::Uptime => Ok(uptime()),
+            Node::Interrupts => Ok(interrupts()),
+            Node::Syscalls => Ok(syscalls()),
+            Node::Status(pid) => status(pid),
+            Node::Root | Node::PidDir(_) => Err(FsError::IsDir),
+            Node::SelfLink => Err(FsError::NotSupported),
+        }
+    }
+}
+
+impl Inode for ProcInode {
+    fn metadata(&self) -> Metadata {
+        let kind = self.kind();
+        // files report their current length so whole-file reads size
+        // their buffer right
+        let size = match kind {
+            FileType::File => self.contents().map_or(0, |c| c.len() as u64),
+            _ => 0,
+        };
+        let mode = match kind {
+            FileType::Dir => 0o555,
+            FileType::Symlink => 0o777,
+            _ => 0o444,
+        };
+        Metadata { ino: self.ino(), kind, size, mode, nlink: 1 }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let text = self.contents()?;
+        let bytes = text.as_bytes();
+        if offset >= bytes.len() as u64 {
+            return Ok(0);
+        }
+        let start = offset as usize;
+        let n = buf.len().min(bytes.len() - start);
+        buf[..n].copy_from_slice(&bytes[start..start + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        match self.kind() {
+            FileType::Dir => Err(FsError::IsDir),
+            _ => Err(FsError::ReadOnly),
+        }
+    }
+
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<InodeRef> {
+        match self.node {
+            Node::Root => {
+                if let Some(&(_, node)) = ROOT_FILES.iter().find(|(n, _)| *n == name) {
+                    return Ok(inode(node));
+                }
+                let pid: Pid = name.parse().map_err(|_| FsError::NotFound)?;
+                process(pid).ok_or(FsError::NotFound)?;
+                Ok(inode(Node::PidDir(pid)))
+            }
+            Node::PidDir(pid) => match name {
+                "status" if process(pid).is_some() => Ok(inode(Node::Status(pid))),
+                _ => Err(FsError::NotFound),
+            },
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        let entry = |name: String, node: Node| {
+            let i = ProcInode { node };
+            DirEntry { name, ino: i.ino(), kind: i.kind() }
+        };
+        match self.node {
+            Node::Root => {
+                let mut out: Vec<DirEntry> =
+                    ROOT_FILES.iter().map(|&(name, node)| entry(String::from(name), node)).collect();
+                for pid in pids() {
+                    out.push(entry(format!("{}", pid), Node::PidDir(pid)));
+                }
+                Ok(out)
+            }
+            Node::PidDir(pid) => {
+                process(pid).ok_or(FsError::NotFound)?;
+                Ok(alloc::vec![entry(String::from("status"), Node::Status(pid))])
+            }
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> FsResult<InodeRef> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readlink(&self) -> FsResult<String> {
+        match self.node {
+            // kernel tasks outside the process table have no directory
+            Node::SelfLink => crate::process::current_pid().map(|pid| format!("{}", pid)).ok_or(FsError::NotFound),
+            _ => Err(FsError::InvalidPath),
+        }
+    }
+}
+
+pub struct ProcFs;
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "proc"
+    }
+
+    fn root(&self) -> InodeRef {
+        inode(Node::Root)
+    }
+}
@@ -0,0 +1,317 @@
+// Nexis/src/fs/tmpfs.rs
+//
+// Writable RAM filesystem. File contents live in whole PMM frames, reached
+// through the physical-memory window and kept in a map by page index, so
+// holes (never-written pages) cost nothing and read as zeros. Frames go back to the PMM when a file shrinks
+// or its last reference is dropped.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::vfs::{DirEntry, FileSystem, FileType, FsError, FsResult, Inode, InodeRef, Metadata};
+use crate::memory::{phys_to_virt, PhysFrame, FRAME_SIZE};
+
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+fn pmm() -> &'static crate::memory::PhysicalMemoryManager {
+    crate::vmm::kernel_vmm().pmm()
+}
+
+fn page_ptr(frame: PhysFrame) -> *mut u8 {
+    phys_to_virt(frame.0) as *mut u8
+}
+
+struct FileData {
+    size: usize,
+    /// Page index -> frame, for the pages that have been written.
+    pages: BTreeMap<usize, PhysFrame>,
+}
+
+impl FileData {
+    fn page_mut(&mut self, index: usize) -> FsResult<PhysFrame> {
+        if let Some(&frame) = self.pages.get(&index) {
+            return Ok(frame);
+        }
+        let frame = pmm().alloc_frame().ok_or(FsError::NoSpace)?;
+        unsafe { ptr::write_bytes(page_ptr(frame), 0, FRAME_SIZE) };
+        self.pages.insert(index, frame);
+        Ok(frame)
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> usize {
+        if offset >= self.size {
+            return 0;
+        }
+        let n = buf.len().min(self.size - offset);
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done;
+            let in_page = pos % FRAME_SIZE;
+            let chunk = (FRAME_SIZE - in_page).min(n - done);
+            let dst = &mut buf[done..done + chunk];
+            match self.pages.get(&(pos / FRAME_SIZE)) {
+                Some(&frame) => unsafe {
+                    ptr::copy_nonoverlapping(page_ptr(frame).add(in_page), dst.as_mut_ptr(), chunk)
+                },
+                None => dst.fill(0),
+            }
+            done += chunk;
+        }
+        n
+    }
+
+    fn write(&mut self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        offset.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_page = pos % FRAME_SIZE;
+            let chunk = (FRAME_SIZE - in_page).min(buf.len() - done);
+            let frame = match self.page_mut(pos / FRAME_SIZE) {
+                Ok(f) => f,
+                // short write if we managed part of it
+                Err(e) if done == 0 => return Err(e),
+                Err(_) => break,
+            };
+            unsafe { ptr::copy_nonoverlapping(buf[done..].as_ptr(), page_ptr(frame).add(in_page), chunk) };
+            done += chunk;
+        }
+        self.size = self.size.max(offset + done);
+        Ok(done)
+    }
+
+    fn truncate(&mut self, size: usize) {
+        let keep = size.div_ceil(FRAME_SIZE);
+        for frame in self.pages.split_off(&keep).into_values() {
+            pmm().free_frame(frame.0);
+        }
+        // zero the tail of the last page so growing again reads zeros
+        if !size.is_multiple_of(FRAME_SIZE) && size < self.size {
+            if let Some(frame) = self.pages.get(&(size / FRAME_SIZE)) {
+                let in_page = size % FRAME_SIZE;
+                unsafe { ptr::write_bytes(page_ptr(*frame).add(in_page), 0, FRAME_SIZE - in_page) };
+            }
+        }
+        self.size = size;
+    }
+}
+
+impl Drop for FileData {
+    fn drop(&mut self) {
+        self.truncate(0);
+    }
+}
+
+enum Node {
+    File(FileData),
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+}
+
+pub struct TmpInode {
+    ino: u64,
+    kind: FileType,
+    mode: u16,
+    node: Mutex<Node>,
+}
+
+impl TmpInode {
+    fn new(kind: FileType, mode: u16) -> Arc<Self> {
+        let node = match kind {
+            FileType::Dir => Node::Dir(BTreeMap::new()),
+            _ => Node::File(FileData { size: 0, pages: BTreeMap::new() }),
+        };
+        Arc::new(TmpInode {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            kind,
+            mode,
+            node: Mutex::new(node),
+        })
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&*self.node.lock(), Node::Dir(e) if e.is_empty())
+    }
+}
+
+/// Check that `src` may replace `dst` in a rename.
+fn check_replace(src: &TmpInode, dst: &TmpInode) -> FsResult<()> {
+    match (src.kind == FileType::Dir, dst.kind == FileType::Dir) {
+        (true, true) if !dst.is_empty_dir() => Err(FsError::NotEmpty),
+        (true, false) => Err(FsError::NotDir),
+        (false, true) => Err(FsError::IsDir),
+        _ => Ok(()),
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let (size, nlink) = match &*self.node.lock() {
+            Node::File(f) => (f.size as u64, 1),
+            Node::Dir(entries) => {
+                let subdirs = entries.values().filter(|i| i.kind == FileType::Dir).count();
+                (entries.len() as u64, 2 + subdirs as u32)
+            }
+        };
+        Metadata { ino: self.ino, kind: self.kind, size, mode: self.mode, nlink }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        match &*self.node.lock() {
+            Node::File(f) => Ok(f.read(offset as usize, buf)),
+            Node::Dir(_) => Err(FsError::IsDir),
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        match &mut *self.node.lock() {
+            Node::File(f) => f.write(offset as usize, buf),
+            Node::Dir(_) => Err(FsError::IsDir),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()> {
+        match &mut *self.node.lock() {
+            Node::File(f) => {
+                f.truncate(size as usize);
+                Ok(())
+            }
+            Node::Dir(_) => Err(FsError::IsDir),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<InodeRef> {
+        match &*self.node.lock() {
+            Node::Dir(entries) => entries
+                .get(name)
+                .map(|i| i.clone() as InodeRef)
+                .ok_or(FsError::NotFound),
+            Node::File(_) => Err(FsError::NotDir),
+        }
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        match &*self.node.lock() {
+            Node::Dir(entries) => Ok(entries
+                .iter()
+                .map(|(name, i)| DirEntry { name: name.clone(), ino: i.ino, kind: i.kind })
+                .collect()),
+            Node::File(_) => Err(FsError::NotDir),
+        }
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u16) -> FsResult<InodeRef> {
+        if kind != FileType::File && kind != FileType::Dir {
+            return Err(FsError::NotSupported);
+        }
+        match &mut *self.node.lock() {
+            Node::Dir(entries) => {
+                if entries.contains_key(name) {
+                    return Err(FsError::Exists);
+                }
+                let inode = TmpInode::new(kind, mode);
+                entries.insert(name.to_string(), inode.clone());
+                Ok(inode)
+            }
+            Node::File(_) => Err(FsError::NotDir),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        match &mut *self.node.lock() {
+            Node::Dir(entries) => {
+                let victim = entries.get(name).ok_or(FsError::NotFound)?;
+                if victim.kind == FileType::Dir && !victim.is_empty_dir() {
+                    return Err(FsError::NotEmpty);
+                }
+                // frames are freed once the last open reference goes away
+                entries.remove(name);
+                Ok(())
+            }
+            Node::File(_) => Err(FsError::NotDir),
+        }
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &InodeRef, new_name: &str) -> FsResult<()> {
+        let target = new_dir.as_any().downcast_ref::<TmpInode>().ok_or(FsError::CrossDevice)?;
+
+        if target.ino == self.ino {
+            let mut node = self.node.lock();
+            let entries = match &mut *node {
+                Node::Dir(e) => e,
+                _ => return Err(FsError::NotDir),
+            };
+            let src = entries.get(old_name).ok_or(FsError::NotFound)?.clone();
+            if old_name == new_name {
+                return Ok(());
+            }
+            if let Some(dst) = entries.get(new_name) {
+                check_replace(&src, dst)?;
+            }
+            entries.remove(old_name);
+            entries.insert(new_name.to_string(), src);
+            return Ok(());
+        }
+
+        // lock both directories in inode order so two renames in opposite
+        // directions cannot deadlock
+        let (mut a, mut b) = if self.ino < target.ino {
+            let a = self.node.lock();
+            (a, target.node.lock())
+        } else {
+            let b = target.node.lock();
+            (self.node.lock(), b)
+        };
+        let (from, to) = match (&mut *a, &mut *b) {
+            (Node::Dir(from), Node::Dir(to)) => (from, to),
+            _ => return Err(FsError::NotDir),
+        };
+        let src = from.get(old_name).ok_or(FsError::NotFound)?.clone();
+        if let Some(dst) = to.get(new_name) {
+            // replacing one of the two locked directories: it is an
+            // ancestor of `src`, so it is not empty
+            if dst.ino == self.ino || dst.ino == target.ino {
+                return Err(FsError::NotEmpty);
+            }
+            check_replace(&src, dst)?;
+        }
+        from.remove(old_name);
+        to.insert(new_name.to_string(), src);
+        Ok(())
+    }
+}
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        TmpFs { root: TmpInode::new(FileType::Dir, 0o755) }
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> InodeRef {
+        self.root.clone()
+    }
+}
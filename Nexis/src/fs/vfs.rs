@@ -0,0 +1,505 @@
+// Nexis/src/fs/vfs.rs
+//
+// Virtual filesystem: the inode and filesystem traits every concrete
+// filesystem implements, the mount table, and path resolution.
+//
+// Paths are resolved one component at a time from the root (or the current
+// directory), keeping the chain of directories walked so far; `..` pops
+// that chain, so it never leaves the root and correctly steps back out of
+// a mounted filesystem. After each step the mount table is consulted and a
+// mount point is replaced by the root of the filesystem mounted on it.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use spin::Mutex;
+
+use crate::errno::{self, Errno};
+
+/// Longest single path component.
+pub const NAME_MAX: usize = 255;
+/// Longest path accepted by `resolve`.
+pub const PATH_MAX: usize = 4096;
+/// Symlinks followed in one lookup before giving up with `Loop`.
+pub const SYMLOOP_MAX: usize = 8;
+/// Largest file offset a descriptor can seek or write to (the FAT limit;
+/// no filesystem here holds more).
+pub const MAX_FILE_SIZE: u64 = 1 << 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    NotDir,
+    IsDir,
+    Exists,
+    NotEmpty,
+    ReadOnly,
+    NoSpace,
+    InvalidPath,
+    NameTooLong,
+    Busy,
+    CrossDevice,
+    Io,
+    NotSupported,
+    /// The device does not hold this kind of filesystem.
+    WrongFs,
+    /// Too many symlinks along a path.
+    Loop,
+}
+
+impl FsError {
+    pub fn errno(self) -> Errno {
+        match self {
+            FsError::NotFound => errno::ENOENT,
+            FsError::NotDir => errno::ENOTDIR,
+            FsError::IsDir => errno::EISDIR,
+            FsError::Exists => errno::EEXIST,
+            FsError::NotEmpty => errno::ENOTEMPTY,
+            FsError::ReadOnly => errno::EROFS,
+            FsError::NoSpace => errno::ENOSPC,
+            FsError::InvalidPath => errno::EINVAL,
+            FsError::NameTooLong => errno::ENAMETOOLONG,
+            FsError::Busy => errno::EBUSY,
+            FsError::CrossDevice => errno::EXDEV,
+            FsError::Io => errno::EIO,
+            FsError::NotSupported => errno::EPERM,
+            FsError::WrongFs => errno::EINVAL,
+            FsError::Loop => errno::ELOOP,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FsError::NotFound => "no such file or directory",
+            FsError::NotDir => "not a directory",
+            FsError::IsDir => "is a directory",
+            FsError::Exists => "file exists",
+            FsError::NotEmpty => "directory not empty",
+            FsError::ReadOnly => "read-only filesystem",
+            FsError::NoSpace => "no space left on device",
+            FsError::InvalidPath => "invalid path",
+            FsError::NameTooLong => "name too long",
+            FsError::Busy => "device or resource busy",
+            FsError::CrossDevice => "cross-device link",
+            FsError::Io => "I/O error",
+            FsError::NotSupported => "operation not supported",
+            FsError::WrongFs => "wrong filesystem type",
+            FsError::Loop => "too many levels of symbolic links",
+        }
+    }
+}
+
+pub type FsResult<T> = Result<T, FsError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+}
+
+impl FileType {
+    /// `ls -l` style type character.
+    pub fn symbol(self) -> char {
+        match self {
+            FileType::File => '-',
+            FileType::Dir => 'd',
+            FileType::Symlink => 'l',
+            FileType::CharDevice => 'c',
+            FileType::BlockDevice => 'b',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    pub ino: u64,
+    pub kind: FileType,
+    pub size: u64,
+    /// Permission bits (0o755 style); not enforced yet.
+    pub mode: u16,
+    pub nlink: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub kind: FileType,
+}
+
+pub type InodeRef = Arc<dyn Inode>;
+
+/// A file, directory or other object inside one filesystem. Operations a
+/// filesystem does not support fall back to the defaults below.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// For downcasting in operations that involve two inodes (rename).
+    fn as_any(&self) -> &dyn Any;
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(not_a_file(self.metadata().kind))
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(not_a_file(self.metadata().kind))
+    }
+
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(not_a_file(self.metadata().kind))
+    }
+
+    fn lookup(&self, _name: &str) -> FsResult<InodeRef> {
+        Err(FsError::NotDir)
+    }
+
+    fn readdir(&self) -> FsResult<Vec<DirEntry>> {
+        Err(FsError::NotDir)
+    }
+
+    fn create(&self, _name: &str, _kind: FileType, _mode: u16) -> FsResult<InodeRef> {
+        Err(FsError::NotDir)
+    }
+
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// Move entry `old_name` of this directory to `new_name` in `new_dir`,
+    /// which must belong to the same filesystem.
+    fn rename(&self, _old_name: &str, _new_dir: &InodeRef, _new_name: &str) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    fn readlink(&self) -> FsResult<String> {
+        Err(FsError::InvalidPath)
+    }
+}
+
+fn not_a_file(kind: FileType) -> FsError {
+    match kind {
+        FileType::Dir => FsError::IsDir,
+        _ => FsError::NotSupported,
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Short type name shown in the mount table ("tmpfs", "fat", ...).
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> InodeRef;
+
+    /// Write back anything cached; called before unmounting.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// A resolved path: the inode plus the canonical absolute path that led
+/// to it.
+#[derive(Clone)]
+pub struct Dentry {
+    pub path: String,
+    pub inode: InodeRef,
+}
+
+impl Dentry {
+    pub fn name(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(i) if self.path.len() > 1 => &self.path[i + 1..],
+            _ => "/",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+lazy_static::lazy_static! {
+    static ref MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+    /// Working directory for relative paths. Shared by every task until
+    /// processes carry their own.
+    static ref CWD: Mutex<String> = Mutex::new(String::from("/"));
+}
+
+fn mounts_snapshot() -> Vec<Mount> {
+    MOUNTS.lock().clone()
+}
+
+fn mounted_at(mounts: &[Mount], path: &str) -> Option<InodeRef> {
+    // later mounts hide earlier ones on the same path
+    mounts.iter().rev().find(|m| m.path == path).map(|m| m.fs.root())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    let mut p = String::with_capacity(dir.len() + name.len() + 1);
+    p.push_str(dir);
+    if !dir.ends_with('/') {
+        p.push('/');
+    }
+    p.push_str(name);
+    p
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(FsError::InvalidPath);
+    }
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    Ok(())
+}
+
+/// Walk `path` from the root or the current directory, following symlinks.
+pub fn resolve(path: &str) -> FsResult<Dentry> {
+    walk(path, true, 0)
+}
+
+/// Like `resolve`, but a symlink in the last component is returned itself.
+pub fn resolve_nofollow(path: &str) -> FsResult<Dentry> {
+    walk(path, false, 0)
+}
+
+fn walk(path: &str, follow_last: bool, links: usize) -> FsResult<Dentry> {
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    if path.len() > PATH_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    let mounts = mounts_snapshot();
+    let root = mounted_at(&mounts, "/").ok_or(FsError::NotFound)?;
+
+    // chain of (absolute path, inode), root first
+    let mut chain: Vec<(String, InodeRef)> = Vec::new();
+    chain.push((String::from("/"), root));
+
+    let full;
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        full = join(&cwd(), path);
+        full.as_str()
+    };
+
+    let comps: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    for (i, &comp) in comps.iter().enumerate() {
+        if comp == ".." {
+            if chain.len() > 1 {
+                chain.pop();
+            }
+            continue;
+        }
+        if comp.len() > NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
+        let (dir_path, dir) = chain.last().expect("chain starts at root");
+        let child_path = join(dir_path, comp);
+        let child = match mounted_at(&mounts, &child_path) {
+            Some(root) => {
+                // only a directory can be a mount point
+                dir.lookup(comp)?;
+                root
+            }
+            None => dir.lookup(comp)?,
+        };
+        let last = i + 1 == comps.len();
+        if child.metadata().kind == FileType::Symlink && (!last || follow_last) {
+            if links >= SYMLOOP_MAX {
+                return Err(FsError::Loop);
+            }
+            // splice the target in place of the link and start over
+            let target = child.readlink()?;
+            let mut rest = if target.starts_with('/') { target } else { join(dir_path, &target) };
+            for c in &comps[i + 1..] {
+                rest = join(&rest, c);
+            }
+            return walk(&rest, follow_last, links + 1);
+        }
+        chain.push((child_path, child));
+    }
+
+    let (path, inode) = chain.pop().expect("chain starts at root");
+    Ok(Dentry { path, inode })
+}
+
+/// Resolve everything but the last component of `path`: the directory an
+/// entry would be created in or removed from, and the entry's name.
+pub fn resolve_parent(path: &str) -> FsResult<(Dentry, String)> {
+    let trimmed = path.trim_end_matches('/');
+    let (dir, name) = match trimmed.rfind('/') {
+        Some(0) => ("/", &trimmed[1..]),
+        Some(i) => (&trimmed[..i], &trimmed[i + 1..]),
+        None => (".", trimmed),
+    };
+    check_name(name)?;
+    let parent = resolve(dir)?;
+    if parent.inode.metadata().kind != FileType::Dir {
+        return Err(FsError::NotDir);
+    }
+    Ok((parent, name.to_string()))
+}
+
+/// Attach `fs` at `path`. The first mount must be "/"; later ones need an
+/// existing directory to cover.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> FsResult<()> {
+    let path = if MOUNTS.lock().is_empty() {
+        if path != "/" {
+            return Err(FsError::NotFound);
+        }
+        String::from("/")
+    } else {
+        let d = resolve(path)?;
+        if d.inode.metadata().kind != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        d.path
+    };
+    MOUNTS.lock().push(Mount { path, fs });
+    Ok(())
+}
+
+/// Detach the filesystem most recently mounted at `path`.
+pub fn unmount(path: &str) -> FsResult<()> {
+    let path = resolve(path)?.path;
+    let mut mounts = MOUNTS.lock();
+    let idx = mounts.iter().rposition(|m| m.path == path).ok_or(FsError::InvalidPath)?;
+    let covering = |m: &Mount| m.path.len() > path.len() && m.path.starts_with(&path);
+    if path == "/" || mounts.iter().any(covering) {
+        return Err(FsError::Busy);
+    }
+    mounts[idx].fs.sync()?;
+    mounts.remove(idx);
+    Ok(())
+}
+
+/// (mount point, filesystem type) for every mount, in mount order.
+pub fn mounts() -> Vec<(String, &'static str)> {
+    MOUNTS.lock().iter().map(|m| (m.path.clone(), m.fs.name())).collect()
+}
+
+/// Sync every mounted filesystem; returns the mount points that failed.
+pub fn sync_all() -> Vec<(String, FsError)> {
+    let mut failed = Vec::new();
+    for m in mounts_snapshot() {
+        if let Err(e) = m.fs.sync() {
+            failed.push((m.path, e));
+        }
+    }
+    failed
+}
+
+pub fn cwd() -> String {
+    CWD.lock().clone()
+}
+
+pub fn chdir(path: &str) -> FsResult<()> {
+    let d = resolve(path)?;
+    if d.inode.metadata().kind != FileType::Dir {
+        return Err(FsError::NotDir);
+    }
+    *CWD.lock() = d.path;
+    Ok(())
+}
+
+/// Read a whole file.
+pub fn read_file(path: &str) -> FsResult<Vec<u8>> {
+    let inode = resolve(path)?.inode;
+    let meta = inode.metadata();
+    if meta.kind == FileType::Dir {
+        return Err(FsError::IsDir);
+    }
+    let mut data = alloc::vec![0u8; meta.size as usize];
+    let mut done = 0;
+    while done < data.len() {
+        match inode.read_at(done as u64, &mut data[done..])? {
+            0 => break,
+            n => done += n,
+        }
+    }
+    data.truncate(done);
+    Ok(data)
+}
+
+/// Entries of a directory, sorted by name.
+pub fn list_dir(path: &str) -> FsResult<Vec<DirEntry>> {
+    let mut entries = resolve(path)?.inode.readdir()?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn is_mount_point(path: &str) -> bool {
+    MOUNTS.lock().iter().any(|m| m.path == path)
+}
+
+/// Create a file or directory; fails if `path` already exists.
+pub fn create(path: &str, kind: FileType, mode: u16) -> FsResult<InodeRef> {
+    let (parent, name) = resolve_parent(path)?;
+    match parent.inode.lookup(&name) {
+        Ok(_) => Err(FsError::Exists),
+        Err(FsError::NotFound) => parent.inode.create(&name, kind, mode),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn mkdir(path: &str) -> FsResult<()> {
+    create(path, FileType::Dir, 0o755).map(|_| ())
+}
+
+/// Remove a file or an empty directory.
+pub fn remove(path: &str) -> FsResult<()> {
+    let (parent, name) = resolve_parent(path)?;
+    parent.inode.lookup(&name)?;
+    if is_mount_point(&join(&parent.path, &name)) {
+        return Err(FsError::Busy);
+    }
+    parent.inode.unlink(&name)
+}
+
+pub fn rename(old: &str, new: &str) -> FsResult<()> {
+    let (old_parent, old_name) = resolve_parent(old)?;
+    let (new_parent, new_name) = resolve_parent(new)?;
+    let old_path = join(&old_parent.path, &old_name);
+    let new_path = join(&new_parent.path, &new_name);
+    old_parent.inode.lookup(&old_name)?;
+    if is_mount_point(&old_path) || is_mount_point(&new_path) {
+        return Err(FsError::Busy);
+    }
+    // a directory cannot move below itself
+    if new_path.starts_with(&old_path) && new_path.as_bytes().get(old_path.len()) == Some(&b'/') {
+        return Err(FsError::InvalidPath);
+    }
+    old_parent.inode.rename(&old_name, &new_parent.inode, &new_name)
+}
+
+/// Replace the contents of `path` with `data`, or append to it, creating
+/// the file if needed.
+pub fn write_file(path: &str, data: &[u8], append: bool) -> FsResult<usize> {
+    let inode = match resolve(path) {
+        Ok(d) => d.inode,
+        Err(FsError::NotFound) => create(path, FileType::File, 0o644)?,
+        Err(e) => return Err(e),
+    };
+    let offset = if append {
+        inode.metadata().size
+    } else {
+        inode.truncate(0)?;
+        0
+    };
+    let mut done = 0;
+    while done < data.len() {
+        match inode.write_at(offset + done as u64, &data[done..])? {
+            0 => return Err(FsError::NoSpace),
+            n => done += n,
+        }
+    }
+    Ok(done)
+}
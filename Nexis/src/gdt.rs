@@ -0,0 +1,94 @@
+// Nexis/src/gdt.rs
+//
+// Kernel-owned GDT and TSS. The entry order is fixed by SYSRET, which
+// derives the user selectors from STAR: user data must sit directly below
+// user code, so the layout is
+//   0x08 kernel code, 0x10 kernel data, 0x18 user data, 0x20 user code, 0x28 TSS.
+
+use core::ptr::addr_of;
+use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+/// IST slot used by the double-fault handler, so a blown kernel stack
+/// still produces a crash screen instead of a triple fault.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+/// RPL 3 selectors; `enter_user` in asm/switch.S hardcodes the same values.
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+
+const IST_STACK_SIZE: usize = 4096 * 5;
+/// Stack used for ring 3 -> ring 0 transitions until the scheduler
+/// installs a per-task kernel stack with `set_kernel_stack`.
+const BOOT_RSP0_STACK_SIZE: usize = 4096 * 4;
+
+static mut DOUBLE_FAULT_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
+static mut BOOT_RSP0_STACK: [u8; BOOT_RSP0_STACK_SIZE] = [0; BOOT_RSP0_STACK_SIZE];
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
+        (gdt, Selectors { kernel_code, kernel_data, user_data, user_code, tss })
+    };
+}
+
+pub fn init() {
+    unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::from_ptr(addr_of!(DOUBLE_FAULT_STACK)) + IST_STACK_SIZE;
+    }
+    set_kernel_stack(addr_of!(BOOT_RSP0_STACK) as usize + BOOT_RSP0_STACK_SIZE);
+
+    GDT.0.load();
+    let sel = &GDT.1;
+    assert_eq!(sel.kernel_code.0, KERNEL_CODE_SELECTOR);
+    assert_eq!(sel.kernel_data.0, KERNEL_DATA_SELECTOR);
+    assert_eq!(sel.user_data.0, USER_DATA_SELECTOR);
+    assert_eq!(sel.user_code.0, USER_CODE_SELECTOR);
+    unsafe {
+        CS::set_reg(sel.kernel_code);
+        SS::set_reg(sel.kernel_data);
+        DS::set_reg(sel.kernel_data);
+        ES::set_reg(sel.kernel_data);
+        load_tss(sel.tss);
+    }
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// Set RSP0, the stack the CPU switches to when ring 3 traps into the kernel,
+/// and the matching SYSCALL entry stack. Called on every switch to a task
+/// that may run user code.
+pub fn set_kernel_stack(top: usize) {
+    unsafe {
+        TSS.privilege_stack_table[0] = VirtAddr::new(top as u64);
+    }
+    crate::syscall_entry::set_kernel_stack(top);
+}
+
+pub fn kernel_stack() -> usize {
+    unsafe { TSS.privilege_stack_table[0].as_u64() as usize }
+}
@@ -0,0 +1,255 @@
+// Nexis/src/heap.rs
+//
+// Kernel heap: an address-ordered free list with coalescing. The heap lives
+// in its own kernel-half window and grows by mapping fresh PMM frames
+// whenever no free block is large enough.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::ptr::null_mut;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::vmm::{PageFlags, VirtualMemoryManager, PAGE_SIZE};
+
+pub const HEAP_START: usize = 0xFFFF_9000_0000_0000;
+pub const HEAP_INITIAL_SIZE: usize = 1024 * 1024;
+pub const HEAP_MAX_SIZE: usize = 256 * 1024 * 1024;
+/// Minimum growth step, to avoid mapping one page per small allocation.
+const GROW_PAGES: usize = 16;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+const MIN_BLOCK: usize = mem::size_of::<FreeBlock>();
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HeapStats {
+    pub heap_size: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub free: usize,
+    pub free_blocks: usize,
+    pub largest_free: usize,
+    pub allocs: u64,
+    pub frees: u64,
+}
+
+impl HeapStats {
+    /// Share of free memory not in the largest free block, in percent.
+    pub fn fragmentation(&self) -> usize {
+        (self.largest_free * 100).checked_div(self.free).map_or(0, |p| 100 - p)
+    }
+}
+
+pub struct LinkedListHeap {
+    head: *mut FreeBlock,
+    heap_end: usize,
+    vmm: Option<&'static VirtualMemoryManager>,
+    in_use: usize,
+    peak: usize,
+    allocs: u64,
+    frees: u64,
+}
+
+// Safety: the free list is only touched with the heap lock held.
+unsafe impl Send for LinkedListHeap {}
+
+#[inline]
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+impl LinkedListHeap {
+    pub const fn new() -> Self {
+        Self {
+            head: null_mut(),
+            heap_end: HEAP_START,
+            vmm: None,
+            in_use: 0,
+            peak: 0,
+            allocs: 0,
+            frees: 0,
+        }
+    }
+
+    /// Round a layout up so every block can later hold a `FreeBlock` header.
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<FreeBlock>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        (layout.size().max(MIN_BLOCK), layout.align())
+    }
+
+    /// Insert a region into the address-ordered list, merging with neighbours.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() && (cur as usize) < addr {
+            prev = cur;
+            cur = (*cur).next;
+        }
+
+        let node = addr as *mut FreeBlock;
+        node.write(FreeBlock { size, next: cur });
+        if prev.is_null() {
+            self.head = node;
+        } else {
+            (*prev).next = node;
+        }
+
+        if !cur.is_null() && addr + (*node).size == cur as usize {
+            (*node).size += (*cur).size;
+            (*node).next = (*cur).next;
+        }
+        if !prev.is_null() && prev as usize + (*prev).size == addr {
+            (*prev).size += (*node).size;
+            (*prev).next = (*node).next;
+        }
+    }
+
+    /// First-fit search; splits the chosen block and returns the aligned start.
+    unsafe fn take(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() {
+            let start = cur as usize;
+            let end = start + (*cur).size;
+
+            let mut alloc_start = align_up(start, align);
+            if alloc_start != start && alloc_start - start < MIN_BLOCK {
+                alloc_start = align_up(start + MIN_BLOCK, align);
+            }
+            let alloc_end = alloc_start.saturating_add(size);
+            let tail = end.saturating_sub(alloc_end);
+
+            if alloc_end <= end && (tail == 0 || tail >= MIN_BLOCK) {
+                let next = (*cur).next;
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    (*prev).next = next;
+                }
+                if alloc_start > start {
+                    self.add_free_region(start, alloc_start - start);
+                }
+                if tail > 0 {
+                    self.add_free_region(alloc_end, tail);
+                }
+                return Some(alloc_start);
+            }
+            prev = cur;
+            cur = (*cur).next;
+        }
+        None
+    }
+
+    /// Map enough new pages at the end of the heap to satisfy `min_bytes`.
+    fn grow(&mut self, min_bytes: usize) -> bool {
+        let vmm = match self.vmm {
+            Some(v) => v,
+            None => return false,
+        };
+        let pages = min_bytes.div_ceil(PAGE_SIZE).max(GROW_PAGES);
+        let start = self.heap_end;
+        if start + pages * PAGE_SIZE > HEAP_START + HEAP_MAX_SIZE {
+            return false;
+        }
+        let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+        let mut mapped = 0;
+        while mapped < pages {
+            if vmm.map_zeroed(vmm.kernel_space(), start + mapped * PAGE_SIZE, flags).is_err() {
+                break;
+            }
+            mapped += 1;
+        }
+        if mapped == 0 {
+            return false;
+        }
+        self.heap_end += mapped * PAGE_SIZE;
+        unsafe { self.add_free_region(start, mapped * PAGE_SIZE) };
+        mapped == pages
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        let addr = match self.take(size, align) {
+            Some(a) => a,
+            None => {
+                self.grow(size + align);
+                match self.take(size, align) {
+                    Some(a) => a,
+                    None => return null_mut(),
+                }
+            }
+        };
+        self.in_use += size;
+        self.peak = self.peak.max(self.in_use);
+        self.allocs += 1;
+        addr as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(ptr as usize, size);
+        self.in_use -= size;
+        self.frees += 1;
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut s = HeapStats {
+            heap_size: self.heap_end - HEAP_START,
+            in_use: self.in_use,
+            peak: self.peak,
+            allocs: self.allocs,
+            frees: self.frees,
+            ..HeapStats::default()
+        };
+        let mut cur = self.head;
+        while !cur.is_null() {
+            let size = unsafe { (*cur).size };
+            s.free += size;
+            s.free_blocks += 1;
+            s.largest_free = s.largest_free.max(size);
+            cur = unsafe { (*cur).next };
+        }
+        s
+    }
+}
+
+impl Default for LinkedListHeap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct KernelHeap(Mutex<LinkedListHeap>);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.lock().alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.lock().dealloc(ptr, layout))
+    }
+}
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: KernelHeap = KernelHeap(Mutex::new(LinkedListHeap::new()));
+
+/// Map the initial heap window; later growth happens on demand.
+pub fn init_heap(vmm: &'static VirtualMemoryManager) -> bool {
+    without_interrupts(|| {
+        let mut heap = GLOBAL_ALLOCATOR.0.lock();
+        heap.vmm = Some(vmm);
+        heap.grow(HEAP_INITIAL_SIZE)
+    })
+}
+
+pub fn stats() -> HeapStats {
+    without_interrupts(|| GLOBAL_ALLOCATOR.0.lock().stats())
+}
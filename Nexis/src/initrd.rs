@@ -0,0 +1,240 @@
+// Nexis/src/initrd.rs
+//
+// Initial ramdisk: a ustar or newc cpio archive embedded by build.rs and
+// unpacked into the root filesystem by `fs_init`. Regular files and
+// directories are created with their archived modes; links and device
+// nodes are skipped.
+
+use alloc::string::String;
+
+use crate::fs::{vfs, FileType, FsError};
+
+/// The archive built from Nexis/initrd (or NEXIS_INITRD), or empty.
+pub static INITRD: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd.img"));
+
+const TAR_BLOCK: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitrdError {
+    UnknownFormat,
+    Truncated,
+    BadHeader,
+    BadChecksum,
+    Fs(FsError),
+}
+
+impl From<FsError> for InitrdError {
+    fn from(e: FsError) -> Self {
+        InitrdError::Fs(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+pub struct Entry<'a> {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u16,
+    pub data: &'a [u8],
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct UnpackStats {
+    pub files: usize,
+    pub dirs: usize,
+    pub bytes: usize,
+    pub skipped: usize,
+}
+
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let mut v = 0usize;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => v = v.checked_mul(8)?.checked_add((b - b'0') as usize)?,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(v)
+}
+
+fn parse_hex(field: &[u8]) -> Option<usize> {
+    core::str::from_utf8(field).ok().and_then(|s| usize::from_str_radix(s, 16).ok())
+}
+
+fn c_str(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+fn to_string(bytes: &[u8]) -> Result<String, InitrdError> {
+    core::str::from_utf8(bytes).map(String::from).map_err(|_| InitrdError::BadHeader)
+}
+
+/// Walk a ustar archive, calling `f` for every member.
+fn walk_tar(data: &[u8], f: &mut dyn FnMut(Entry) -> Result<(), InitrdError>) -> Result<(), InitrdError> {
+    let mut off = 0;
+    let mut long_name: Option<String> = None;
+    while off + TAR_BLOCK <= data.len() {
+        let h = &data[off..off + TAR_BLOCK];
+        if h.iter().all(|&b| b == 0) {
+            return Ok(()); // end-of-archive marker
+        }
+        let stored = parse_octal(&h[148..156]).ok_or(InitrdError::BadHeader)?;
+        let sum: usize = h[..148].iter().chain(&[b' '; 8]).chain(&h[156..]).map(|&b| b as usize).sum();
+        if sum != stored {
+            return Err(InitrdError::BadChecksum);
+        }
+        let size = parse_octal(&h[124..136]).ok_or(InitrdError::BadHeader)?;
+        let start = off + TAR_BLOCK;
+        let end = start.checked_add(size).filter(|&e| e <= data.len()).ok_or(InitrdError::Truncated)?;
+        let body = &data[start..end];
+        off = start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+        let typeflag = h[156];
+        if typeflag == b'L' {
+            // GNU long name: the body is the name of the next member
+            long_name = Some(to_string(c_str(body))?);
+            continue;
+        }
+        if typeflag == b'x' || typeflag == b'g' {
+            continue; // pax extended headers
+        }
+        let path = match long_name.take() {
+            Some(name) => name,
+            None => {
+                let name = c_str(&h[0..100]);
+                let prefix = c_str(&h[345..500]);
+                let mut p = String::new();
+                if &h[257..262] == b"ustar" && !prefix.is_empty() {
+                    p.push_str(&to_string(prefix)?);
+                    p.push('/');
+                }
+                p.push_str(&to_string(name)?);
+                p
+            }
+        };
+        let kind = match typeflag {
+            b'0' | 0 | b'7' => EntryKind::File,
+            b'5' => EntryKind::Dir,
+            b'2' => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        let mode = parse_octal(&h[100..108]).ok_or(InitrdError::BadHeader)? as u16 & 0o7777;
+        f(Entry { path, kind, mode, data: body })?;
+    }
+    Err(InitrdError::Truncated)
+}
+
+/// Walk a newc ("070701"/"070702") cpio archive.
+fn walk_cpio(data: &[u8], f: &mut dyn FnMut(Entry) -> Result<(), InitrdError>) -> Result<(), InitrdError> {
+    const HDR: usize = 110;
+    let align4 = |n: usize| (n + 3) & !3;
+    let mut off = 0;
+    while off + HDR <= data.len() {
+        let h = &data[off..off + HDR];
+        if &h[..6] != b"070701" && &h[..6] != b"070702" {
+            return Err(InitrdError::BadHeader);
+        }
+        let field = |i: usize| parse_hex(&h[6 + i * 8..14 + i * 8]).ok_or(InitrdError::BadHeader);
+        let mode = field(1)?;
+        let size = field(6)?;
+        let namesize = field(11)?;
+
+        let name_end = (off + HDR).checked_add(namesize).filter(|&e| e <= data.len());
+        let name_end = name_end.ok_or(InitrdError::Truncated)?;
+        let name = to_string(c_str(&data[off + HDR..name_end]))?;
+        let start = align4(name_end);
+        let end = start.checked_add(size).filter(|&e| e <= data.len()).ok_or(InitrdError::Truncated)?;
+        off = align4(end);
+
+        if name == "TRAILER!!!" {
+            return Ok(());
+        }
+        let kind = match mode & 0o170000 {
+            0o100000 => EntryKind::File,
+            0o040000 => EntryKind::Dir,
+            0o120000 => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        f(Entry { path: name, kind, mode: (mode & 0o7777) as u16, data: &data[start..end] })?;
+    }
+    Err(InitrdError::Truncated)
+}
+
+/// Call `f` for every member of `archive`, whichever format it is in.
+pub fn walk(archive: &[u8], f: &mut dyn FnMut(Entry) -> Result<(), InitrdError>) -> Result<(), InitrdError> {
+    if archive.starts_with(b"0707") {
+        walk_cpio(archive, f)
+    } else if archive.len() >= TAR_BLOCK && &archive[257..262] == b"ustar" {
+        walk_tar(archive, f)
+    } else {
+        Err(InitrdError::UnknownFormat)
+    }
+}
+
+/// Create every missing directory on the way to `path`.
+fn make_parents(path: &str) -> Result<(), InitrdError> {
+    for (i, _) in path.match_indices('/').filter(|&(i, _)| i > 0) {
+        match vfs::create(&path[..i], FileType::Dir, 0o755) {
+            Ok(_) | Err(FsError::Exists) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Unpack `archive` below `/`. Existing files are overwritten.
+pub fn unpack(archive: &[u8]) -> Result<UnpackStats, InitrdError> {
+    let mut stats = UnpackStats::default();
+    walk(archive, &mut |e| {
+        // archive paths are relative ("./etc/motd", "etc/motd")
+        let rel = e.path.trim_start_matches("./").trim_matches('/');
+        if rel.is_empty() || rel == "." {
+            return Ok(());
+        }
+        let mut path = String::from("/");
+        path.push_str(rel);
+        make_parents(&path)?;
+        match e.kind {
+            EntryKind::Dir => {
+                match vfs::create(&path, FileType::Dir, e.mode) {
+                    Ok(_) | Err(FsError::Exists) => {}
+                    Err(err) => return Err(err.into()),
+                }
+                stats.dirs += 1;
+            }
+            EntryKind::File => {
+                if vfs::resolve(&path).is_err() {
+                    vfs::create(&path, FileType::File, e.mode)?;
+                }
+                vfs::write_file(&path, e.data, false)?;
+                stats.files += 1;
+                stats.bytes += e.data.len();
+            }
+            EntryKind::Symlink | EntryKind::Other => stats.skipped += 1,
+        }
+        Ok(())
+    })?;
+    Ok(stats)
+}
+
+/// Unpack the embedded initrd, if there is one.
+pub fn load() {
+    if INITRD.is_empty() {
+        return;
+    }
+    match unpack(INITRD) {
+        Ok(s) => crate::vga::vprintln!(
+            "initrd: {} files, {} directories, {} bytes ({} skipped)",
+            s.files, s.dirs, s.bytes, s.skipped
+        ),
+        Err(e) => crate::vga::vprintln!("initrd: unpack failed: {:?}", e),
+    }
+}
@@ -0,0 +1,145 @@
+// Nexis/src/interrupts.rs
+#![no_std]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::kb::Kb;
+
+pub const PIC1_COMMAND: u16 = 0x20;
+pub const PIC1_DATA: u16 = 0x21;
+pub const PIC2_COMMAND: u16 = 0xA0;
+pub const PIC2_DATA: u16 = 0xA1;
+
+/// IRQ lines with a handler, and the name `/proc/interrupts` shows.
+const IRQ_NAMES: [(u8, &str); 4] = [(0, "timer"), (1, "keyboard"), (14, "ata0"), (15, "ata1")];
+
+static IRQ_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+
+lazy_static! {
+    static ref IDT: Mutex<Option<InterruptDescriptorTable>> = Mutex::new(None);
+}
+
+pub fn init_idt() {
+    let mut idt = InterruptDescriptorTable::new();
+    crate::exceptions::install(&mut idt); // vectors 0..32
+    idt[32].set_handler_fn(timer_interrupt); // PIT
+    idt[33].set_handler_fn(keyboard_interrupt); // keyboard
+    idt[46].set_handler_fn(ata_primary_interrupt); // IRQ14
+    idt[47].set_handler_fn(ata_secondary_interrupt); // IRQ15
+    crate::syscall_entry::install(&mut idt); // int 0x80
+    *IDT.lock() = Some(idt);
+    if let Some(ref i) = *IDT.lock() {
+        unsafe { i.load_unsafe(); } // lives in a static for the kernel's lifetime
+    }
+}
+
+pub fn remap_pic() {
+    unsafe {
+        let mut a1 = Port::<u8>::new(PIC1_DATA);
+        let mut a2 = Port::<u8>::new(PIC2_DATA);
+        let mask1 = a1.read();
+        let mask2 = a2.read();
+
+        let mut cmd1 = Port::<u8>::new(PIC1_COMMAND);
+        let mut cmd2 = Port::<u8>::new(PIC2_COMMAND);
+
+        cmd1.write(0x11);
+        cmd2.write(0x11);
+
+        a1.write(0x20);
+        a2.write(0x28);
+
+        a1.write(4);
+        a2.write(2);
+
+        a1.write(0x01);
+        a2.write(0x01);
+
+        a1.write(mask1);
+        a2.write(mask2);
+    }
+}
+
+/// Allow an IRQ line (0..16) through the PICs.
+pub fn unmask_irq(irq: u8) {
+    unsafe {
+        if irq < 8 {
+            let mut p = Port::<u8>::new(PIC1_DATA);
+            let m = p.read();
+            p.write(m & !(1 << irq));
+        } else {
+            let mut p = Port::<u8>::new(PIC2_DATA);
+            let m = p.read();
+            p.write(m & !(1 << (irq - 8)));
+            // the slave is chained through IRQ2
+            let mut p1 = Port::<u8>::new(PIC1_DATA);
+            let m1 = p1.read();
+            p1.write(m1 & !(1 << 2));
+        }
+    }
+}
+
+pub fn enable_interrupts() {
+    unsafe { interrupts::enable(); }
+}
+
+/// Every handled IRQ line with its name and how often it has fired.
+pub fn irq_stats() -> impl Iterator<Item = (u8, &'static str, u64)> {
+    IRQ_NAMES
+        .iter()
+        .map(|&(irq, name)| (irq, name, IRQ_COUNTS[irq as usize].load(Ordering::Relaxed)))
+}
+
+fn count_irq(irq: u8) {
+    IRQ_COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+fn send_eoi(irq: u8) {
+    unsafe {
+        let mut cmd = Port::<u8>::new(PIC1_COMMAND);
+        if irq >= 8 {
+            let mut s = Port::<u8>::new(PIC2_COMMAND);
+            s.write(0x20);
+        }
+        cmd.write(0x20);
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt(stack_frame: InterruptStackFrame) {
+    count_irq(0);
+    let now = crate::pit::tick();
+    crate::waitqueue::expire_timers(now);
+    // EOI first: we may switch away below and only return here much later.
+    send_eoi(0);
+    let from_user = stack_frame.code_segment & 3 == 3;
+    crate::scheduler::timer_tick(from_user);
+}
+
+extern "x86-interrupt" fn keyboard_interrupt(_stack_frame: InterruptStackFrame) {
+    count_irq(1);
+    unsafe {
+        let mut port = Port::<u8>::new(0x60);
+        let scancode: u8 = port.read();
+        Kb::push_scancode(scancode);
+    }
+    send_eoi(1);
+    // let the input task run now rather than at the next tick
+    crate::scheduler::preempt_if_needed();
+}
+
+extern "x86-interrupt" fn ata_primary_interrupt(_stack_frame: InterruptStackFrame) {
+    count_irq(14);
+    crate::ata::handle_irq(0);
+    send_eoi(14);
+}
+
+extern "x86-interrupt" fn ata_secondary_interrupt(_stack_frame: InterruptStackFrame) {
+    count_irq(15);
+    crate::ata::handle_irq(1);
+    send_eoi(15);
+}
@@ -1,7 +1,10 @@
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 use lazy_static::lazy_static;
 use pc_keyboard::{Keyboard, layouts, ScancodeSet1, DecodedKey, HandleControl, KeyCode};
-use x86_64::instructions::hlt;
+use x86_64::instructions::interrupts;
+
+use crate::waitqueue::WaitQueue;
 
 pub struct XorShift64 { state: u64 }
 impl XorShift64 {
@@ -23,98 +26,164 @@ impl XorShift64 {
 
 const BUF_SIZE: usize = 1024;
 
+/// Real-time priority of the input task: above every fair task, so typing
+/// stays responsive under load.
+const INPUT_TASK_PRIORITY: u8 = 50;
+
+/// Tasks waiting for a scancode.
+static INPUT: WaitQueue = WaitQueue::new();
+/// Tasks waiting for a decoded key.
+static KEYS: WaitQueue = WaitQueue::new();
+/// Held by the task reading a line, so two readers' keystrokes don't mix.
+static LINE_LOCK: Mutex<()> = Mutex::new(());
+/// Tasks waiting for `LINE_LOCK`.
+static LINE_READERS: WaitQueue = WaitQueue::new();
+/// Whether the input task is decoding scancodes into `KEY_QUEUE`; until
+/// then readers decode for themselves.
+static INPUT_TASK: AtomicBool = AtomicBool::new(false);
+
+type Decoder = Keyboard<layouts::Us104Key, ScancodeSet1, HandleControl>;
+
 lazy_static! {
-    static ref SCANCODE_QUEUE: Mutex<ScancodeQueue> = Mutex::new(ScancodeQueue::new());
+    static ref SCANCODE_QUEUE: Mutex<Ring<u8>> = Mutex::new(Ring::new(0));
+    static ref KEY_QUEUE: Mutex<Ring<Key>> = Mutex::new(Ring::new(Key::Enter));
+    static ref DECODER: Mutex<Decoder> = Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1));
 }
 
-struct ScancodeQueue {
-    buf: [u8; BUF_SIZE],
+/// A key press the line editor cares about.
+#[derive(Clone, Copy)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+}
+
+/// Fixed-size FIFO; pushing onto a full ring drops the new entry.
+struct Ring<T: Copy> {
+    buf: [T; BUF_SIZE],
     head: usize,
     tail: usize,
 }
-impl ScancodeQueue {
-    const fn new() -> Self {
-        Self { buf: [0; BUF_SIZE], head: 0, tail: 0 }
+impl<T: Copy> Ring<T> {
+    const fn new(fill: T) -> Self {
+        Self { buf: [fill; BUF_SIZE], head: 0, tail: 0 }
     }
-    fn push(&mut self, sc: u8) {
+    fn push(&mut self, v: T) {
         let next = (self.head + 1) % BUF_SIZE;
         if next != self.tail {
-            self.buf[self.head] = sc;
+            self.buf[self.head] = v;
             self.head = next;
         }
     }
-    fn pop(&mut self) -> Option<u8> {
+    fn pop(&mut self) -> Option<T> {
         if self.tail == self.head { return None; }
-        let sc = self.buf[self.tail];
+        let v = self.buf[self.tail];
         self.tail = (self.tail + 1) % BUF_SIZE;
-        Some(sc)
+        Some(v)
+    }
+}
+
+/// Feed one scancode to the decoder; `Some` once it completes a key.
+fn decode(sc: u8) -> Option<Key> {
+    let mut keyboard = DECODER.lock();
+    let event = keyboard.add_byte(sc).ok()??;
+    match keyboard.process_keyevent(event)? {
+        DecodedKey::Unicode(ch) => Some(Key::Char(ch)),
+        DecodedKey::RawKey(KeyCode::Enter) => Some(Key::Enter),
+        DecodedKey::RawKey(KeyCode::Backspace) => Some(Key::Backspace),
+        DecodedKey::RawKey(_) => None,
     }
 }
 
+/// Decodes scancodes as they arrive. Runs FIFO, so a key press is handled
+/// within the interrupt that delivered it even while user code hogs the CPU.
+extern "C" fn input_task() {
+    loop {
+        if let Some(key) = decode(Kb::read_scancode_blocking()) {
+            interrupts::without_interrupts(|| KEY_QUEUE.lock().push(key));
+            KEYS.wake_all();
+        }
+    }
+}
+
+/// Length of `buf[..len]` without its last UTF-8 character.
+fn pop_char(buf: &[u8], len: usize) -> usize {
+    let mut n = len.saturating_sub(1);
+    while n > 0 && buf[n] & 0xC0 == 0x80 {
+        n -= 1;
+    }
+    n
+}
+
 pub struct Kb;
 impl Kb {
     pub fn init() {
         // nothing for now; PIC & IDT set up elsewhere
     }
+    /// Start the input task. Needs the scheduler.
+    pub fn start_input_task() -> bool {
+        let started = crate::scheduler::spawn_fifo(input_task, 4, "kbd", INPUT_TASK_PRIORITY).is_some();
+        INPUT_TASK.store(started, Ordering::SeqCst);
+        started
+    }
+    /// Called from the keyboard interrupt.
     pub fn push_scancode(sc: u8) {
         SCANCODE_QUEUE.lock().push(sc);
+        INPUT.wake_all();
     }
     fn read_scancode_blocking() -> u8 {
+        // the queue is shared with the interrupt handler
+        INPUT.wait_until(|| interrupts::without_interrupts(|| SCANCODE_QUEUE.lock().pop()))
+    }
+    fn read_key() -> Key {
+        if INPUT_TASK.load(Ordering::SeqCst) {
+            return KEYS.wait_until(|| interrupts::without_interrupts(|| KEY_QUEUE.lock().pop()));
+        }
         loop {
-            if let Some(sc) = SCANCODE_QUEUE.lock().pop() {
-                return sc;
+            if let Some(key) = decode(Self::read_scancode_blocking()) {
+                return key;
             }
-            hlt();
         }
     }
 
-    pub fn read_line_irq() -> &'static str {
-        static mut LINE_BUF: [u8; 256] = [0; 256];
-        let mut len = 0usize;
-
-        // FIXED: pass 2 args, not 3
-        let mut keyboard: Keyboard<layouts::Us104Key, ScancodeSet1, HandleControl> =
-            Keyboard::new(layouts::Us104Key, ScancodeSet1);
+    /// Read one line from the keyboard into `buf`, echoing it, and return
+    /// its length in bytes (UTF-8, without the newline). Input past the
+    /// end of `buf` is dropped. One reader edits a line at a time; others
+    /// wait their turn.
+    pub fn read_line(buf: &mut [u8]) -> usize {
+        let reader = LINE_READERS.wait_until(|| LINE_LOCK.try_lock());
+        let len = Self::edit_line(buf);
+        drop(reader);
+        LINE_READERS.wake_all();
+        len
+    }
 
+    fn edit_line(buf: &mut [u8]) -> usize {
+        let mut len = 0usize;
         loop {
-            let sc = Self::read_scancode_blocking();
-            if let Ok(Some(event)) = keyboard.add_byte(sc) {
-                if let Some(key) = keyboard.process_keyevent(event) {
-                    match key {
-                        DecodedKey::Unicode(ch) => {
-                            crate::vga::VGA_WRITER.lock().put_char(ch);
-                            crate::vga::sprint!("{}", ch);
-                            if ch == '\r' || ch == '\n' {
-                                crate::vga::vprintln!("");
-                                break;
-                            } else if ch == '\x08' {
-                                if len > 0 { len -= 1; }
-                            } else if len < 255 {
-                                unsafe { LINE_BUF[len] = ch as u8; }
-                                len += 1;
-                            }
-                        }
-                        DecodedKey::RawKey(k) => {
-                            match k {
-                                KeyCode::Enter => {
-                                    crate::vga::vprintln!("");
-                                    break;
-                                }
-                                KeyCode::Backspace => {
-                                    if len > 0 { len -= 1; }
-                                    crate::vga::sprint!("\x08 \x08");
-                                }
-                                _ => {}
-                            }
-                        }
+            match Self::read_key() {
+                Key::Char(ch) => {
+                    crate::vga::VGA_WRITER.lock().put_char(ch);
+                    crate::vga::sprint!("{}", ch);
+                    if ch == '\r' || ch == '\n' {
+                        crate::vga::vprintln!("");
+                        break;
+                    } else if ch == '\x08' {
+                        len = pop_char(buf, len);
+                    } else if len + ch.len_utf8() <= buf.len() {
+                        len += ch.encode_utf8(&mut buf[len..]).len();
                     }
                 }
+                Key::Enter => {
+                    crate::vga::vprintln!("");
+                    break;
+                }
+                Key::Backspace => {
+                    len = pop_char(buf, len);
+                    crate::vga::sprint!("\x08 \x08");
+                }
             }
         }
-
-        unsafe {
-            LINE_BUF[len] = 0;
-            core::str::from_utf8_unchecked(&LINE_BUF[..len])
-        }
+        len
     }
 }
@@ -1,24 +1,40 @@
 #![no_std]
+#![feature(abi_x86_interrupt)]
 
-pub mod alloc;
+extern crate alloc;
+
+pub mod heap;
 pub mod context;
+pub mod gdt;
+pub mod exceptions;
 pub mod interrupts;
 pub mod pit;
+pub mod power;
+pub mod cmdline;
 pub mod kb;
 pub mod vga;
 pub mod memory;
+pub mod vmm;
 pub mod task;
 pub mod scheduler;
+pub mod waitqueue;
 pub mod process;
+pub mod errno;
+pub mod uaccess;
 pub mod syscall;
-pub mod syscall_dispatch;
+pub mod syscall_entry;
 pub mod fs;
+pub mod fd;
+pub mod pipe;
+pub mod initrd;
+pub mod pci;
+pub mod block;
+pub mod chardev;
+pub mod virtio_blk;
+pub mod ata;
+pub mod partition;
+pub mod elf;
 pub mod userland;
 
-#[alloc_error_handler]
-fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
-    panic!("Allocation error: {:?}", layout);
-}
-
 // re-export PMM if needed
 pub use memory::PhysicalMemoryManager;
\ No newline at end of file
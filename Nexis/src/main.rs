@@ -1,33 +1,66 @@
 #![no_std]
 #![no_main]
+#![feature(abi_x86_interrupt)]
 
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::boot_info::MemoryRegion;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
-pub mod alloc;
+pub mod heap;
 pub mod context;
+pub mod gdt;
+pub mod exceptions;
 pub mod interrupts;
 pub mod pit;
+pub mod power;
+pub mod cmdline;
 pub mod kb;
 pub mod vga;
 pub mod memory;
+pub mod vmm;
 pub mod task;
 pub mod scheduler;
+pub mod waitqueue;
 pub mod process;
+pub mod errno;
+pub mod uaccess;
 pub mod syscall;
-pub mod syscall_dispatch;
+pub mod syscall_entry;
 pub mod fs;
+pub mod fd;
+pub mod pipe;
+pub mod initrd;
+pub mod pci;
+pub mod block;
+pub mod chardev;
+pub mod virtio_blk;
+pub mod ata;
+pub mod partition;
+pub mod elf;
 pub mod userland;
 
 use crate::vga::VGA_WRITER;
 use crate::kb::Kb;
 use crate::memory::{PhysicalMemoryManager, FRAME_SIZE};
+use crate::vmm::VirtualMemoryManager;
 
 entry_point!(kernel_main);
 
 static mut PMM: PhysicalMemoryManager = PhysicalMemoryManager::new_uninit();
+static mut VMM: VirtualMemoryManager = VirtualMemoryManager::new_uninit();
+
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    let phys_offset = boot_info
+        .physical_memory_offset
+        .into_option()
+        .expect("bootloader did not map physical memory");
+    memory::set_phys_mem_offset(phys_offset as usize);
 
-fn kernel_main(_boot_info: &'static BootInfo) -> ! {
+    gdt::init();
+    syscall_entry::init();
     interrupts::init_idt();
     interrupts::remap_pic();
     interrupts::enable_interrupts();
@@ -39,23 +72,56 @@ fn kernel_main(_boot_info: &'static BootInfo) -> ! {
         vw.write_str("Init sequence starting...\n\n");
     }
 
-    unsafe { pmm_setup_linker(); }
+    unsafe {
+        pmm_setup(&boot_info.memory_regions);
+        VMM.init(&PMM).expect("VMM: cannot allocate kernel page tables");
+        vmm::set_kernel_vmm(&*core::ptr::addr_of!(VMM));
+        if !heap::init_heap(&VMM) {
+            panic!("heap: cannot map initial heap");
+        }
+    }
 
     Kb::init();
-    pit::init();
+    cmdline::init();
+    let hz = cmdline::get_u32("hz").unwrap_or(pit::DEFAULT_HZ);
+    pit::init(hz);
+    interrupts::unmask_irq(0);
+    crate::vga::vprintln!("PIT: {} Hz timer", pit::hz());
 
+    chardev::init();
+    virtio_blk::init();
+    ata::init();
+    partition::scan_all();
     crate::fs::fs_init();
 
     extern "C" fn shell_task() {
         shell_loop();
     }
 
-    unsafe {
-        if let Some(_) = scheduler::spawn(shell_task, &PMM, 16) {
+    unsafe { scheduler::init(&VMM); }
+    if !Kb::start_input_task() {
+        crate::vga::vprintln!("kbd: no input task, readers decode keys themselves");
+    }
+    // `init=none` keeps the built-in shell, as does an init that won't load
+    let init = cmdline::get("init").unwrap_or(userland::DEFAULT_INIT);
+    let init_started = init != "none"
+        && match userland::spawn_init(init) {
+            Ok(pid) => {
+                crate::vga::vprintln!("init: started {} as pid {}", init, pid);
+                true
+            }
+            Err(e) => {
+                crate::vga::vprintln!("init: cannot run {} (errno {}), using the kernel shell", init, e);
+                false
+            }
+        };
+    if !init_started {
+        if let Some(_) = scheduler::spawn_named(shell_task, 16, "shell") {
             crate::vga::vprintln!("Shell task spawned");
         } else {
             crate::vga::vprintln!("Shell spawn failed");
         }
+        userland::spawn_demo();
     }
 
     scheduler::schedule_loop()
@@ -66,10 +132,12 @@ fn shell_loop() -> ! {
     let mut rng = crate::kb::XorShift64::new(0xabcdef123456789u64);
 
     loop {
-        crate::vga::vprint!("ironveil@nexis:~$ ");
-        crate::vga::sprint!("ironveil@nexis:~$ ");
-        let line = Kb::read_line_irq();
-        let cmd = line.trim();
+        let cwd = crate::fs::vfs::cwd();
+        crate::vga::vprint!("ironveil@nexis:{}$ ", cwd);
+        crate::vga::sprint!("ironveil@nexis:{}$ ", cwd);
+        let mut buf = [0u8; 256];
+        let n = Kb::read_line(&mut buf);
+        let cmd = core::str::from_utf8(&buf[..n]).unwrap_or("").trim();
 
         match cmd {
             "help" => {
@@ -79,9 +147,25 @@ fn shell_loop() -> ! {
                 crate::vga::vprintln!("  genpass    - generate password");
                 crate::vga::vprintln!("  ip         - fake IPv4");
                 crate::vga::vprintln!("  mac        - fake MAC");
-                crate::vga::vprintln!("  reboot     - halt");
-                crate::vga::vprintln!("  fs ls      - list files");
+                crate::vga::vprintln!("  heap       - kernel heap statistics");
+                crate::vga::vprintln!("  userland   - run the embedded userland demo");
+                crate::vga::vprintln!("  syscalls   - syscall table and call counts");
+                crate::vga::vprintln!("  ps         - tasks with scheduling class and CPU time");
+                crate::vga::vprintln!("  lspci      - list PCI devices");
+                crate::vga::vprintln!("  lsblk      - list block devices and cache usage");
+                crate::vga::vprintln!("  sync       - write cached disk blocks back");
+                crate::vga::vprintln!("  mount [<dev> <dir> [type]] - list or add mounts");
+                crate::vga::vprintln!("  umount <d> - unmount a filesystem");
+                crate::vga::vprintln!("  reboot     - sync disks and restart");
+                crate::vga::vprintln!("  fs ls [d]  - list a directory");
                 crate::vga::vprintln!("  fs cat <f> - print file contents");
+                crate::vga::vprintln!("  cd <d>     - change directory");
+                crate::vga::vprintln!("  touch <f>  - create an empty file");
+                crate::vga::vprintln!("  echo <t> > <f> / >> <f> - write / append text");
+                crate::vga::vprintln!("  mkdir <d>  - create a directory");
+                crate::vga::vprintln!("  rm <p>     - remove a file or empty directory");
+                crate::vga::vprintln!("  mv <a> <b> - rename or move");
+                crate::vga::vprintln!("  pwd        - print working directory");
             }
             "clear" | "cls" => {
                 VGA_WRITER.lock().clear_screen();
@@ -108,12 +192,156 @@ fn shell_loop() -> ! {
                 crate::vga::vprintln!("Fake MAC: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
                     parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]);
             }
-            "reboot" => {
-                crate::vga::vprintln!("System halting. Restart QEMU to continue.");
-                loop { core::hint::spin_loop(); }
+            "heap" => {
+                let s = crate::heap::stats();
+                crate::vga::vprintln!("Heap size:     {} KiB", s.heap_size / 1024);
+                crate::vga::vprintln!("In use:        {} bytes (peak {})", s.in_use, s.peak);
+                crate::vga::vprintln!("Free:          {} bytes in {} blocks (largest {})",
+                    s.free, s.free_blocks, s.largest_free);
+                crate::vga::vprintln!("Fragmentation: {}%", s.fragmentation());
+                crate::vga::vprintln!("Allocs/frees:  {}/{}", s.allocs, s.frees);
+            }
+            "userland" => {
+                crate::userland::spawn_demo();
+            }
+            "syscalls" => {
+                crate::vga::vprintln!(" NR  NAME         ARGS  CALLS");
+                for (nr, call, count) in crate::syscall::stats() {
+                    crate::vga::vprintln!("{:>3}  {:<12} {:>4}  {}", nr, call.name, call.argc, count);
+                }
+                crate::vga::vprintln!("Unknown: {}", crate::syscall::unknown_calls());
+            }
+            "ps" => {
+                let procs: Vec<_> = crate::process::PROC_TABLE.lock().procs.iter()
+                    .filter(|p| p.is_live()).map(|p| (p.slot, p.pid)).collect();
+                crate::vga::vprintln!("  PID SLOT NAME             STATE    CLASS   NI      USER       SYS");
+                for t in crate::scheduler::tasks() {
+                    let pid = procs.iter().find(|&&(slot, _)| slot == t.slot).map(|&(_, pid)| pid);
+                    let class = match t.policy {
+                        crate::task::SchedPolicy::Fifo(prio) => alloc::format!("fifo/{}", prio),
+                        fair => alloc::string::String::from(fair.name()),
+                    };
+                    let ms = |ticks| crate::pit::ticks_to_us(ticks) / 1000;
+                    crate::vga::vprintln!("{:>5} {:>4} {:<16} {:<8} {:<7} {:>3} {:>7} ms {:>6} ms",
+                        pid.map_or(alloc::string::String::from("-"), |p| alloc::format!("{}", p)),
+                        t.slot, t.name, alloc::format!("{:?}", t.state), class, t.nice,
+                        ms(t.times.user), ms(t.times.system));
+                }
+            }
+            "lspci" => {
+                for d in crate::pci::scan() {
+                    crate::vga::vprintln!("{:02x}:{:02x}.{} {:04x}:{:04x} {}",
+                        d.bus, d.device, d.function, d.vendor_id, d.device_id,
+                        crate::pci::class_name(d.class, d.subclass));
+                }
+            }
+            "lsblk" => {
+                crate::vga::vprintln!("NAME      SIZE  CACHED  DIRTY  HITS/MISSES");
+                for disk in crate::block::disks() {
+                    let s = disk.cache_stats();
+                    crate::vga::vprintln!("{:<5} {:>6} MiB {:>6} {:>6}  {}/{}{}",
+                        disk.name(), disk.size() / (1024 * 1024), s.cached, s.dirty,
+                        s.hits, s.misses, if disk.read_only() { "  ro" } else { "" });
+                }
+            }
+            "sync" => {
+                for (path, e) in crate::fs::vfs::sync_all() {
+                    crate::vga::vprintln!("sync: {}: {}", path, e.as_str());
+                }
+                for (name, e) in crate::block::sync_all() {
+                    crate::vga::vprintln!("sync: {}: {}", name, e.as_str());
+                }
+            }
+            "mount" => {
+                for (path, fstype) in crate::fs::vfs::mounts() {
+                    crate::vga::vprintln!("{} on {}", fstype, path);
+                }
+            }
+            x if x.starts_with("mount ") => {
+                let args: Vec<&str> = x[6..].split_whitespace().collect();
+                if args.len() < 2 || args.len() > 3 {
+                    crate::vga::vprintln!("Usage: mount <dev> <dir> [type]");
+                } else {
+                    match crate::fs::mount_device(args[0], args[1], args.get(2).copied()) {
+                        Ok(fstype) => crate::vga::vprintln!("{} mounted on {} ({})", args[0], args[1], fstype),
+                        Err(e) => crate::vga::vprintln!("mount: {}: {}", args[0], e.as_str()),
+                    }
+                }
+            }
+            x if x.starts_with("umount ") => {
+                let path = x[7..].trim();
+                if let Err(e) = crate::fs::vfs::unmount(path) {
+                    crate::vga::vprintln!("umount: {}: {}", path, e.as_str());
+                }
+            }
+            "reboot" => crate::power::reboot(),
+            "pwd" => {
+                crate::vga::vprintln!("{}", crate::fs::vfs::cwd());
+            }
+            x if x == "cd" || x.starts_with("cd ") => {
+                let dir = x[2..].trim();
+                let dir = if dir.is_empty() { "/" } else { dir };
+                if let Err(e) = crate::fs::vfs::chdir(dir) {
+                    crate::vga::vprintln!("cd: {}: {}", dir, e.as_str());
+                }
+            }
+            x if x.starts_with("touch ") => {
+                let path = x[6..].trim();
+                match crate::fs::vfs::resolve(path) {
+                    Ok(_) => {}
+                    Err(_) => {
+                        if let Err(e) = crate::fs::vfs::create(path, crate::fs::FileType::File, 0o644) {
+                            crate::vga::vprintln!("touch: {}: {}", path, e.as_str());
+                        }
+                    }
+                }
             }
-            x if x.starts_with("fs ls") => {
-                crate::fs::list_files();
+            x if x == "echo" || x.starts_with("echo ") => {
+                let rest = &x[4..];
+                // `echo text > file` truncates, `echo text >> file` appends
+                let (text, target) = match rest.find('>') {
+                    Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+                    None => (rest, None),
+                };
+                let text = text.trim();
+                match target {
+                    None => crate::vga::vprintln!("{}", text),
+                    Some(t) => {
+                        let (append, path) = match t.strip_prefix('>') {
+                            Some(p) => (true, p.trim()),
+                            None => (false, t.trim()),
+                        };
+                        let mut line = alloc::string::String::from(text);
+                        line.push('\n');
+                        if let Err(e) = crate::fs::vfs::write_file(path, line.as_bytes(), append) {
+                            crate::vga::vprintln!("echo: {}: {}", path, e.as_str());
+                        }
+                    }
+                }
+            }
+            x if x.starts_with("mkdir ") => {
+                let path = x[6..].trim();
+                if let Err(e) = crate::fs::vfs::mkdir(path) {
+                    crate::vga::vprintln!("mkdir: {}: {}", path, e.as_str());
+                }
+            }
+            x if x.starts_with("rm ") => {
+                let path = x[3..].trim();
+                if let Err(e) = crate::fs::vfs::remove(path) {
+                    crate::vga::vprintln!("rm: {}: {}", path, e.as_str());
+                }
+            }
+            x if x.starts_with("mv ") => {
+                let args: Vec<&str> = x[3..].split_whitespace().collect();
+                if args.len() != 2 {
+                    crate::vga::vprintln!("Usage: mv <from> <to>");
+                } else if let Err(e) = crate::fs::vfs::rename(args[0], args[1]) {
+                    crate::vga::vprintln!("mv: {}", e.as_str());
+                }
+            }
+            x if x == "fs ls" || x.starts_with("fs ls ") => {
+                let dir = x[5..].trim();
+                crate::fs::list_files(if dir.is_empty() { "." } else { dir });
             }
             x if x.starts_with("fs cat ") => {
                 let parts: Vec<&str> = x.splitn(3, ' ').collect();
@@ -137,46 +365,22 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         crate::vga::vprintln!("panic: {}", info);
     }
-    loop { core::hint::spin_loop(); }
+    crate::power::halt()
 }
 
-unsafe fn pmm_setup_linker() {
-    extern "C" {
-        static __kernel_start: u8;
-        static __kernel_end: u8;
+unsafe fn pmm_setup(regions: &[MemoryRegion]) {
+    for r in regions {
+        crate::vga::vprintln!(
+            "  mem {:#012x}-{:#012x} {:>6} KiB {}",
+            r.start, r.end, (r.end - r.start) / 1024, memory::region_kind_name(r.kind)
+        );
     }
-
-    let kstart = &__kernel_start as *const _ as usize;
-    let kend = &__kernel_end as *const _ as usize;
-    let kernel_end_page = ((kend + FRAME_SIZE - 1) / FRAME_SIZE) * FRAME_SIZE;
-    let pool_start = if kernel_end_page < 0x0010_0000 { 0x0010_0000 } else { kernel_end_page };
-    let max_pool_size = 128 * 1024 * 1024usize;
-    let pool_end = pool_start.saturating_add(max_pool_size);
-    let pool_size = if pool_end > pool_start { pool_end - pool_start } else { 0usize };
-    if pool_size < FRAME_SIZE { return; }
-
-    let total_frames = pool_size / FRAME_SIZE;
-    let bitmap_bytes_needed = (total_frames + 7) / 8;
-    let bitmap_frames = (bitmap_bytes_needed + FRAME_SIZE - 1) / FRAME_SIZE;
-    let bitmap_phys = pool_start;
-    let bitmap_bytes_reserved = bitmap_frames * FRAME_SIZE;
-
-    let base_frame_addr = pool_start + bitmap_bytes_reserved;
-    let base_frame = base_frame_addr / FRAME_SIZE;
-    let frames_managed = (pool_size - bitmap_bytes_reserved) / FRAME_SIZE;
-
-    PMM.init(bitmap_phys as *mut u8, bitmap_bytes_reserved, base_frame, frames_managed);
-
-    for i in 0..bitmap_frames {
-        let pa = bitmap_phys + i * FRAME_SIZE;
-        PMM.mark_used(pa);
+    if !PMM.init_from_memory_map(regions) {
+        panic!("PMM: no usable memory reported by bootloader");
     }
-    let kstart_frame = (kstart / FRAME_SIZE) * FRAME_SIZE;
-    let kend_frame = ((kend + FRAME_SIZE - 1) / FRAME_SIZE) * FRAME_SIZE;
-    let mut pa = kstart_frame;
-    while pa < kend_frame {
-        PMM.mark_used(pa);
-        pa += FRAME_SIZE;
-    }
-    crate::vga::vprintln!("PMM ready: {} frames managed", PMM.free_frames());
-}
\ No newline at end of file
+    crate::vga::vprintln!(
+        "PMM ready: {} free frames ({} MiB)",
+        PMM.free_frames(),
+        PMM.free_frames() * FRAME_SIZE / (1024 * 1024)
+    );
+}
@@ -1,10 +1,33 @@
+use bootloader::boot_info::{MemoryRegion, MemoryRegionKind};
 use core::ptr;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Mutex;
+use x86_64::instructions::interrupts;
 
 /// Page/frame size — 4 KiB
 pub const FRAME_SIZE: usize = 4096;
 
+/// Frames below 1 MiB are never handed out (BIOS data, real-mode structures).
+const LOW_MEMORY_LIMIT: usize = 0x0010_0000;
+
+/// Virtual address at which the bootloader maps all of physical memory.
+static PHYS_MEM_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_phys_mem_offset(offset: usize) {
+    PHYS_MEM_OFFSET.store(offset, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn phys_mem_offset() -> usize {
+    PHYS_MEM_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Translate a physical address into the kernel's physical-memory window.
+#[inline]
+pub fn phys_to_virt(phys_addr: usize) -> usize {
+    phys_addr + phys_mem_offset()
+}
+
 /// A physical frame address (aligned to FRAME_SIZE).
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PhysFrame(pub usize);
@@ -28,6 +51,10 @@ pub struct PhysicalMemoryManager {
     lock: Mutex<()>,
 }
 
+// Safety: the bitmap is only modified with `lock` held.
+unsafe impl Send for PhysicalMemoryManager {}
+unsafe impl Sync for PhysicalMemoryManager {}
+
 impl PhysicalMemoryManager {
     pub const fn new_uninit() -> Self {
         Self {
@@ -62,6 +89,70 @@ impl PhysicalMemoryManager {
         self.free_frames.store(total_frames, Ordering::SeqCst);
     }
 
+    /// Initialise from the bootloader memory map.
+    ///
+    /// The bitmap covers every frame between the lowest and highest usable
+    /// address; frames in holes and in non-usable regions stay marked used,
+    /// so only `Usable` memory is ever returned by `alloc_frame`. The bitmap
+    /// itself is carved out of the first usable region large enough for it.
+    ///
+    /// # Safety
+    /// `set_phys_mem_offset` must have been called and the regions must
+    /// describe real memory reachable through the physical-memory window.
+    pub unsafe fn init_from_memory_map(&mut self, regions: &[MemoryRegion]) -> bool {
+        let usable = || {
+            regions
+                .iter()
+                .filter(|r| r.kind == MemoryRegionKind::Usable)
+                .filter_map(usable_range)
+        };
+
+        let lowest = match usable().map(|(s, _)| s).min() {
+            Some(a) => a,
+            None => return false,
+        };
+        let highest = usable().map(|(_, e)| e).max().unwrap_or(lowest);
+
+        let total_frames = (highest - lowest) / FRAME_SIZE;
+        let bitmap_bytes = total_frames.div_ceil(8);
+        let bitmap_frames = bitmap_bytes.div_ceil(FRAME_SIZE);
+        let bitmap_phys = match usable().find(|(s, e)| e - s >= bitmap_frames * FRAME_SIZE) {
+            Some((s, _)) => s,
+            None => return false,
+        };
+
+        let bitmap_ptr = phys_to_virt(bitmap_phys) as *mut u8;
+        ptr::write_bytes(bitmap_ptr, 0xFF, bitmap_bytes); // everything used until proven usable
+
+        self.bitmap = bitmap_ptr;
+        self.bitmap_len = bitmap_frames * FRAME_SIZE;
+        self.base_frame = lowest / FRAME_SIZE;
+        self.total_frames = total_frames;
+        self.free_frames.store(0, Ordering::SeqCst);
+
+        for (start, end) in usable() {
+            let mut pa = start;
+            while pa < end {
+                self.mark_free(pa);
+                pa += FRAME_SIZE;
+            }
+        }
+        for i in 0..bitmap_frames {
+            self.mark_used(bitmap_phys + i * FRAME_SIZE);
+        }
+        true
+    }
+
+    /// Run `f` with `lock` held and interrupts off; frames are also
+    /// allocated with interrupts disabled (heap growth, task spawning),
+    /// which would spin forever on the lock of a preempted task.
+    fn locked<R>(&self, f: impl FnOnce() -> R) -> R {
+        interrupts::without_interrupts(|| {
+            let _g = self.lock.lock();
+            f()
+        })
+    }
+
     #[inline]
     pub fn total_frames(&self) -> usize {
         self.total_frames
@@ -81,8 +172,7 @@ impl PhysicalMemoryManager {
         let byte = idx / 8;
         let bit = idx % 8;
 
-        let _g = self.lock.lock();
-        unsafe {
+        self.locked(|| unsafe {
             let p = self.bitmap.add(byte);
             let old = ptr::read_volatile(p);
             let mask = 1u8 << bit;
@@ -90,9 +180,9 @@ impl PhysicalMemoryManager {
                 return false;
             }
             ptr::write_volatile(p, old | mask);
-        }
-        self.free_frames.fetch_sub(1, Ordering::SeqCst);
-        true
+            self.free_frames.fetch_sub(1, Ordering::SeqCst);
+            true
+        })
     }
 
     pub fn mark_free(&self, phys_addr: usize) -> bool {
@@ -104,8 +194,7 @@ impl PhysicalMemoryManager {
         let byte = idx / 8;
         let bit = idx % 8;
 
-        let _g = self.lock.lock();
-        unsafe {
+        self.locked(|| unsafe {
             let p = self.bitmap.add(byte);
             let old = ptr::read_volatile(p);
             let mask = 1u8 << bit;
@@ -113,13 +202,16 @@ impl PhysicalMemoryManager {
                 return false;
             }
             ptr::write_volatile(p, old & !mask);
-        }
-        self.free_frames.fetch_add(1, Ordering::SeqCst);
-        true
+            self.free_frames.fetch_add(1, Ordering::SeqCst);
+            true
+        })
     }
 
     pub fn alloc_frame(&self) -> Option<PhysFrame> {
-        let _g = self.lock.lock();
+        self.locked(|| self.alloc_frame_locked())
+    }
+
+    fn alloc_frame_locked(&self) -> Option<PhysFrame> {
         let bytes = (self.total_frames + 7) / 8;
 
         for b in 0..bytes {
@@ -143,11 +235,53 @@ impl PhysicalMemoryManager {
         None
     }
 
+    /// Allocate `count` physically contiguous frames (for DMA); returns the
+    /// first. Free them one by one with `free_frame`.
+    pub fn alloc_contiguous(&self, count: usize) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+        self.locked(|| self.alloc_contiguous_locked(count))
+    }
+
+    fn alloc_contiguous_locked(&self, count: usize) -> Option<PhysFrame> {
+        let used = |idx: usize| unsafe {
+            ptr::read_volatile(self.bitmap.add(idx / 8)) & (1u8 << (idx % 8)) != 0
+        };
+        let mut run = 0;
+        for idx in 0..self.total_frames {
+            if used(idx) {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == count {
+                let first = idx + 1 - count;
+                for i in first..=idx {
+                    unsafe {
+                        let p = self.bitmap.add(i / 8);
+                        ptr::write_volatile(p, ptr::read_volatile(p) | (1u8 << (i % 8)));
+                    }
+                }
+                self.free_frames.fetch_sub(count, Ordering::SeqCst);
+                return Some(PhysFrame((self.base_frame + first) * FRAME_SIZE));
+            }
+        }
+        None
+    }
+
     #[inline]
     pub fn free_frame(&self, addr: usize) -> bool {
         self.mark_free(addr)
     }
 
+    /// Position of the frame holding `phys_addr` among the managed
+    /// frames (0..total_frames), for per-frame tables kept elsewhere.
+    pub fn frame_index(&self, phys_addr: usize) -> Option<usize> {
+        let frame = phys_addr / FRAME_SIZE;
+        (frame >= self.base_frame && frame < self.base_frame + self.total_frames).then(|| frame - self.base_frame)
+    }
+
     pub fn is_used(&self, phys_addr: usize) -> bool {
         let frame = phys_addr / FRAME_SIZE;
         if frame < self.base_frame || frame >= self.base_frame + self.total_frames {
@@ -160,3 +294,24 @@ impl PhysicalMemoryManager {
         (cur & (1u8 << bit)) != 0
     }
 }
+
+/// Page-aligned, low-memory-clamped bounds of a usable region.
+fn usable_range(r: &MemoryRegion) -> Option<(usize, usize)> {
+    let start = (r.start as usize).max(LOW_MEMORY_LIMIT);
+    let start = (start + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+    let end = (r.end as usize) & !(FRAME_SIZE - 1);
+    if end > start { Some((start, end)) } else { None }
+}
+
+pub fn region_kind_name(kind: MemoryRegionKind) -> &'static str {
+    match kind {
+        MemoryRegionKind::Usable => "usable",
+        MemoryRegionKind::Bootloader => "bootloader",
+        MemoryRegionKind::UnknownBios(3) => "acpi-reclaimable",
+        MemoryRegionKind::UnknownBios(4) => "acpi-nvs",
+        MemoryRegionKind::UnknownBios(5) => "bad",
+        MemoryRegionKind::UnknownBios(_) => "reserved",
+        MemoryRegionKind::UnknownUefi(_) => "uefi-reserved",
+        _ => "reserved",
+    }
+}
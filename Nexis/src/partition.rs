@@ -0,0 +1,154 @@
+// Nexis/src/partition.rs
+//
+// MBR partition tables. Each primary partition of a registered disk is
+// registered as a block device of its own ("vda1", "hdb2", ...) that maps
+// sector numbers onto its slice of the disk. Extended partitions and GPT
+// (protective type 0xEE) are recognised but not descended into.
+//
+// A partition gets its own buffer cache, separate from the whole disk's,
+// so do not write to a disk and its partitions at the same time.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::block::{self, BlockDevice, BlockResult, Disk, SECTOR_SIZE};
+
+const TABLE_OFFSET: usize = 446;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MbrEntry {
+    pub bootable: bool,
+    pub kind: u8,
+    pub start: u64,
+    pub sectors: u64,
+}
+
+impl MbrEntry {
+    pub fn is_extended(&self) -> bool {
+        matches!(self.kind, 0x05 | 0x0F | 0x85)
+    }
+}
+
+/// Short name for a partition type byte.
+pub fn kind_name(kind: u8) -> &'static str {
+    match kind {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0E => "FAT16",
+        0x0B | 0x0C => "FAT32",
+        0x05 | 0x0F | 0x85 => "extended",
+        0x07 => "NTFS/exFAT",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0xEE => "GPT",
+        _ => "unknown",
+    }
+}
+
+/// A FAT boot sector also ends in 55 AA; tell it apart from an MBR by its
+/// jump instruction and a sane sector size.
+fn looks_like_boot_sector(s: &[u8]) -> bool {
+    let jump = s[0] == 0xEB && s[2] == 0x90 || s[0] == 0xE9;
+    let bps = u16::from_le_bytes([s[11], s[12]]);
+    jump && bps.is_power_of_two() && (512..=4096).contains(&bps)
+}
+
+/// Parse the partition table in `sector0`, checked against a disk of
+/// `disk_sectors`. `None` if there is no valid MBR.
+pub fn parse_mbr(sector0: &[u8], disk_sectors: u64) -> Option<Vec<(usize, MbrEntry)>> {
+    if sector0.len() < SECTOR_SIZE || sector0[510] != 0x55 || sector0[511] != 0xAA {
+        return None;
+    }
+    if looks_like_boot_sector(sector0) {
+        return None;
+    }
+    let mut entries = Vec::new();
+    for i in 0..4 {
+        let e = &sector0[TABLE_OFFSET + i * 16..TABLE_OFFSET + (i + 1) * 16];
+        if e[0] != 0x00 && e[0] != 0x80 {
+            return None; // status byte must be 0 or 0x80
+        }
+        let start = u32::from_le_bytes([e[8], e[9], e[10], e[11]]) as u64;
+        let sectors = u32::from_le_bytes([e[12], e[13], e[14], e[15]]) as u64;
+        if e[4] == 0 || sectors == 0 {
+            continue;
+        }
+        if start == 0 || start + sectors > disk_sectors {
+            return None;
+        }
+        entries.push((i + 1, MbrEntry { bootable: e[0] == 0x80, kind: e[4], start, sectors }));
+    }
+    Some(entries)
+}
+
+/// A window onto part of another block device.
+pub struct Partition {
+    name: String,
+    dev: Arc<dyn BlockDevice>,
+    start: u64,
+    sectors: u64,
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_only(&self) -> bool {
+        self.dev.read_only()
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        block::check_request(self, lba, buf.len())?;
+        self.dev.read_sectors(self.start + lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        block::check_request(self, lba, buf.len())?;
+        self.dev.write_sectors(self.start + lba, buf)
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        self.dev.flush()
+    }
+}
+
+/// Register the primary partitions of `disk`; returns how many.
+pub fn scan(disk: &Disk) -> BlockResult<usize> {
+    let mut sector0 = [0u8; SECTOR_SIZE];
+    disk.read(0, &mut sector0)?;
+    let entries = match parse_mbr(&sector0, disk.sector_count()) {
+        Some(e) => e,
+        None => return Ok(0),
+    };
+    let mut count = 0;
+    for (n, e) in entries {
+        if e.is_extended() || e.kind == 0xEE {
+            continue;
+        }
+        let name = format!("{}{}", disk.name(), n);
+        crate::vga::vprintln!(
+            "{}: {} MiB, type {:#04x} ({}){}",
+            name, e.sectors * SECTOR_SIZE as u64 / (1024 * 1024), e.kind,
+            kind_name(e.kind), if e.bootable { ", boot" } else { "" }
+        );
+        let part = Partition { name, dev: disk.device().clone(), start: e.start, sectors: e.sectors };
+        block::register(Arc::new(part));
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Look for partition tables on every disk registered so far.
+pub fn scan_all() {
+    for disk in block::disks() {
+        if let Err(e) = scan(&disk) {
+            crate::vga::vprintln!("{}: cannot read partition table: {}", disk.name(), e.as_str());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// Nexis/src/pci.rs
+//
+// PCI configuration space through the legacy 0xCF8/0xCFC ports (mechanism
+// #1), and a brute-force bus scan. Enough to find and set up devices; no
+// MSI, no bridges beyond what the scan sees on its own.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// Serialises the address/data port pair.
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+fn address(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    1 << 31
+        | (bus as u32) << 16
+        | (device as u32) << 11
+        | (function as u32) << 8
+        | (offset as u32 & 0xFC)
+}
+
+pub fn read_u32(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let _g = CONFIG_LOCK.lock();
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address(bus, device, function, offset));
+        Port::<u32>::new(CONFIG_DATA).read()
+    }
+}
+
+pub fn write_u32(bus: u8, device: u8, function: u8, offset: u8, value: u32) {
+    let _g = CONFIG_LOCK.lock();
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address(bus, device, function, offset));
+        Port::<u32>::new(CONFIG_DATA).write(value);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bar {
+    Io(u16),
+    Memory(u64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+impl PciDevice {
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        read_u32(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        write_u32(self.bus, self.device, self.function, offset, value)
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(offset) & !(0xFFFF << shift);
+        self.write_u32(offset, old | (value as u32) << shift);
+    }
+
+    /// Base address register `index` (0..6), or `None` if it is unused.
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        let offset = 0x10 + index * 4;
+        let raw = self.read_u32(offset);
+        if raw & 1 != 0 {
+            let port = (raw & !0x3) as u16;
+            return if port == 0 { None } else { Some(Bar::Io(port)) };
+        }
+        let mut addr = (raw & !0xF) as u64;
+        if (raw >> 1) & 0x3 == 0x2 && index < 5 {
+            addr |= (self.read_u32(offset + 4) as u64) << 32; // 64-bit BAR
+        }
+        if addr == 0 { None } else { Some(Bar::Memory(addr)) }
+    }
+
+    /// Legacy PIC line the firmware routed INTx to (0xFF if none).
+    pub fn interrupt_line(&self) -> u8 {
+        self.read_u32(0x3C) as u8
+    }
+
+    /// Set bits in the command register, e.g. to allow I/O and DMA.
+    pub fn enable(&self, bits: u16) {
+        let cmd = self.read_u16(0x04);
+        self.write_u16(0x04, cmd | bits);
+    }
+}
+
+fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+    let id = read_u32(bus, device, function, 0x00);
+    if id & 0xFFFF == 0xFFFF {
+        return None;
+    }
+    let class = read_u32(bus, device, function, 0x08);
+    Some(PciDevice {
+        bus,
+        device,
+        function,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+    })
+}
+
+/// Every function on every bus.
+pub fn scan() -> Vec<PciDevice> {
+    let mut found = Vec::new();
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let first = match probe(bus, device, 0) {
+                Some(d) => d,
+                None => continue,
+            };
+            found.push(first);
+            let header_type = (read_u32(bus, device, 0, 0x0C) >> 16) as u8;
+            if header_type & 0x80 != 0 {
+                found.extend((1..8).filter_map(|f| probe(bus, device, f)));
+            }
+        }
+    }
+    found
+}
+
+/// Devices matching `vendor_id`:`device_id`.
+pub fn find(vendor_id: u16, device_id: u16) -> Vec<PciDevice> {
+    scan()
+        .into_iter()
+        .filter(|d| d.vendor_id == vendor_id && d.device_id == device_id)
+        .collect()
+}
+
+/// Short description of a class code for `lspci`.
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "storage controller",
+        (0x02, _) => "network controller",
+        (0x03, _) => "display controller",
+        (0x06, 0x00) => "host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "bridge",
+        (0x0C, 0x03) => "USB controller",
+        _ => "device",
+    }
+}
@@ -0,0 +1,119 @@
+// Nexis/src/pipe.rs
+//
+// Anonymous pipes: a bounded byte buffer between a read end and a write end,
+// each held by one `OpenFile`. Readers block while the pipe is empty and
+// writers while it is full. Once the write end is closed, reads drain what
+// is left and then return 0; once the read end is closed, writes fail with
+// EPIPE.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use crate::errno::{Errno, EPIPE};
+use crate::waitqueue::WaitQueue;
+
+/// Bytes a pipe holds before writers block.
+pub const PIPE_BUF: usize = 4096;
+
+struct Pipe {
+    buf: Mutex<VecDeque<u8>>,
+    read_open: AtomicBool,
+    write_open: AtomicBool,
+    /// Woken when data arrives or the write end closes.
+    readable: WaitQueue,
+    /// Woken when room frees up or the read end closes.
+    writable: WaitQueue,
+}
+
+/// One end of a pipe. `dup` and `fork` share the `OpenFile` around it, so
+/// the end closes when the last descriptor referring to it does.
+pub struct PipeEnd {
+    pipe: Arc<Pipe>,
+    write: bool,
+}
+
+/// A new pipe, as (read end, write end).
+pub fn new() -> (PipeEnd, PipeEnd) {
+    let pipe = Arc::new(Pipe {
+        buf: Mutex::new(VecDeque::with_capacity(PIPE_BUF)),
+        read_open: AtomicBool::new(true),
+        write_open: AtomicBool::new(true),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeEnd { pipe: pipe.clone(), write: false }, PipeEnd { pipe, write: true })
+}
+
+impl PipeEnd {
+    /// Take up to `out.len()` buffered bytes, blocking while the pipe is
+    /// empty and still has a writer; 0 means end of file.
+    pub fn read(&self, out: &mut [u8]) -> usize {
+        if out.is_empty() {
+            return 0;
+        }
+        let p = &self.pipe;
+        let n = p.readable.wait_until(|| {
+            let mut buf = p.buf.lock();
+            if buf.is_empty() {
+                return (!p.write_open.load(Ordering::SeqCst)).then_some(0);
+            }
+            let n = out.len().min(buf.len());
+            for (o, b) in out.iter_mut().zip(buf.drain(..n)) {
+                *o = b;
+            }
+            Some(n)
+        });
+        p.writable.wake_all();
+        n
+    }
+
+    /// Queue all of `data`, blocking whenever the pipe is full. If the read
+    /// end closes part way, the count written so far is returned.
+    pub fn write(&self, data: &[u8]) -> Result<usize, Errno> {
+        let p = &self.pipe;
+        let mut done = 0;
+        while done < data.len() {
+            let r = p.writable.wait_until(|| {
+                if !p.read_open.load(Ordering::SeqCst) {
+                    return Some(Err(EPIPE));
+                }
+                let mut buf = p.buf.lock();
+                let n = (PIPE_BUF - buf.len()).min(data.len() - done);
+                if n == 0 {
+                    return None;
+                }
+                buf.extend(&data[done..done + n]);
+                Some(Ok(n))
+            });
+            match r {
+                Ok(n) => {
+                    done += n;
+                    p.readable.wake_all();
+                }
+                Err(e) if done == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(done)
+    }
+
+    /// Bytes waiting to be read.
+    pub fn buffered(&self) -> usize {
+        self.pipe.buf.lock().len()
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let p = &self.pipe;
+        if self.write {
+            p.write_open.store(false, Ordering::SeqCst);
+            p.readable.wake_all();
+        } else {
+            p.read_open.store(false, Ordering::SeqCst);
+            p.writable.wake_all();
+        }
+    }
+}
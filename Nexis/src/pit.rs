@@ -1,10 +1,17 @@
 use x86_64::instructions::port::Port;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+pub const PIT_BASE_HZ: u32 = 1193182;
+pub const DEFAULT_HZ: u32 = 100;
 
 static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
+static HZ: AtomicU32 = AtomicU32::new(DEFAULT_HZ);
 
+/// Program channel 0 to fire IRQ0 `hz` times per second (clamped to 19..=1193182).
 pub fn init(hz: u32) {
-    let divisor = if hz == 0 { 0 } else { (1193182u32 / hz) as u16 };
+    let hz = hz.clamp(19, PIT_BASE_HZ);
+    let divisor = (PIT_BASE_HZ / hz) as u16;
+    HZ.store(hz, Ordering::SeqCst);
     unsafe {
         let mut cmd = Port::<u8>::new(0x43);
         let mut data = Port::<u8>::new(0x40);
@@ -20,4 +27,21 @@ pub fn tick() -> u64 {
 
 pub fn ticks() -> u64 {
     TICK_COUNT.load(Ordering::SeqCst)
-}
\ No newline at end of file
+}
+
+pub fn hz() -> u32 {
+    HZ.load(Ordering::Relaxed)
+}
+
+/// Convert milliseconds to ticks, rounding up so short sleeps still sleep.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    ms.saturating_mul(hz() as u64).saturating_add(999) / 1000
+}
+
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    ticks.saturating_mul(1_000_000) / hz() as u64
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / hz() as u64
+}
@@ -0,0 +1,45 @@
+// Nexis/src/power.rs
+//
+// Restarting and stopping the machine. Reboot pulses the CPU reset line
+// through the 8042 keyboard controller, which every PC and QEMU machine
+// has; should that not work, a triple fault resets the CPU anyway.
+
+use x86_64::instructions::{hlt, interrupts, port::Port};
+
+const KBC_STATUS: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_CMD_RESET: u8 = 0xFE;
+
+/// Write back cached data so nothing is lost across a reset or power-off.
+fn flush() {
+    crate::fs::vfs::sync_all();
+    crate::block::sync_all();
+}
+
+pub fn reboot() -> ! {
+    flush();
+    crate::vga::vprintln!("Rebooting...");
+    interrupts::disable();
+    unsafe {
+        let mut kbc = Port::<u8>::new(KBC_STATUS);
+        for _ in 0..0x10000 {
+            if kbc.read() & KBC_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        kbc.write(KBC_CMD_RESET);
+        // still here: load an empty IDT, so the next exception can't be
+        // delivered and escalates to a triple fault
+        let empty = x86_64::structures::DescriptorTablePointer { limit: 0, base: x86_64::VirtAddr::new(0) };
+        x86_64::instructions::tables::lidt(&empty);
+        core::arch::asm!("int3", options(noreturn));
+    }
+}
+
+/// Stop for good: interrupts off, CPU halted.
+pub fn halt() -> ! {
+    interrupts::disable();
+    loop {
+        hlt();
+    }
+}
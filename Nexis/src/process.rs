@@ -1,17 +1,34 @@
 #![no_std]
+//
+// The process table. A process is a scheduler task plus what outlives or
+// surrounds it: pid, parent, name and exit status. An exited process stays
+// a zombie until its parent collects the status with `wait`; orphans are
+// handed to init (pid 1), and a process with no parent is reaped as soon
+// as it exits.
 
 use core::sync::atomic::{AtomicU32, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+use crate::errno::{Errno, EAGAIN, ECHILD, ENOMEM, ESRCH};
+use crate::exceptions::TrapFrame;
+use crate::task::CpuTimes;
+use crate::waitqueue::WaitQueue;
+
 pub type Pid = u32;
 
+/// Reserved for init (see `spawn_init`), which adopts orphans. Without an
+/// init no process has this pid.
+pub const INIT_PID: Pid = 1;
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ProcState {
     Runnable,
     Running,
     Sleeping(u64),
+    /// Exited; waiting for the parent to collect `exit_status`.
     Zombie,
+    /// Reaped, or never used: the slot is free.
     Finished,
 }
 
@@ -24,6 +41,12 @@ pub struct Process {
     pub stack_size: usize,
     pub parent: Option<Pid>,
     pub name: [u8; 16],
+    /// Wait status (see `nexis_abi::process`), once a zombie.
+    pub exit_status: i32,
+    /// CPU time used, recorded at exit; while running the task keeps count.
+    pub times: CpuTimes,
+    /// CPU time of every child this process has reaped, theirs included.
+    pub child_times: CpuTimes,
 }
 
 impl Process {
@@ -31,11 +54,35 @@ impl Process {
         Self {
             pid: 0,
             slot: 0,
-            state: ProcState::Zombie,
+            state: ProcState::Finished,
             stack_base: 0,
             stack_size: 0,
             parent: None,
             name: [0u8; 16],
+            exit_status: 0,
+            times: CpuTimes { user: 0, system: 0 },
+            child_times: CpuTimes { user: 0, system: 0 },
+        }
+    }
+}
+
+impl Process {
+    pub fn name_str(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// Still running (not a zombie, not a free slot).
+    pub fn is_live(&self) -> bool {
+        self.state != ProcState::Zombie && self.state != ProcState::Finished
+    }
+
+    /// CPU time used so far, or in total once exited.
+    pub fn cpu_times(&self) -> CpuTimes {
+        if self.is_live() {
+            crate::scheduler::task_times(self.slot).unwrap_or_default()
+        } else {
+            self.times
         }
     }
 }
@@ -50,17 +97,16 @@ impl ProcessTable {
     pub const fn new() -> Self {
         Self {
             procs: [Process::empty(); ProcessTable::MAX_PROCS],
-            next_pid: AtomicU32::new(1),
+            next_pid: AtomicU32::new(INIT_PID + 1),
         }
     }
 
     fn alloc_slot(&mut self) -> Option<usize> {
-        for i in 0..Self::MAX_PROCS {
-            if self.procs[i].state == ProcState::Zombie || self.procs[i].state == ProcState::Finished {
-                return Some(i);
-            }
-        }
-        None
+        self.procs.iter().position(|p| p.state == ProcState::Finished)
+    }
+
+    fn live(&self, pid: Pid) -> Option<&Process> {
+        self.procs.iter().find(|p| p.pid == pid && p.is_live())
     }
 
     fn alloc_pid(&self) -> Pid {
@@ -68,77 +114,229 @@ impl ProcessTable {
     }
 }
 
+/// Parents blocked in `wait`; woken whenever a process becomes a zombie.
+static CHILD_EXIT: WaitQueue = WaitQueue::new();
+
 lazy_static! {
     pub static ref PROC_TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable::new());
 }
 
+fn name_bytes(name: &str) -> [u8; 16] {
+    let mut n = [0u8; 16];
+    let len = name.len().min(n.len());
+    n[..len].copy_from_slice(&name.as_bytes()[..len]);
+    n
+}
+
+/// Record a freshly spawned scheduler task in the process table, under
+/// `pid` or a new pid. If the table is full the task is torn down again.
+fn register_as(pid: Option<Pid>, slot_idx: usize, stack_size: usize, parent: Option<Pid>, name: &str) -> Option<Pid> {
+    let mut table = PROC_TABLE.lock();
+    if let Some(pt_slot) = table.alloc_slot() {
+        let pid = pid.unwrap_or_else(|| table.alloc_pid());
+        table.procs[pt_slot] = Process {
+            pid,
+            slot: slot_idx,
+            state: ProcState::Runnable,
+            stack_base: 0,
+            stack_size,
+            parent,
+            name: name_bytes(name),
+            ..Process::empty()
+        };
+        drop(table);
+        crate::fd::init_process(pid);
+        Some(pid)
+    } else {
+        drop(table);
+        crate::scheduler::task_exit(slot_idx);
+        None
+    }
+}
+
+fn register(slot_idx: usize, stack_size: usize, parent: Option<Pid>, name: &str) -> Option<Pid> {
+    register_as(None, slot_idx, stack_size, parent, name)
+}
+
 pub fn spawn(entry: extern "C" fn(), pages: usize, parent: Option<Pid>) -> Option<Pid> {
-    if let Some(slot_idx) = crate::scheduler::spawn(entry, unsafe { &crate::PMM }, pages) {
-        let mut table = PROC_TABLE.lock();
-        if let Some(pt_slot) = table.alloc_slot() {
-            let pid = table.alloc_pid();
-            table.procs[pt_slot] = Process {
-                pid,
-                slot: slot_idx,
-                state: ProcState::Runnable,
-                stack_base: 0,
-                stack_size: pages * crate::memory::FRAME_SIZE,
-                parent,
-                name: [0u8; 16],
-            };
-            return Some(pid);
-        } else {
-            crate::scheduler::task_exit(slot_idx, unsafe { &crate::PMM });
+    let slot_idx = crate::scheduler::spawn(entry, pages)?;
+    register(slot_idx, pages * crate::memory::FRAME_SIZE, parent, "")
+}
+
+/// Start a loaded user program as a new process.
+pub fn spawn_user(name: &str, image: crate::userland::UserImage, parent: Option<Pid>) -> Option<Pid> {
+    spawn_user_as(None, name, image, parent)
+}
+
+/// Start a loaded user program as init. Fails if init is already running.
+pub fn spawn_init(name: &str, image: crate::userland::UserImage) -> Option<Pid> {
+    if PROC_TABLE.lock().live(INIT_PID).is_some() {
+        unsafe { crate::vmm::kernel_vmm().destroy_address_space(image.space) };
+        return None;
+    }
+    spawn_user_as(Some(INIT_PID), name, image, None)
+}
+
+fn spawn_user_as(pid: Option<Pid>, name: &str, image: crate::userland::UserImage, parent: Option<Pid>) -> Option<Pid> {
+    let slot_idx = match crate::scheduler::spawn_user(image.space, image.entry, image.stack_top, name) {
+        Some(s) => s,
+        None => {
+            unsafe { crate::vmm::kernel_vmm().destroy_address_space(image.space) };
             return None;
         }
-    }
-    None
+    };
+    let stack_size = crate::userland::USER_STACK_PAGES * crate::memory::FRAME_SIZE;
+    register_as(pid, slot_idx, stack_size, parent, name)
 }
 
 pub fn current_pid() -> Option<Pid> {
-    if let Some(cur_slot) = crate::scheduler::current_index() {
-        let table = PROC_TABLE.lock();
-        for p in table.procs.iter() {
-            if p.slot == cur_slot && p.state != ProcState::Zombie && p.state != ProcState::Finished {
-                return Some(p.pid);
-            }
+    current().map(|p| p.pid)
+}
+
+/// The calling process, copied out of the table.
+pub fn current() -> Option<Process> {
+    let cur_slot = crate::scheduler::current_index()?;
+    let table = PROC_TABLE.lock();
+    table.procs.iter().find(|p| p.slot == cur_slot && p.is_live()).copied()
+}
+
+/// Like `current_pid`, but never blocks; used from fault handlers where the
+/// table lock may already be held by the interrupted code.
+pub fn try_current() -> Option<Process> {
+    let cur_slot = crate::scheduler::current_index()?;
+    let table = PROC_TABLE.try_lock()?;
+    table
+        .procs
+        .iter()
+        .find(|p| p.slot == cur_slot && p.is_live())
+        .copied()
+}
+
+/// Duplicate the calling process. The child gets a copy-on-write copy of
+/// the address space and the descriptor table, and resumes from `frame`
+/// with 0 in rax. Returns the child's pid.
+pub fn fork(frame: &TrapFrame) -> Result<Pid, Errno> {
+    let parent = current().ok_or(ESRCH)?;
+    let vmm = crate::vmm::kernel_vmm();
+    let space = vmm.fork_address_space(crate::vmm::AddressSpace::current()).map_err(|_| ENOMEM)?;
+    let mut child_frame = *frame;
+    child_frame.rax = 0;
+    let slot = match crate::scheduler::spawn_fork(space, child_frame, parent.name_str()) {
+        Some(s) => s,
+        None => {
+            unsafe { vmm.destroy_address_space(space) };
+            return Err(ENOMEM);
         }
-    }
-    None
+    };
+    // on failure `register` kills the still-blocked task, which frees `space`
+    let pid = register(slot, parent.stack_size, Some(parent.pid), parent.name_str()).ok_or(EAGAIN)?;
+    crate::fd::fork(parent.pid, pid);
+    crate::scheduler::unblock(slot);
+    Ok(pid)
 }
 
-pub fn exit_self(pid: Pid) -> bool {
+/// Rename a live process (after `execve`).
+pub fn set_name(pid: Pid, name: &str) {
     let mut table = PROC_TABLE.lock();
-    for i in 0..ProcessTable::MAX_PROCS {
-        if table.procs[i].pid == pid {
-            let slot = table.procs[i].slot;
-            table.procs[i].state = ProcState::Finished;
-            crate::scheduler::task_exit(slot, unsafe { &crate::PMM });
-            return true;
-        }
+    if let Some(p) = table.procs.iter_mut().find(|p| p.pid == pid && p.is_live()) {
+        p.name = name_bytes(name);
     }
-    false
 }
 
-pub fn sleep_ms(pid: Pid, ms: u64) {
-    let wake = crate::pit::ticks().saturating_add(ms);
+/// End process `pid` with wait status `status`. Its children go to init,
+/// and its parent, if any, is woken to collect it. If `pid` is the caller,
+/// this does not return. Init itself must never exit: the system cannot
+/// go on without it, so that is a kernel panic.
+pub fn exit(pid: Pid, status: i32) -> bool {
+    use nexis_abi::process::{exit_code, exited, term_signal};
     let mut table = PROC_TABLE.lock();
-    for i in 0..ProcessTable::MAX_PROCS {
-        if table.procs[i].pid == pid {
-            table.procs[i].state = ProcState::Sleeping(wake);
-            break;
+    let i = match table.procs.iter().position(|p| p.pid == pid && p.is_live()) {
+        Some(i) => i,
+        None => return false,
+    };
+    if pid == INIT_PID {
+        drop(table);
+        if exited(status) {
+            panic!("init (pid 1) exited with code {}", exit_code(status));
         }
+        panic!("init (pid 1) was killed by signal {}", term_signal(status).unwrap_or(0));
     }
+    let slot = table.procs[i].slot;
+    table.procs[i].times = crate::scheduler::task_times(slot).unwrap_or_default();
+    let has_parent = table.procs[i].parent.and_then(|pp| table.live(pp)).is_some();
+    table.procs[i].exit_status = status;
+    // nobody will wait for a process without a parent
+    table.procs[i].state = if has_parent { ProcState::Zombie } else { ProcState::Finished };
+
+    let mut wake = has_parent;
+    let has_init = table.live(INIT_PID).is_some();
+    for p in table.procs.iter_mut().filter(|p| p.parent == Some(pid) && p.state != ProcState::Finished) {
+        if has_init {
+            p.parent = Some(INIT_PID);
+            wake |= p.state == ProcState::Zombie;
+        } else {
+            p.parent = None;
+            if p.state == ProcState::Zombie {
+                p.state = ProcState::Finished;
+            }
+        }
+    }
+    drop(table); // task_exit may switch away for good
+
+    if wake {
+        CHILD_EXIT.wake_all();
+    }
+    crate::fd::release(pid);
+    crate::scheduler::task_exit(slot);
+    true
 }
 
-pub fn wake_sleepers() {
-    let ticks = crate::pit::ticks();
-    let mut table = PROC_TABLE.lock();
-    for i in 0..ProcessTable::MAX_PROCS {
-        if let ProcState::Sleeping(until) = table.procs[i].state {
-            if ticks >= until {
-                table.procs[i].state = ProcState::Runnable;
+/// Collect an exited child of the caller: `target` or, with `None`, any.
+/// Returns its pid and wait status, or `None` if `nohang` and no child
+/// has exited yet.
+pub fn wait(target: Option<Pid>, nohang: bool) -> Result<Option<(Pid, i32)>, Errno> {
+    let me = current_pid().ok_or(ESRCH)?;
+    CHILD_EXIT.wait_until(|| {
+        let mut table = PROC_TABLE.lock();
+        let mut children = table
+            .procs
+            .iter_mut()
+            .filter(|p| p.parent == Some(me) && p.state != ProcState::Finished)
+            .filter(|p| target.is_none_or(|t| p.pid == t))
+            .peekable();
+        if children.peek().is_none() {
+            return Some(Err(ECHILD));
+        }
+        if let Some(p) = children.find(|p| p.state == ProcState::Zombie) {
+            p.state = ProcState::Finished;
+            let (pid, status) = (p.pid, p.exit_status);
+            let mut used = p.times;
+            used.add(p.child_times);
+            if let Some(parent) = table.procs.iter_mut().find(|p| p.pid == me && p.is_live()) {
+                parent.child_times.add(used);
             }
+            return Some(Ok(Some((pid, status))));
         }
+        nohang.then_some(Ok(None))
+    })
+}
+
+/// Put the calling process to sleep for at least `ms` milliseconds. It
+/// shows as sleeping in /proc meanwhile.
+pub fn sleep_ms(ms: u64) {
+    let until = crate::pit::ticks().saturating_add(crate::pit::ms_to_ticks(ms));
+    set_current_state(ProcState::Sleeping(until));
+    crate::waitqueue::sleep_ms(ms);
+    set_current_state(ProcState::Runnable);
+}
+
+fn set_current_state(state: ProcState) {
+    let slot = match crate::scheduler::current_index() {
+        Some(s) => s,
+        None => return,
+    };
+    let mut table = PROC_TABLE.lock();
+    if let Some(p) = table.procs.iter_mut().find(|p| p.slot == slot && p.is_live()) {
+        p.state = state;
     }
-}
\ No newline at end of file
+}
@@ -1,15 +1,93 @@
-use crate::task::Task;
+// Nexis/src/scheduler.rs
+//
+// Two scheduling classes. FIFO tasks (real time, for latency-sensitive
+// kernel work such as input handling) always run first, highest priority
+// first, and keep the CPU until they block or yield; they are not time
+// sliced. Everything else is fair: each timer tick charges the running
+// task virtual runtime scaled by the weight of its nice level, and the
+// task with the least virtual runtime runs next, so CPU time is shared in
+// proportion to the weights.
+
+use crate::task::{prepare_stack, CpuTimes, KernelStack, SchedPolicy, Task, TaskState};
 use crate::context::context_switch;
+use crate::exceptions::TrapFrame;
+use crate::vmm::{AddressSpace, VirtualMemoryManager};
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Length of a time slice before the timer preempts the running task.
+pub const TIME_SLICE_MS: u64 = 10;
+
+/// Slot 0 is the boot context, which becomes the idle task.
+pub const IDLE_TASK: usize = 0;
+
+const NO_TASK: usize = usize::MAX;
+
+/// Most tasks alive at once, the idle task included. Per-task state kept
+/// outside the scheduler (wait queues) is sized by this.
+pub const MAX_TASKS: usize = 128;
+
+/// Kernel stack size for user processes (syscalls and traps run on it).
+pub const USER_KSTACK_PAGES: usize = 8;
+
+pub const NICE_MIN: i8 = nexis_abi::resource::NICE_MIN as i8;
+pub const NICE_MAX: i8 = nexis_abi::resource::NICE_MAX as i8;
+
+/// Weight of each nice level from -20 to 19, as in Linux: one level up or
+/// down is roughly 10% more or less CPU against a competing task.
+const NICE_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906, 3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423, 335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Virtual runtime a nice-0 task is charged per tick.
+const VRUNTIME_PER_TICK: u64 = 1024;
+
+/// Virtual runtime charged to a task at `nice` for one tick.
+fn vruntime_delta(nice: i8) -> u64 {
+    let weight = NICE_WEIGHTS[(nice.clamp(NICE_MIN, NICE_MAX) - NICE_MIN) as usize];
+    VRUNTIME_PER_TICK * NICE_0_WEIGHT / weight
+}
 
 lazy_static::lazy_static! {
     pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
 }
 
+/// Mirror of `Scheduler::current`, readable without taking the lock
+/// (fault handlers, `process::try_current`).
+static CURRENT: AtomicUsize = AtomicUsize::new(NO_TASK);
+
+/// A pending context switch: where to save the old rsp, which rsp to load.
+type Switch = (*mut usize, usize);
+
 pub struct Scheduler {
-    tasks: Vec<Task>,
+    tasks: Vec<Option<Task>>,
     current: usize,
+    slice_left: u64,
+    /// Never decreases; new and woken fair tasks start no further back
+    /// than this, so sleeping does not bank CPU time.
+    min_vruntime: u64,
+    /// Stamps `Task::ready_seq`.
+    ready_seq: u64,
+    /// A task that should preempt the running one became ready.
+    need_resched: bool,
+    vmm: Option<&'static VirtualMemoryManager>,
+}
+
+/// A task as `ps` shows it.
+pub struct TaskInfo {
+    pub slot: usize,
+    pub name: String,
+    pub state: TaskState,
+    pub policy: SchedPolicy,
+    pub nice: i8,
+    pub times: CpuTimes,
 }
 
 impl Scheduler {
@@ -17,30 +95,557 @@ impl Scheduler {
         Self {
             tasks: Vec::new(),
             current: 0,
+            slice_left: 0,
+            min_vruntime: 0,
+            ready_seq: 0,
+            need_resched: false,
+            vmm: None,
         }
     }
 
-    pub fn add_task(&mut self, task: Task) {
-        self.tasks.push(task);
+    fn free_slot(&mut self) -> Option<usize> {
+        match self.tasks.iter().position(|t| t.is_none()) {
+            Some(i) => Some(i),
+            None if self.tasks.len() < MAX_TASKS => {
+                self.tasks.push(None);
+                Some(self.tasks.len() - 1)
+            }
+            None => None,
+        }
     }
 
-    pub fn schedule(&mut self) {
-        if self.tasks.is_empty() {
-            return;
+    pub fn add_task(&mut self, task: Task) -> Option<usize> {
+        let slot = self.free_slot()?;
+        self.install(slot, task);
+        Some(slot)
+    }
+
+    /// Put `task` into `slot`, replacing whatever reserved it.
+    fn install(&mut self, slot: usize, mut task: Task) {
+        task.id = slot;
+        task.vruntime = self.min_vruntime;
+        let ready = task.state == TaskState::Ready;
+        self.tasks[slot] = Some(task);
+        if ready {
+            self.make_ready(slot);
+        }
+    }
+
+    /// Move a task onto the ready list: stamp it for FIFO order, keep a
+    /// fair task from coming back with a long-stale virtual runtime, and ask
+    /// for a reschedule if it should preempt the running task.
+    fn make_ready(&mut self, slot: usize) {
+        self.ready_seq += 1;
+        let seq = self.ready_seq;
+        // a waking task may be up to half a slice ahead of the others
+        let credit = crate::pit::ms_to_ticks(TIME_SLICE_MS) * VRUNTIME_PER_TICK / 2;
+        let floor = self.min_vruntime.saturating_sub(credit);
+        let current = self.current_task().map(|t| t.policy);
+        let t = match self.task_mut(slot) {
+            Some(t) => t,
+            None => return,
+        };
+        t.state = TaskState::Ready;
+        t.ready_seq = seq;
+        let preempt = match t.policy {
+            SchedPolicy::Fair => {
+                t.vruntime = t.vruntime.max(floor);
+                false
+            }
+            SchedPolicy::Fifo(prio) => !matches!(current, Some(SchedPolicy::Fifo(p)) if p >= prio),
+        };
+        self.need_resched |= preempt;
+    }
+
+    pub fn task(&self, slot: usize) -> Option<&Task> {
+        self.tasks.get(slot).and_then(|t| t.as_ref())
+    }
+
+    pub fn task_mut(&mut self, slot: usize) -> Option<&mut Task> {
+        self.tasks.get_mut(slot).and_then(|t| t.as_mut())
+    }
+
+    pub fn tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.iter().flatten()
+    }
+
+    /// A dead task's slot, kernel stack and address space, for `reap_dead`
+    /// to free. Never the running task, whose stack we are still on; it
+    /// gets reaped once something else runs. The slot stays taken until
+    /// `reap_dead` has unmapped the stack, since the next task in the slot
+    /// maps the same range.
+    fn take_dead(&mut self) -> Option<(usize, Option<KernelStack>, Option<AddressSpace>)> {
+        let cur = self.current;
+        let slot = (0..self.tasks.len())
+            .find(|&i| i != cur && matches!(&self.tasks[i], Some(t) if t.state == TaskState::Dead))?;
+        let t = self.tasks[slot].as_mut()?;
+        Some((slot, t.stack.take(), t.address_space.take()))
+    }
+
+    /// The task that should run: the highest-priority FIFO task, the one
+    /// ready longest among equals; else the fair task with the least
+    /// virtual runtime; else nothing (the idle task).
+    fn best_runnable(&self) -> Option<usize> {
+        let prev = self.current;
+        let mut fifo: Option<(usize, u8, u64)> = None;
+        let mut fair: Option<(usize, u64)> = None;
+        for (i, t) in self.tasks.iter().enumerate() {
+            let t = match t {
+                Some(t) if i != IDLE_TASK => t,
+                _ => continue,
+            };
+            if !(t.state == TaskState::Ready || (i == prev && t.state == TaskState::Running)) {
+                continue;
+            }
+            match t.policy {
+                SchedPolicy::Fifo(prio) => {
+                    if fifo.is_none_or(|(_, p, seq)| prio > p || (prio == p && t.ready_seq < seq)) {
+                        fifo = Some((i, prio, t.ready_seq));
+                    }
+                }
+                SchedPolicy::Fair => {
+                    if fair.is_none_or(|(_, v)| t.vruntime < v) {
+                        fair = Some((i, t.vruntime));
+                    }
+                }
+            }
         }
+        fifo.map(|(i, _, _)| i).or(fair.map(|(i, _)| i))
+    }
 
+    fn pick_next(&mut self) -> Option<Switch> {
+        if self.tasks.is_empty() {
+            return None;
+        }
         let prev = self.current;
-        self.current = (self.current + 1) % self.tasks.len();
+        let next = self.best_runnable().unwrap_or(IDLE_TASK);
+
+        self.need_resched = false;
+        self.slice_left = crate::pit::ms_to_ticks(TIME_SLICE_MS).max(1);
+        if let Some(t) = self.task(next) {
+            if next != IDLE_TASK && t.policy == SchedPolicy::Fair {
+                // the least among runnable fair tasks
+                self.min_vruntime = self.min_vruntime.max(t.vruntime);
+            }
+        }
+        if next == prev {
+            // `yield_now` may have queued it as ready
+            if let Some(t) = self.task_mut(prev) {
+                t.state = TaskState::Running;
+            }
+            return None;
+        }
 
-        let prev_task = &mut self.tasks[prev];
-        let next_task = &self.tasks[self.current];
+        if matches!(self.task(prev), Some(t) if t.state == TaskState::Running) {
+            self.make_ready(prev);
+        }
+        let next_task = self.tasks[next].as_mut()?;
+        next_task.state = TaskState::Running;
+        if let Some(stack) = &next_task.stack {
+            crate::gdt::set_kernel_stack(stack.top());
+        }
+        let next_space = next_task.address_space;
+        let next_rsp = next_task.stack_pointer;
+        if let Some(vmm) = self.vmm {
+            // kernel stacks live in the shared kernel half, so switching
+            // CR3 before the stack switch is safe
+            unsafe { next_space.unwrap_or(vmm.kernel_space()).activate() };
+        }
+
+        self.current = next;
+        CURRENT.store(next, Ordering::SeqCst);
+        // dead tasks are only reaped once not current, so `prev` is still here
+        let prev_rsp = &mut self.tasks[prev].as_mut()?.stack_pointer as *mut usize;
+        Some((prev_rsp, next_rsp))
+    }
+
+    pub fn schedule(&mut self) -> Option<Switch> {
+        self.pick_next()
+    }
 
-        unsafe {
-            context_switch(&mut prev_task.stack_pointer, next_task.stack_pointer);
+    /// Charge one timer tick to the running task, to user or system time
+    /// by `user`. True when it should be preempted: something more urgent
+    /// is ready, or it is a fair task whose slice is used up.
+    fn tick(&mut self, user: bool) -> bool {
+        if self.slice_left > 0 {
+            self.slice_left -= 1;
         }
+        let cur = self.current;
+        let fifo = match self.task_mut(cur) {
+            Some(t) => {
+                if user {
+                    t.times.user += 1;
+                } else {
+                    t.times.system += 1;
+                }
+                if t.policy == SchedPolicy::Fair {
+                    t.vruntime += vruntime_delta(t.nice);
+                }
+                matches!(t.policy, SchedPolicy::Fifo(_))
+            }
+            None => false,
+        };
+        self.need_resched || (self.slice_left == 0 && !fifo)
     }
 
     pub fn current_task(&self) -> Option<&Task> {
-        self.tasks.get(self.current)
+        self.task(self.current)
+    }
+}
+
+/// Turn the boot context into the idle task. Must run before `spawn`.
+pub fn init(vmm: &'static VirtualMemoryManager) {
+    let mut s = SCHEDULER.lock();
+    s.vmm = Some(vmm);
+    let mut idle = Task::new(IDLE_TASK, None, "idle");
+    idle.state = TaskState::Running;
+    let slot = s.add_task(idle);
+    debug_assert_eq!(slot, Some(IDLE_TASK));
+    s.current = IDLE_TASK;
+    CURRENT.store(IDLE_TASK, Ordering::SeqCst);
+}
+
+fn switch_to(switch: Option<Switch>) {
+    if let Some((prev, next)) = switch {
+        unsafe { context_switch(prev, next) };
+    }
+}
+
+/// Create a kernel task running `entry` on a fresh `pages`-page stack.
+/// Returns the task's slot index.
+pub fn spawn(entry: extern "C" fn(), pages: usize) -> Option<usize> {
+    spawn_named(entry, pages, "task")
+}
+
+pub fn spawn_named(entry: extern "C" fn(), pages: usize, name: &str) -> Option<usize> {
+    spawn_with(entry, pages, name, |_| {})
+}
+
+/// Like `spawn_named`, but the task runs under `SchedPolicy::Fifo(prio)`.
+pub fn spawn_fifo(entry: extern "C" fn(), pages: usize, name: &str, prio: u8) -> Option<usize> {
+    let prio = prio.clamp(1, SchedPolicy::MAX_RT_PRIORITY);
+    spawn_with(entry, pages, name, |t| t.policy = SchedPolicy::Fifo(prio))
+}
+
+/// Build a task and let `setup` finish it before it becomes visible to the
+/// scheduler, so it can never run half-initialised. The stack is mapped
+/// without the scheduler lock held, in a slot reserved for it meanwhile.
+fn spawn_with(entry: extern "C" fn(), pages: usize, name: &str, setup: impl FnOnce(&mut Task)) -> Option<usize> {
+    reap_dead();
+    let (vmm, slot) = interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        let vmm = s.vmm?;
+        let slot = s.free_slot()?;
+        let mut placeholder = Task::new(slot, None, name);
+        placeholder.state = TaskState::Starting;
+        s.tasks[slot] = Some(placeholder);
+        Some((vmm, slot))
+    })?;
+    let stack = KernelStack::alloc(vmm, slot, pages);
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        let stack = match stack {
+            Some(stack) => stack,
+            None => {
+                s.tasks[slot] = None;
+                return None;
+            }
+        };
+        let mut task = Task::new(slot, None, name);
+        task.stack_pointer = prepare_stack(entry, stack.bottom(), stack.size());
+        task.stack = Some(stack);
+        setup(&mut task);
+        s.install(slot, task);
+        Some(slot)
+    })
+}
+
+/// Only one `reap_dead` at a time: it frees a slot's resources before
+/// releasing the slot, and a second reaper must not release it earlier.
+static REAPER: Mutex<()> = Mutex::new(());
+
+/// Free the kernel stacks and address spaces of dead tasks and release
+/// their slots. Runs without the scheduler lock, so the VMM and PMM are
+/// never entered with it held; called by the idle loop and before spawning.
+pub fn reap_dead() {
+    let _reaper = match REAPER.try_lock() {
+        Some(g) => g,
+        None => return, // someone else is at it
+    };
+    let vmm = match interrupts::without_interrupts(|| SCHEDULER.lock().vmm) {
+        Some(v) => v,
+        None => return,
+    };
+    while let Some((slot, stack, space)) = interrupts::without_interrupts(|| SCHEDULER.lock().take_dead()) {
+        if let Some(stack) = stack {
+            stack.free(vmm);
+        }
+        if let Some(space) = space {
+            // not active: pick_next loaded another CR3 when it died
+            unsafe { vmm.destroy_address_space(space) };
+        }
+        interrupts::without_interrupts(|| SCHEDULER.lock().tasks[slot] = None);
+    }
+}
+
+/// Create a task that enters ring 3 at `rip` with stack `rsp` inside `space`.
+/// The task owns `space` from now on and frees it when it is reaped.
+pub fn spawn_user(space: AddressSpace, rip: usize, rsp: usize, name: &str) -> Option<usize> {
+    spawn_with(crate::userland::user_task_start, USER_KSTACK_PAGES, name, |t| {
+        t.address_space = Some(space);
+        t.user_entry = Some((rip, rsp));
+    })
+}
+
+/// Create the task for a forked child: it resumes user mode in `space`
+/// with registers `frame`. The task starts out blocked so the caller can
+/// finish setting up the process; `unblock` lets it run.
+/// The child inherits the caller's policy and nice value.
+pub fn spawn_fork(space: AddressSpace, frame: TrapFrame, name: &str) -> Option<usize> {
+    let (policy, nice) = interrupts::without_interrupts(|| {
+        SCHEDULER.lock().current_task().map_or((SchedPolicy::Fair, 0), |t| (t.policy, t.nice))
+    });
+    spawn_with(crate::userland::fork_child_start, USER_KSTACK_PAGES, name, |t| {
+        t.address_space = Some(space);
+        t.resume_frame = Some(frame);
+        t.state = TaskState::Blocked;
+        t.policy = policy;
+        t.nice = nice;
+    })
+}
+
+/// The registers a forked child was created with, once.
+pub fn take_resume_frame() -> Option<TrapFrame> {
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        let cur = s.current;
+        s.task_mut(cur).and_then(|t| t.resume_frame.take())
+    })
+}
+
+/// Switch the running task to user address space `space` (for `execve`)
+/// and return the one it had, which the caller must destroy.
+pub fn replace_address_space(space: AddressSpace) -> Option<AddressSpace> {
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        let cur = s.current;
+        let t = s.task_mut(cur)?;
+        let old = t.address_space.replace(space);
+        unsafe { space.activate() };
+        old
+    })
+}
+
+/// Rename the running task.
+pub fn set_current_name(name: &str) {
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        let cur = s.current;
+        if let Some(t) = s.task_mut(cur) {
+            t.set_name(name);
+        }
+    })
+}
+
+/// Where the running user task should enter ring 3, if it is one.
+pub fn current_user_entry() -> Option<(usize, usize)> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().current_task().and_then(|t| t.user_entry))
+}
+
+/// Whether the running task has a user address space.
+pub fn current_is_user() -> bool {
+    interrupts::without_interrupts(|| {
+        SCHEDULER.lock().current_task().is_some_and(|t| t.address_space.is_some())
+    })
+}
+
+/// Add `inc` to the running task's nice value, clamped to
+/// `NICE_MIN..=NICE_MAX`. Returns the new value.
+pub fn renice_current(inc: i32) -> i8 {
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        let cur = s.current;
+        match s.task_mut(cur) {
+            Some(t) => {
+                t.nice = (t.nice as i32).saturating_add(inc).clamp(NICE_MIN as i32, NICE_MAX as i32) as i8;
+                t.nice
+            }
+            None => 0,
+        }
+    })
+}
+
+/// Change a task's scheduling policy. It takes effect at the next
+/// scheduling decision.
+pub fn set_policy(slot: usize, policy: SchedPolicy) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        match s.task_mut(slot) {
+            Some(t) if t.state != TaskState::Dead => {
+                t.policy = policy;
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// CPU time used by the task in `slot`.
+pub fn task_times(slot: usize) -> Option<CpuTimes> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().task(slot).map(|t| t.times))
+}
+
+pub fn current_times() -> CpuTimes {
+    interrupts::without_interrupts(|| SCHEDULER.lock().current_task().map(|t| t.times).unwrap_or_default())
+}
+
+fn task_info(t: &Task) -> TaskInfo {
+    TaskInfo {
+        slot: t.id,
+        name: String::from(t.name_str()),
+        state: t.state,
+        policy: t.policy,
+        nice: t.nice,
+        times: t.times,
     }
-}
\ No newline at end of file
+}
+
+/// Every task that has not been reaped yet.
+pub fn tasks() -> Vec<TaskInfo> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().tasks().map(task_info).collect())
+}
+
+pub fn task(slot: usize) -> Option<TaskInfo> {
+    interrupts::without_interrupts(|| SCHEDULER.lock().task(slot).map(task_info))
+}
+
+/// Give up the CPU voluntarily. A FIFO task goes behind the other ready
+/// tasks of its priority.
+pub fn yield_now() {
+    interrupts::without_interrupts(|| {
+        let switch = {
+            let mut s = SCHEDULER.lock();
+            let cur = s.current;
+            if cur != IDLE_TASK {
+                s.make_ready(cur);
+            }
+            s.schedule()
+        };
+        switch_to(switch);
+    });
+}
+
+/// Put the running task to sleep until someone calls `unblock` on it. If
+/// that already happened since the last block, return at once: callers
+/// check their condition, then block, and the wakeup may land in between.
+pub fn block_current() {
+    interrupts::without_interrupts(|| {
+        let switch = {
+            let mut s = SCHEDULER.lock();
+            let cur = s.current;
+            if cur == IDLE_TASK {
+                return; // the idle task must always be runnable
+            }
+            if let Some(t) = s.task_mut(cur) {
+                if t.wake_pending {
+                    t.wake_pending = false;
+                    return;
+                }
+                t.state = TaskState::Blocked;
+            }
+            s.schedule()
+        };
+        switch_to(switch);
+    });
+}
+
+/// Make a blocked task runnable again; a task that is not blocked yet
+/// will skip its next block. Safe to call from interrupt handlers.
+pub fn unblock(slot: usize) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut s = SCHEDULER.lock();
+        match s.task_mut(slot) {
+            Some(t) if t.state == TaskState::Blocked => {
+                s.make_ready(slot);
+                true
+            }
+            Some(t) if t.state != TaskState::Dead => {
+                t.wake_pending = true;
+                false
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Terminate a task. Its stack is reclaimed once another task is running;
+/// if `slot` is the caller, this does not return.
+pub fn task_exit(slot: usize) {
+    if slot == IDLE_TASK {
+        return;
+    }
+    if Some(slot) == current_index() {
+        exit_current();
+    }
+    interrupts::without_interrupts(|| {
+        if let Some(t) = SCHEDULER.lock().task_mut(slot) {
+            t.state = TaskState::Dead;
+        }
+    });
+}
+
+pub fn exit_current() -> ! {
+    interrupts::disable();
+    let switch = {
+        let mut s = SCHEDULER.lock();
+        let cur = s.current;
+        if let Some(t) = s.task_mut(cur) {
+            t.state = TaskState::Dead;
+        }
+        s.schedule()
+    };
+    switch_to(switch);
+    unreachable!("dead task was scheduled again");
+}
+
+pub fn current_index() -> Option<usize> {
+    match CURRENT.load(Ordering::SeqCst) {
+        NO_TASK => None,
+        i => Some(i),
+    }
+}
+
+/// The idle loop: hand the CPU to anything runnable, otherwise `hlt` until
+/// the next interrupt.
+pub fn schedule_loop() -> ! {
+    loop {
+        reap_dead();
+        yield_now();
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Timer interrupt hook: charge the tick to the running task (`user` if it
+/// interrupted ring 3) and preempt it if it is due. Runs with interrupts
+/// disabled; if the scheduler is locked by the interrupted code we skip
+/// this tick instead of deadlocking. Dead tasks are not reaped here (see
+/// `reap_dead`).
+pub fn timer_tick(user: bool) {
+    let switch = match SCHEDULER.try_lock() {
+        Some(mut s) => {
+            if s.tick(user) { s.pick_next() } else { None }
+        }
+        None => None,
+    };
+    switch_to(switch);
+}
+
+/// Interrupt hook: switch right away if the interrupt woke a task that
+/// should preempt the running one, instead of waiting for the next tick.
+pub fn preempt_if_needed() {
+    let switch = match SCHEDULER.try_lock() {
+        Some(mut s) if s.need_resched => s.pick_next(),
+        _ => None,
+    };
+    switch_to(switch);
+}
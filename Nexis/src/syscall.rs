@@ -1,38 +1,374 @@
 // Nexis/src/syscall.rs
 #![no_std]
+//
+// System call table. The calling convention, numbers and error values are
+// defined in `nexis-abi` (Nexis/abi), which user programs share.
+//
+// Pointers are user virtual addresses and are only accessed through
+// `uaccess`, so a bad pointer fails with EFAULT. Every call that fills a
+// buffer takes the buffer's length and never writes past it.
+//
+//   0  console_write(buf, len)                -> bytes written
+//   1  exit(code)                             -> does not return
+//   2  list_files(buf, len)                   -> bytes written ("name\n" per entry of cwd)
+//   3  read_file(path, path_len, buf, len)    -> bytes copied (at most len)
+//   4  getpid()                               -> pid of the caller
+//   5  open(path, path_len, flags, mode)      -> fd
+//   6  close(fd)
+//   7  read(fd, buf, len)                     -> bytes read, 0 at end of file
+//   8  write(fd, buf, len)                    -> bytes written
+//   9  lseek(fd, offset, whence)              -> new offset
+//  10  stat(path, path_len, stat_buf)
+//  11  fstat(fd, stat_buf)
+//  12  dup(fd)                                -> new fd
+//  13  dup2(fd, new_fd)                       -> new_fd
+//  14  getdents(fd, buf, len)                 -> bytes of records, 0 at the end
+//  15  fork()                                 -> child pid; 0 in the child
+//  16  execve(path, path_len, argv, envp)     -> does not return on success
+//  17  waitpid(pid, status_ptr, options)      -> pid of the reaped child, 0 with WNOHANG
+//  18  sleep_ms(ms)
+//  19  nanosleep(req, rem)                    (rem, if given, is always zeroed)
+//  20  nice(inc)                              -> 20 - new nice value
+//  21  getrusage(who, rusage_buf)
+//  22  pipe(fds)                              (fds[0] reads, fds[1] writes; two usizes)
+//
+// Descriptors are per process (fd.rs); 0, 1 and 2 start on the console.
+// fork and execve work on the caller's saved user registers (see
+// syscall_entry.rs), so only user processes can make them.
 
-pub const SYS_WRITE: usize = 0;
-pub const SYS_EXIT: usize = 1;
-pub const SYS_LIST_FILES: usize = 2;
-pub const SYS_READ_FILE: usize = 3;
+use alloc::vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 
-pub fn syscall_handler(num: usize, a1: usize, a2: usize, a3: usize) -> usize {
-    match num {
-        SYS_WRITE => sys_write(a1 as *const u8, a2),
-        SYS_EXIT => sys_exit(a1 as i32),
-        SYS_LIST_FILES => crate::fs::list_files_syscall(a1 as *mut u8, a2),
-        SYS_READ_FILE => crate::fs::read_file_syscall(a1 as *const u8, a2 as usize, a3 as *mut u8),
-        _ => usize::MAX,
+use nexis_abi::process::{exit_status, WAIT_ANY, WNOHANG};
+use nexis_abi::resource::{Rusage, NICE_BIAS, RUSAGE_CHILDREN, RUSAGE_SELF};
+use nexis_abi::time::{Timespec, Timeval};
+
+use crate::errno::{self, SysResult, EINVAL, ENOSYS, ESRCH};
+use crate::fs::{vfs, FsError};
+use crate::fd;
+use crate::uaccess::{copy_from_user, copy_to_user, get_user, put_user, user_str, user_str_array};
+
+pub use nexis_abi::nr::*;
+
+/// Raw argument registers of one call, in ABI order (rdi, rsi, rdx, r10).
+#[derive(Clone, Copy)]
+pub struct Args([usize; 4]);
+
+impl Args {
+    #[inline]
+    fn usize(&self, i: usize) -> usize {
+        self.0[i]
+    }
+
+    #[inline]
+    fn i32(&self, i: usize) -> i32 {
+        self.0[i] as i32
+    }
+
+    #[inline]
+    fn u32(&self, i: usize) -> u32 {
+        self.0[i] as u32
+    }
+
+    #[inline]
+    fn i64(&self, i: usize) -> i64 {
+        self.0[i] as i64
+    }
+}
+
+pub struct Syscall {
+    pub name: &'static str,
+    pub argc: usize,
+    /// Decodes the argument registers and calls the implementation.
+    handler: fn(Args) -> SysResult,
+}
+
+/// Indexed by syscall number.
+static TABLE: [Syscall; NR_SYSCALLS] = [
+    Syscall { name: "console_write", argc: 2, handler: |a| sys_console_write(a.usize(0), a.usize(1)) },
+    Syscall { name: "exit", argc: 1, handler: |a| sys_exit(a.i32(0)) },
+    Syscall { name: "list_files", argc: 2, handler: |a| sys_list_files(a.usize(0), a.usize(1)) },
+    Syscall {
+        name: "read_file",
+        argc: 4,
+        handler: |a| sys_read_file(a.usize(0), a.usize(1), a.usize(2), a.usize(3)),
+    },
+    Syscall { name: "getpid", argc: 0, handler: |_| sys_getpid() },
+    Syscall {
+        name: "open",
+        argc: 4,
+        handler: |a| sys_open(a.usize(0), a.usize(1), a.u32(2), a.u32(3)),
+    },
+    Syscall { name: "close", argc: 1, handler: |a| sys_close(a.usize(0)) },
+    Syscall { name: "read", argc: 3, handler: |a| sys_read(a.usize(0), a.usize(1), a.usize(2)) },
+    Syscall { name: "write", argc: 3, handler: |a| sys_write(a.usize(0), a.usize(1), a.usize(2)) },
+    Syscall { name: "lseek", argc: 3, handler: |a| sys_lseek(a.usize(0), a.i64(1), a.usize(2)) },
+    Syscall { name: "stat", argc: 3, handler: |a| sys_stat(a.usize(0), a.usize(1), a.usize(2)) },
+    Syscall { name: "fstat", argc: 2, handler: |a| sys_fstat(a.usize(0), a.usize(1)) },
+    Syscall { name: "dup", argc: 1, handler: |a| sys_dup(a.usize(0)) },
+    Syscall { name: "dup2", argc: 2, handler: |a| sys_dup2(a.usize(0), a.usize(1)) },
+    Syscall { name: "getdents", argc: 3, handler: |a| sys_getdents(a.usize(0), a.usize(1), a.usize(2)) },
+    Syscall { name: "fork", argc: 0, handler: |_| sys_fork() },
+    Syscall {
+        name: "execve",
+        argc: 4,
+        handler: |a| sys_execve(a.usize(0), a.usize(1), a.usize(2), a.usize(3)),
+    },
+    Syscall { name: "waitpid", argc: 3, handler: |a| sys_waitpid(a.i64(0), a.usize(1), a.usize(2)) },
+    Syscall { name: "sleep_ms", argc: 1, handler: |a| sys_sleep_ms(a.usize(0) as u64) },
+    Syscall { name: "nanosleep", argc: 2, handler: |a| sys_nanosleep(a.usize(0), a.usize(1)) },
+    Syscall { name: "nice", argc: 1, handler: |a| sys_nice(a.i32(0)) },
+    Syscall { name: "getrusage", argc: 2, handler: |a| sys_getrusage(a.i32(0), a.usize(1)) },
+    Syscall { name: "pipe", argc: 1, handler: |a| sys_pipe(a.usize(0)) },
+];
+
+static CALLS: [AtomicU64; NR_SYSCALLS] = [const { AtomicU64::new(0) }; NR_SYSCALLS];
+static UNKNOWN_CALLS: AtomicU64 = AtomicU64::new(0);
+
+/// Largest single write; longer requests are short writes.
+const MAX_WRITE: usize = 4096;
+/// Bounce buffer size for copying file data out to user space.
+const CHUNK: usize = 4096;
+/// Largest single read or write on a descriptor; longer requests are short.
+const MAX_IO: usize = 64 * 1024;
+
+pub fn syscall_handler(num: usize, a1: usize, a2: usize, a3: usize, a4: usize) -> usize {
+    let r = match TABLE.get(num) {
+        Some(call) => {
+            CALLS[num].fetch_add(1, Ordering::Relaxed);
+            (call.handler)(Args([a1, a2, a3, a4]))
+        }
+        None => {
+            UNKNOWN_CALLS.fetch_add(1, Ordering::Relaxed);
+            Err(ENOSYS)
+        }
+    };
+    errno::encode(r)
+}
+
+/// Every syscall with its number and how often it has been invoked.
+pub fn stats() -> impl Iterator<Item = (usize, &'static Syscall, u64)> {
+    TABLE
+        .iter()
+        .enumerate()
+        .map(|(nr, call)| (nr, call, CALLS[nr].load(Ordering::Relaxed)))
+}
+
+/// Calls with a number outside the table.
+pub fn unknown_calls() -> u64 {
+    UNKNOWN_CALLS.load(Ordering::Relaxed)
+}
+
+fn sys_console_write(ptr: usize, len: usize) -> SysResult {
+    let len = len.min(MAX_WRITE);
+    let mut buf = vec![0u8; len];
+    copy_from_user(&mut buf, ptr)?;
+    // Stop short of a character cut off at the end, so the caller's next
+    // write completes it; anything else that is not UTF-8 prints lossily.
+    let n = match core::str::from_utf8(&buf) {
+        Err(e) if e.error_len().is_none() && e.valid_up_to() > 0 => e.valid_up_to(),
+        _ => len,
+    };
+    Ok(crate::chardev::console_write(&buf[..n]))
+}
+
+fn sys_exit(code: i32) -> SysResult {
+    if let Some(pid) = crate::process::current_pid() {
+        crate::process::exit(pid, exit_status(code));
+    }
+    // not a process (or already gone from the table): just end the task
+    crate::scheduler::exit_current()
+}
+
+fn sys_list_files(buf: usize, len: usize) -> SysResult {
+    let entries = vfs::list_dir(".").map_err(FsError::errno)?;
+    let mut written = 0;
+    for e in entries.iter() {
+        for part in [e.name.as_bytes(), b"\n"] {
+            let n = part.len().min(len - written);
+            copy_to_user(buf + written, &part[..n])?;
+            written += n;
+        }
+        if written == len {
+            break;
+        }
+    }
+    Ok(written)
+}
+
+fn sys_read_file(name_ptr: usize, name_len: usize, buf: usize, len: usize) -> SysResult {
+    let name = user_str(name_ptr, name_len)?;
+    let inode = vfs::resolve(&name).map_err(FsError::errno)?.inode;
+    let mut chunk = vec![0u8; len.min(CHUNK)];
+    let mut done = 0;
+    while done < len {
+        let want = chunk.len().min(len - done);
+        let n = inode.read_at(done as u64, &mut chunk[..want]).map_err(FsError::errno)?;
+        if n == 0 {
+            break;
+        }
+        copy_to_user(buf + done, &chunk[..n])?;
+        done += n;
+    }
+    Ok(done)
+}
+
+fn sys_getpid() -> SysResult {
+    crate::process::current_pid().map(|pid| pid as usize).ok_or(ESRCH)
+}
+
+fn sys_open(path_ptr: usize, path_len: usize, flags: u32, mode: u32) -> SysResult {
+    let path = user_str(path_ptr, path_len)?;
+    let file = fd::open(&path, flags, mode)?;
+    fd::with_current(|t| t.insert(file))
+}
+
+fn sys_close(fd: usize) -> SysResult {
+    fd::with_current(|t| t.close(fd)).map(|_| 0)
+}
+
+fn sys_read(fd: usize, buf: usize, len: usize) -> SysResult {
+    let file = fd::current_file(fd)?;
+    let mut kbuf = vec![0u8; len.min(MAX_IO)];
+    let n = file.read(&mut kbuf)?;
+    copy_to_user(buf, &kbuf[..n])?;
+    Ok(n)
+}
+
+fn sys_write(fd: usize, buf: usize, len: usize) -> SysResult {
+    let file = fd::current_file(fd)?;
+    let mut kbuf = vec![0u8; len.min(MAX_IO)];
+    copy_from_user(&mut kbuf, buf)?;
+    file.write(&kbuf)
+}
+
+fn sys_lseek(fd: usize, offset: i64, whence: usize) -> SysResult {
+    fd::current_file(fd)?.seek(offset, whence).map(|off| off as usize)
+}
+
+fn sys_stat(path_ptr: usize, path_len: usize, stat_buf: usize) -> SysResult {
+    let path = user_str(path_ptr, path_len)?;
+    let inode = vfs::resolve(&path).map_err(FsError::errno)?.inode;
+    put_user(stat_buf, &fd::stat_of(&inode))?;
+    Ok(0)
+}
+
+fn sys_fstat(fd: usize, stat_buf: usize) -> SysResult {
+    let st = fd::current_file(fd)?.stat();
+    put_user(stat_buf, &st)?;
+    Ok(0)
+}
+
+fn sys_dup(fd: usize) -> SysResult {
+    fd::with_current(|t| {
+        let file = t.get(fd)?;
+        t.insert(file)
+    })
+}
+
+fn sys_dup2(fd: usize, new_fd: usize) -> SysResult {
+    fd::with_current(|t| t.dup2(fd, new_fd))
+}
+
+fn sys_pipe(fds_ptr: usize) -> SysResult {
+    let (r, w) = fd::OpenFile::pipe();
+    let fds = fd::with_current(|t| {
+        let rfd = t.insert(r)?;
+        match t.insert(w) {
+            Ok(wfd) => Ok([rfd, wfd]),
+            Err(e) => {
+                t.close(rfd)?;
+                Err(e)
+            }
+        }
+    })?;
+    if let Err(e) = put_user(fds_ptr, &fds) {
+        fd::with_current(|t| fds.iter().try_for_each(|&fd| t.close(fd)))?;
+        return Err(e);
     }
+    Ok(0)
 }
 
-fn sys_write(ptr: *const u8, len: usize) -> usize {
-    if ptr.is_null() || len == 0 {
-        return 0;
+fn sys_getdents(fd: usize, buf: usize, len: usize) -> SysResult {
+    let file = fd::current_file(fd)?;
+    let mut kbuf = vec![0u8; len.min(MAX_IO)];
+    let n = file.getdents(&mut kbuf)?;
+    copy_to_user(buf, &kbuf[..n])?;
+    Ok(n)
+}
+
+/// The caller's saved ring-3 registers, if the caller is a user process.
+fn user_frame() -> Result<*mut crate::exceptions::TrapFrame, errno::Errno> {
+    if !crate::scheduler::current_is_user() {
+        return Err(EINVAL);
     }
-    unsafe {
-        let slice = core::slice::from_raw_parts(ptr, len);
-        if let Ok(s) = core::str::from_utf8(slice) {
-            crate::vga::vprint!("{}", s);
-            len
-        } else {
-            0
+    Ok(crate::syscall_entry::user_frame())
+}
+
+fn sys_fork() -> SysResult {
+    let frame = unsafe { &*user_frame()? };
+    crate::process::fork(frame).map(|pid| pid as usize)
+}
+
+fn sys_execve(path_ptr: usize, path_len: usize, argv: usize, envp: usize) -> SysResult {
+    let path = user_str(path_ptr, path_len)?;
+    let argv = user_str_array(argv)?;
+    let envp = user_str_array(envp)?;
+    let frame = unsafe { &mut *user_frame()? };
+    crate::userland::exec(frame, &path, &argv, &envp)?;
+    // the return value lands in the new program's rax; keep it zeroed
+    Ok(0)
+}
+
+fn sys_waitpid(pid: i64, status_ptr: usize, options: usize) -> SysResult {
+    if options & !WNOHANG != 0 {
+        return Err(EINVAL);
+    }
+    let target = match pid as isize {
+        WAIT_ANY => None,
+        p if p > 0 => Some(p as crate::process::Pid),
+        _ => return Err(EINVAL), // no process groups
+    };
+    match crate::process::wait(target, options & WNOHANG != 0)? {
+        Some((child, status)) => {
+            if status_ptr != 0 {
+                put_user(status_ptr, &status)?;
+            }
+            Ok(child as usize)
         }
+        None => Ok(0),
     }
 }
 
-fn sys_exit(_code: i32) -> usize {
-    loop {
-        core::hint::spin_loop();
+fn sys_sleep_ms(ms: u64) -> SysResult {
+    crate::process::sleep_ms(ms);
+    Ok(0)
+}
+
+fn sys_nanosleep(req: usize, rem: usize) -> SysResult {
+    let ts: Timespec = get_user(req)?;
+    if !ts.is_valid() {
+        return Err(EINVAL);
     }
-}
\ No newline at end of file
+    crate::process::sleep_ms(ts.as_millis_ceil());
+    if rem != 0 {
+        put_user(rem, &Timespec::default())?;
+    }
+    Ok(0)
+}
+
+fn sys_nice(inc: i32) -> SysResult {
+    let nice = crate::scheduler::renice_current(inc);
+    Ok((NICE_BIAS - nice as i32) as usize)
+}
+
+fn sys_getrusage(who: i32, buf: usize) -> SysResult {
+    let times = match who {
+        RUSAGE_SELF => crate::scheduler::current_times(),
+        RUSAGE_CHILDREN => crate::process::current().ok_or(ESRCH)?.child_times,
+        _ => return Err(EINVAL),
+    };
+    let tv = |ticks| Timeval::from_micros(crate::pit::ticks_to_us(ticks));
+    put_user(buf, &Rusage { ru_utime: tv(times.user), ru_stime: tv(times.system) })?;
+    Ok(0)
+}
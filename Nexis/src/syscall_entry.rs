@@ -0,0 +1,193 @@
+// Nexis/src/syscall_entry.rs
+//
+// The two ways into the syscall table: the SYSCALL instruction and the
+// legacy `int 0x80` gate. Both entry stubs save every general purpose
+// register into a `TrapFrame` before any Rust code runs, so the arguments
+// are read from the frame rather than from whatever the compiler left in
+// the registers, and the result goes back in the saved rax.
+//
+// SYSCALL does not switch stacks. The stub uses `swapgs` to reach the
+// per-CPU block below, which holds the running task's kernel stack, and
+// swaps back before calling into Rust. The kernel never relies on GS
+// otherwise, so KernelGsBase always points at `PERCPU` and exception and
+// interrupt entries need no `swapgs` of their own.
+//
+// Either way the frame sits at the very top of the task's kernel stack,
+// which is how `fork` and `execve` find the registers to copy or replace
+// (`user_frame`), and `trap_return` leaves the kernel through such a frame.
+
+use core::arch::global_asm;
+use core::mem::size_of;
+use core::ptr::addr_of;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, KernelGsBase, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+use crate::exceptions::TrapFrame;
+
+pub const SYSCALL_VECTOR: usize = 0x80;
+
+/// Per-CPU data reached through GS during SYSCALL entry. Field offsets are
+/// hardcoded in the stub below.
+#[repr(C)]
+struct PerCpu {
+    /// Top of the running task's kernel stack (offset 0).
+    kernel_rsp: u64,
+    /// User rsp, stashed while switching stacks (offset 8).
+    user_rsp: u64,
+}
+
+static mut PERCPU: PerCpu = PerCpu { kernel_rsp: 0, user_rsp: 0 };
+
+global_asm!(
+    r#"
+    .macro SAVE_REGS
+        push rax
+        push rbx
+        push rcx
+        push rdx
+        push rsi
+        push rdi
+        push rbp
+        push r8
+        push r9
+        push r10
+        push r11
+        push r12
+        push r13
+        push r14
+        push r15
+    .endm
+
+    .macro RESTORE_REGS
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rbp
+        pop rdi
+        pop rsi
+        pop rdx
+        pop rcx
+        pop rbx
+        pop rax
+    .endm
+
+    .section .text
+    .global syscall_entry
+    syscall_entry:
+        // rcx = user rip, r11 = user rflags, IF and DF cleared by SFMASK
+        swapgs
+        mov qword ptr gs:[8], rsp
+        mov rsp, qword ptr gs:[0]
+        // build the same frame an interrupt from ring 3 would
+        push 0x1B
+        push qword ptr gs:[8]
+        push r11
+        push 0x23
+        push rcx
+        swapgs
+        push 0
+        push 0x80
+        SAVE_REGS
+        mov rdi, rsp
+        call syscall_trap
+        RESTORE_REGS
+        add rsp, 16
+        cli
+        // SYSRET with a non-canonical rip would fault in ring 0 on the
+        // user stack; let iretq raise that fault in ring 3 instead
+        mov rcx, qword ptr [rsp]
+        bt rcx, 47
+        jc 1f
+        mov r11, qword ptr [rsp + 16]
+        mov rsp, qword ptr [rsp + 24]
+        sysretq
+    1:
+        iretq
+
+    .global int80_entry
+    int80_entry:
+        push 0
+        push 0x80
+        SAVE_REGS
+        mov rdi, rsp
+        cld
+        call syscall_trap
+        RESTORE_REGS
+        add rsp, 16
+        iretq
+
+    .global trap_return
+    trap_return:
+        // rdi = frame to restore, anywhere on the current kernel stack
+        cli
+        mov rsp, rdi
+        RESTORE_REGS
+        add rsp, 16
+        iretq
+    "#
+);
+
+extern "C" {
+    fn syscall_entry();
+    fn int80_entry();
+    fn trap_return(frame: *const TrapFrame) -> !;
+}
+
+#[no_mangle]
+extern "C" fn syscall_trap(frame: *mut TrapFrame) {
+    // both gates enter with interrupts off; syscalls may block or run long
+    interrupts::enable();
+    // read a copy: fork and execve reach the same frame through `user_frame`
+    let regs = unsafe { frame.read() };
+    let ret = crate::syscall::syscall_handler(
+        regs.rax as usize,
+        regs.rdi as usize,
+        regs.rsi as usize,
+        regs.rdx as usize,
+        regs.r10 as usize,
+    );
+    interrupts::disable();
+    unsafe { (*frame).rax = ret as u64 };
+}
+
+/// The frame the running task entered the kernel with from ring 3. Only
+/// meaningful while handling a syscall or trap from a user task.
+pub fn user_frame() -> *mut TrapFrame {
+    (crate::gdt::kernel_stack() - size_of::<TrapFrame>()) as *mut TrapFrame
+}
+
+/// Leave for ring 3 with the registers in `frame`, which must be a user
+/// frame living on the current kernel stack.
+pub fn return_to_user(frame: &TrapFrame) -> ! {
+    unsafe { trap_return(frame) }
+}
+
+/// Route `int 0x80` from ring 3 to the syscall table.
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    let entry = unsafe { idt[SYSCALL_VECTOR].set_handler_addr(VirtAddr::new(int80_entry as *const () as u64)) };
+    entry.set_privilege_level(PrivilegeLevel::Ring3);
+}
+
+/// Enable SYSCALL/SYSRET. Must run after `gdt::init`.
+pub fn init() {
+    let sel = crate::gdt::selectors();
+    Star::write(sel.user_code, sel.user_data, sel.kernel_code, sel.kernel_data)
+        .expect("GDT layout is not SYSRET compatible");
+    LStar::write(VirtAddr::new(syscall_entry as *const () as u64));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    KernelGsBase::write(VirtAddr::from_ptr(addr_of!(PERCPU)));
+    unsafe { Efer::update(|f| f.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Stack the SYSCALL stub switches to; kept in step with TSS RSP0.
+pub fn set_kernel_stack(top: usize) {
+    unsafe { PERCPU.kernel_rsp = top as u64 };
+}
@@ -1,3 +1,203 @@
+use alloc::vec::Vec;
+use core::arch::global_asm;
+
+use crate::exceptions::TrapFrame;
+use crate::memory::PhysFrame;
+use crate::vmm::{AddressSpace, PageFlags, VirtualMemoryManager, PAGE_SIZE};
+
+/// Kernel stacks live in their own window, one fixed-size slot per task.
+/// The lowest page of each slot is left unmapped as a guard page.
+pub const KERNEL_STACKS_START: usize = 0xFFFF_A000_0000_0000;
+pub const MAX_STACK_PAGES: usize = 64;
+const STACK_SLOT_SIZE: usize = (MAX_STACK_PAGES + 1) * PAGE_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    /// Slot reserved while `spawn` sets up the stack; never scheduled.
+    Starting,
+    Ready,
+    Running,
+    Blocked,
+    Dead,
+}
+
+/// How the scheduler picks between runnable tasks (see scheduler.rs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Share the CPU in proportion to the weight of the task's nice level.
+    Fair,
+    /// Real time, priority 1..=99: runs before every fair task and keeps the
+    /// CPU until it blocks, yields or a higher-priority FIFO task is ready.
+    Fifo(u8),
+}
+
+impl SchedPolicy {
+    pub const MAX_RT_PRIORITY: u8 = 99;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchedPolicy::Fair => "fair",
+            SchedPolicy::Fifo(_) => "fifo",
+        }
+    }
+}
+
+/// CPU time consumed, in timer ticks, split by the mode the CPU was in
+/// when the tick landed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: u64,
+    pub system: u64,
+}
+
+impl CpuTimes {
+    pub fn add(&mut self, other: CpuTimes) {
+        self.user += other.user;
+        self.system += other.system;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.user + self.system
+    }
+}
+
+/// A mapped kernel stack and the PMM frames that back it.
+pub struct KernelStack {
+    bottom: usize,
+    frames: Vec<PhysFrame>,
+}
+
+impl KernelStack {
+    pub fn alloc(vmm: &VirtualMemoryManager, slot: usize, pages: usize) -> Option<Self> {
+        if pages == 0 || pages > MAX_STACK_PAGES {
+            return None;
+        }
+        let slot_top = KERNEL_STACKS_START + (slot + 1) * STACK_SLOT_SIZE;
+        let bottom = slot_top - pages * PAGE_SIZE;
+        let mut stack = KernelStack { bottom, frames: Vec::with_capacity(pages) };
+        for i in 0..pages {
+            let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+            match vmm.map_zeroed(vmm.kernel_space(), bottom + i * PAGE_SIZE, flags) {
+                Ok(frame) => stack.frames.push(frame),
+                Err(_) => {
+                    stack.free(vmm);
+                    return None;
+                }
+            }
+        }
+        Some(stack)
+    }
+
+    #[inline]
+    pub fn bottom(&self) -> usize {
+        self.bottom
+    }
+
+    #[inline]
+    pub fn top(&self) -> usize {
+        self.bottom + self.frames.len() * PAGE_SIZE
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// Unmap the stack and give its frames back to the PMM.
+    pub fn free(self, vmm: &VirtualMemoryManager) {
+        for i in 0..self.frames.len() {
+            if let Ok(frame) = vmm.unmap(vmm.kernel_space(), self.bottom + i * PAGE_SIZE) {
+                vmm.pmm().free_frame(frame.0);
+            }
+        }
+    }
+}
+
+pub struct Task {
+    pub id: usize,
+    pub stack_pointer: usize,
+    /// `None` for the boot context, which runs on the bootloader's stack.
+    pub stack: Option<KernelStack>,
+    pub state: TaskState,
+    pub policy: SchedPolicy,
+    /// -20 (most CPU) to 19 (least); only matters under `SchedPolicy::Fair`.
+    pub nice: i8,
+    /// Weighted CPU time used so far; the fair policy runs the task with
+    /// the least.
+    pub vruntime: u64,
+    /// When the task last became ready, for first-in first-out order among
+    /// FIFO tasks of equal priority.
+    pub ready_seq: u64,
+    pub times: CpuTimes,
+    pub name: [u8; 16],
+    /// User address space; `None` for kernel tasks, which run on the kernel's.
+    pub address_space: Option<AddressSpace>,
+    /// Initial user (rip, rsp) for tasks started through `enter_user`.
+    pub user_entry: Option<(usize, usize)>,
+    /// Registers a forked child returns to user mode with.
+    pub resume_frame: Option<TrapFrame>,
+    /// An `unblock` arrived while the task was not blocked; its next
+    /// `block_current` returns at once instead of sleeping through it.
+    pub wake_pending: bool,
+}
+
+impl Task {
+    pub fn new(id: usize, stack: Option<KernelStack>, name: &str) -> Self {
+        let mut task = Self {
+            id,
+            stack_pointer: 0,
+            stack,
+            state: TaskState::Ready,
+            policy: SchedPolicy::Fair,
+            nice: 0,
+            vruntime: 0,
+            ready_seq: 0,
+            times: CpuTimes::default(),
+            name: [0u8; 16],
+            address_space: None,
+            user_entry: None,
+            resume_frame: None,
+            wake_pending: false,
+        };
+        task.set_name(name);
+        task
+    }
+
+    /// Set the name, truncated to 16 bytes.
+    pub fn set_name(&mut self, name: &str) {
+        let len = name.len().min(self.name.len());
+        self.name = [0u8; 16];
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
+    pub fn name_str(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
+// First code a new task runs: `context_switch` pops the prepared registers
+// and returns here with the entry point in rbx.
+global_asm!(
+    r#"
+    .global task_trampoline
+    task_trampoline:
+        sti
+        call rbx
+        call task_exit_current
+        ud2
+    "#
+);
+
+extern "C" {
+    fn task_trampoline();
+}
+
+#[no_mangle]
+extern "C" fn task_exit_current() -> ! {
+    crate::scheduler::exit_current()
+}
+
+/// Build the initial frame `context_switch` expects on a fresh stack.
 #[inline(always)]
 pub fn prepare_stack(entry: extern "C" fn(), stack_base: usize, stack_size: usize) -> usize {
     // Start at top of stack, align to 16 bytes
@@ -6,14 +206,18 @@ pub fn prepare_stack(entry: extern "C" fn(), stack_base: usize, stack_size: usiz
     unsafe {
         // Return address (what "ret" will jump to)
         sp -= core::mem::size_of::<usize>();
-        (sp as *mut usize).write_volatile(entry as usize);
+        (sp as *mut usize).write_volatile(task_trampoline as *const () as usize);
 
         // RBP (frame pointer)
         sp -= core::mem::size_of::<usize>();
         (sp as *mut usize).write_volatile(0);
 
-        // Callee-saved registers (rbx, r12, r13, r14, r15)
-        for _ in 0..5 {
+        // RBX carries the entry point into the trampoline
+        sp -= core::mem::size_of::<usize>();
+        (sp as *mut usize).write_volatile(entry as usize);
+
+        // Remaining callee-saved registers (r12, r13, r14, r15)
+        for _ in 0..4 {
             sp -= core::mem::size_of::<usize>();
             (sp as *mut usize).write_volatile(0);
         }
@@ -21,4 +225,3 @@ pub fn prepare_stack(entry: extern "C" fn(), stack_base: usize, stack_size: usiz
 
     sp
 }
-
@@ -0,0 +1,132 @@
+// Nexis/src/uaccess.rs
+//
+// The only sanctioned way for syscalls to touch user memory. Every range is
+// checked against the caller's address space page by page (present, user
+// accessible, writable for stores) and then copied through the kernel's
+// physical-memory window, so a bad pointer yields EFAULT instead of a
+// kernel page fault. Stores to copy-on-write pages break the sharing first.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr;
+
+use nexis_abi::process::{StrRef, MAX_ARGS, MAX_ARG_BYTES};
+
+use crate::errno::{Errno, E2BIG, EFAULT, EINVAL, ENAMETOOLONG};
+use crate::memory::phys_to_virt;
+use crate::vmm::{AddressSpace, PageFlags, PAGE_SIZE, USER_SPACE_END};
+
+/// Longest path or name accepted from user space.
+pub const MAX_USER_STR: usize = 256;
+
+fn check_range(addr: usize, len: usize) -> Result<(), Errno> {
+    let end = addr.checked_add(len).ok_or(EFAULT)?;
+    if addr == 0 || end > USER_SPACE_END {
+        return Err(EFAULT);
+    }
+    Ok(())
+}
+
+/// Walk `[addr, addr+len)` in page-sized pieces, resolving each to a kernel
+/// pointer after checking the page flags.
+fn for_each_chunk(
+    addr: usize,
+    len: usize,
+    write: bool,
+    mut f: impl FnMut(*mut u8, usize, usize),
+) -> Result<(), Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+    check_range(addr, len)?;
+    let vmm = crate::vmm::kernel_vmm();
+    let space = AddressSpace::current();
+    let mut done = 0;
+    while done < len {
+        let va = addr + done;
+        let chunk = (PAGE_SIZE - va % PAGE_SIZE).min(len - done);
+        let (mut pa, flags) = vmm.lookup(space, va).ok_or(EFAULT)?;
+        if !flags.contains(PageFlags::USER) {
+            return Err(EFAULT);
+        }
+        if write && !flags.contains(PageFlags::WRITABLE) {
+            // copy-on-write pages get their private copy now, as a user
+            // store would have
+            if vmm.break_cow(space, va) != Ok(true) {
+                return Err(EFAULT);
+            }
+            pa = vmm.translate(space, va).ok_or(EFAULT)?;
+        }
+        f(phys_to_virt(pa) as *mut u8, done, chunk);
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// Copy `dst.len()` bytes from user address `src`.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), Errno> {
+    let out = dst.as_mut_ptr();
+    for_each_chunk(src, dst.len(), false, |k, off, n| unsafe {
+        ptr::copy_nonoverlapping(k as *const u8, out.add(off), n)
+    })
+}
+
+/// Copy `src` to user address `dst`.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), Errno> {
+    let inp = src.as_ptr();
+    for_each_chunk(dst, src.len(), true, |k, off, n| unsafe {
+        ptr::copy_nonoverlapping(inp.add(off), k, n)
+    })
+}
+
+/// Copy a plain `#[repr(C)]` value, such as `Stat`, to user address `dst`.
+pub fn put_user<T: Copy>(dst: usize, val: &T) -> Result<(), Errno> {
+    let bytes = unsafe { core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst, bytes)
+}
+
+/// Read a plain `#[repr(C)]` value, such as `Timespec`, from user address
+/// `src`.
+pub fn get_user<T: Copy + Default>(src: usize) -> Result<T, Errno> {
+    let mut val = T::default();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(&mut val as *mut T as *mut u8, size_of::<T>()) };
+    copy_from_user(bytes, src)?;
+    Ok(val)
+}
+
+/// Copy a `(ptr, len)` UTF-8 string such as a file name from user space.
+pub fn user_str(ptr: usize, len: usize) -> Result<String, Errno> {
+    if len > MAX_USER_STR {
+        return Err(ENAMETOOLONG);
+    }
+    let mut buf = vec![0u8; len];
+    copy_from_user(&mut buf, ptr)?;
+    String::from_utf8(buf).map_err(|_| EINVAL)
+}
+
+/// Copy an array of `StrRef`s ending in a null `ptr` (argv, envp) from
+/// user space. A null `ptr` for the array itself is an empty list.
+pub fn user_str_array(ptr: usize) -> Result<Vec<String>, Errno> {
+    let mut out = Vec::new();
+    if ptr == 0 {
+        return Ok(out);
+    }
+    let mut total = 0;
+    for i in 0..=MAX_ARGS {
+        let r: StrRef = get_user(ptr + i * size_of::<StrRef>())?;
+        if r.ptr == 0 {
+            return Ok(out);
+        }
+        total += r.len.saturating_add(1);
+        if i == MAX_ARGS || total > MAX_ARG_BYTES {
+            return Err(E2BIG);
+        }
+        // bounded by MAX_ARG_BYTES rather than MAX_USER_STR
+        let mut buf = vec![0u8; r.len];
+        copy_from_user(&mut buf, r.ptr)?;
+        out.push(String::from_utf8(buf).map_err(|_| EINVAL)?);
+    }
+    Err(E2BIG)
+}
@@ -1,32 +1,306 @@
-pub fn write(s: &str) -> usize {
-    unsafe {
-        let ptr = s.as_ptr();
-        let len = s.len();
-        let ret: usize;
-        asm!(
-            "mov rax, 0",
-            "mov rdi, {0}",
-            "mov rsi, {1}",
-            "mov rdx, 0",
-            "int 0x80",
-            in(reg) ptr,
-            in(reg) len,
-            out("rax") ret
-        );
-        ret
-    }
-}
-
-pub fn exit(code: i32) -> ! {
-    unsafe {
-        asm!(
-            "mov rax, 1",
-            "mov rdi, {0}",
-            "mov rsi, 0",
-            "mov rdx, 0",
-            "int 0x80",
-            in(reg) code
-        );
-        core::hint::unreachable_unchecked();
-    }
-}
\ No newline at end of file
+// Nexis/src/userland.rs
+//
+// Loading ELF images into fresh user address spaces and entering them in
+// ring 3. The `userland` crate is embedded at build time (see build.rs).
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+
+use nexis_abi::process::MAX_ARG_BYTES;
+
+use crate::elf::{ElfError, ElfFile, PF_W, PF_X, PT_LOAD};
+use crate::memory::{phys_to_virt, PhysFrame};
+use crate::errno::{Errno, E2BIG, EAGAIN, EFAULT, ENOEXEC, ENOMEM};
+use crate::exceptions::TrapFrame;
+use crate::process::Pid;
+use crate::vmm::{AddressSpace, MapError, PageFlags, VirtualMemoryManager, PAGE_SIZE, USER_SPACE_END};
+
+/// The `userland` demo binary, or empty if it was not built.
+pub static USERLAND_ELF: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/userland.elf"));
+
+/// Top of the initial user stack; the page below the stack stays unmapped.
+pub const USER_STACK_TOP: usize = 0x0000_7FFF_FFFF_F000;
+pub const USER_STACK_PAGES: usize = 16;
+
+/// Program run as pid 1 unless the command line says `init=<path>`.
+pub const DEFAULT_INIT: &str = "/sbin/init";
+
+/// Pages a user binary may not touch: keep null dereferences faulting.
+const USER_MIN_ADDR: usize = 0x1000;
+
+extern "C" {
+    /// asm/switch.S: iretq to `rip` in ring 3 with stack `rsp`.
+    fn enter_user(rip: usize, rsp: usize) -> !;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    Map(MapError),
+    BadSegment,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(e: ElfError) -> Self {
+        LoadError::Elf(e)
+    }
+}
+
+impl From<MapError> for LoadError {
+    fn from(e: MapError) -> Self {
+        LoadError::Map(e)
+    }
+}
+
+/// A loaded program, ready to be entered.
+pub struct UserImage {
+    pub space: AddressSpace,
+    pub entry: usize,
+    pub stack_top: usize,
+}
+
+fn segment_flags(elf_flags: u32) -> PageFlags {
+    let mut flags = PageFlags::USER;
+    if elf_flags & PF_W != 0 {
+        flags = flags | PageFlags::WRITABLE;
+    }
+    if elf_flags & PF_X == 0 {
+        flags = flags | PageFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// Map every PT_LOAD segment of `image` into a new address space, plus a stack.
+pub fn load_elf(vmm: &VirtualMemoryManager, image: &[u8]) -> Result<UserImage, LoadError> {
+    let elf = ElfFile::parse(image)?;
+    let space = vmm.new_address_space()?;
+    match load_into(vmm, space, &elf) {
+        Ok(stack_top) => Ok(UserImage { space, entry: elf.entry, stack_top }),
+        Err(e) => {
+            unsafe { vmm.destroy_address_space(space) };
+            Err(e)
+        }
+    }
+}
+
+fn load_into(vmm: &VirtualMemoryManager, space: AddressSpace, elf: &ElfFile) -> Result<usize, LoadError> {
+    for ph in elf.program_headers().filter(|ph| ph.p_type == PT_LOAD && ph.memsz > 0) {
+        let end = ph.vaddr.checked_add(ph.memsz).ok_or(LoadError::BadSegment)?;
+        if ph.vaddr < USER_MIN_ADDR || end > USER_STACK_TOP - (USER_STACK_PAGES + 1) * PAGE_SIZE {
+            return Err(LoadError::BadSegment);
+        }
+        if ph.filesz > ph.memsz || ph.flags & (PF_W | PF_X) == PF_W | PF_X {
+            return Err(LoadError::BadSegment);
+        }
+        let data = elf.segment_data(&ph).ok_or(LoadError::BadSegment)?;
+        let flags = segment_flags(ph.flags);
+
+        let mut page = ph.vaddr & !(PAGE_SIZE - 1);
+        while page < end {
+            let frame = map_segment_page(vmm, space, page, flags)?;
+
+            // copy the part of the file image that lands on this page
+            let copy_start = page.max(ph.vaddr);
+            let copy_end = (page + PAGE_SIZE).min(ph.vaddr + ph.filesz);
+            if copy_start < copy_end {
+                let src = &data[copy_start - ph.vaddr..copy_end - ph.vaddr];
+                let dst = phys_to_virt(frame.0) + (copy_start - page);
+                unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len()) };
+            }
+            page += PAGE_SIZE;
+        }
+    }
+
+    let stack_flags = PageFlags::USER | PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+    for i in 1..=USER_STACK_PAGES {
+        vmm.map_zeroed(space, USER_STACK_TOP - i * PAGE_SIZE, stack_flags)?;
+    }
+    Ok(USER_STACK_TOP)
+}
+
+/// Map a fresh page, or widen the permissions of one shared with the
+/// previous segment (segments need not be page aligned). A shared page
+/// that would end up both writable and executable is refused.
+fn map_segment_page(
+    vmm: &VirtualMemoryManager,
+    space: AddressSpace,
+    page: usize,
+    flags: PageFlags,
+) -> Result<PhysFrame, LoadError> {
+    match vmm.map_zeroed(space, page, flags) {
+        Err(MapError::AlreadyMapped) => {
+            let (pa, old) = vmm.lookup(space, page).ok_or(MapError::NotMapped)?;
+            let mut merged = old.without(PageFlags::NO_EXECUTE) | flags.without(PageFlags::NO_EXECUTE);
+            if old.contains(PageFlags::NO_EXECUTE) && flags.contains(PageFlags::NO_EXECUTE) {
+                merged = merged | PageFlags::NO_EXECUTE;
+            }
+            if merged.contains(PageFlags::WRITABLE) && !merged.contains(PageFlags::NO_EXECUTE) {
+                return Err(LoadError::BadSegment);
+            }
+            vmm.protect(space, page, merged)?;
+            Ok(PhysFrame(pa))
+        }
+        other => Ok(other?),
+    }
+}
+
+/// Kernel-side entry of a user task: jump to ring 3 and never come back
+/// (the process leaves through `sys_exit` or a fault).
+pub extern "C" fn user_task_start() {
+    let entry = crate::scheduler::current_user_entry();
+    match entry {
+        Some((rip, rsp)) if rip < USER_SPACE_END && rsp <= USER_SPACE_END => unsafe { enter_user(rip, rsp) },
+        _ => crate::vga::vprintln!("user task started without a user entry point"),
+    }
+}
+
+/// Kernel-side entry of a forked child: return to ring 3 with the
+/// registers the parent had at `fork`, rax aside.
+pub extern "C" fn fork_child_start() {
+    match crate::scheduler::take_resume_frame() {
+        // copy the frame onto this task's own stack before leaving with it
+        Some(frame) => crate::syscall_entry::return_to_user(&frame),
+        None => crate::vga::vprintln!("forked task started without a frame"),
+    }
+}
+
+/// Copy `bytes` to `addr` in `space`, which must be mapped.
+fn write_user(vmm: &VirtualMemoryManager, space: AddressSpace, addr: usize, bytes: &[u8]) -> Result<(), Errno> {
+    let mut done = 0;
+    while done < bytes.len() {
+        let at = addr + done;
+        let n = (PAGE_SIZE - at % PAGE_SIZE).min(bytes.len() - done);
+        let pa = vmm.translate(space, at).ok_or(EFAULT)?;
+        unsafe { ptr::copy_nonoverlapping(bytes[done..].as_ptr(), phys_to_virt(pa) as *mut u8, n) };
+        done += n;
+    }
+    Ok(())
+}
+
+/// Lay out the initial stack the SysV ABI describes below `top`: argc,
+/// the argv and envp pointer arrays (each NULL-terminated) and an empty
+/// auxiliary vector, with the strings themselves above. Returns the new
+/// stack pointer and the addresses of argv and envp.
+fn push_args(
+    vmm: &VirtualMemoryManager,
+    space: AddressSpace,
+    top: usize,
+    argv: &[String],
+    envp: &[String],
+) -> Result<(usize, usize, usize), Errno> {
+    let mut sp = top;
+    let mut ptrs: Vec<u64> = Vec::with_capacity(argv.len() + envp.len() + 5);
+    ptrs.push(argv.len() as u64);
+    for (i, s) in argv.iter().chain(envp.iter()).enumerate() {
+        if i == argv.len() {
+            ptrs.push(0);
+        }
+        sp -= s.len() + 1;
+        write_user(vmm, space, sp, s.as_bytes())?;
+        write_user(vmm, space, sp + s.len(), &[0])?;
+        ptrs.push(sp as u64);
+    }
+    if envp.is_empty() {
+        ptrs.push(0);
+    }
+    ptrs.extend_from_slice(&[0, 0, 0]); // envp terminator, AT_NULL
+
+    // rsp must be 16-byte aligned at the entry point, pointing at argc
+    sp = (sp - ptrs.len() * 8) & !15;
+    let words: Vec<u8> = ptrs.iter().flat_map(|w| w.to_le_bytes()).collect();
+    write_user(vmm, space, sp, &words)?;
+    let argv_at = sp + 8;
+    Ok((sp, argv_at, argv_at + (argv.len() + 1) * 8))
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Load the executable at `path`, with errors as `execve` reports them.
+fn load_file(vmm: &VirtualMemoryManager, path: &str) -> Result<UserImage, Errno> {
+    let image = crate::fs::vfs::read_file(path).map_err(crate::fs::FsError::errno)?;
+    load_elf(vmm, &image).map_err(|e| match e {
+        LoadError::Map(_) => ENOMEM,
+        LoadError::Elf(_) | LoadError::BadSegment => ENOEXEC,
+    })
+}
+
+/// Replace the calling process's program with the ELF file at `path`.
+/// On success `frame` is rewritten to enter the new program; on failure
+/// the old program is left untouched.
+pub fn exec(frame: &mut TrapFrame, path: &str, argv: &[String], envp: &[String]) -> Result<(), Errno> {
+    let strings: usize = argv.iter().chain(envp.iter()).map(|s| s.len() + 1).sum();
+    if strings > MAX_ARG_BYTES {
+        return Err(E2BIG);
+    }
+    let vmm = crate::vmm::kernel_vmm();
+    let loaded = load_file(vmm, path)?;
+    let (sp, argv_at, envp_at) = match push_args(vmm, loaded.space, loaded.stack_top, argv, envp) {
+        Ok(v) => v,
+        Err(e) => {
+            unsafe { vmm.destroy_address_space(loaded.space) };
+            return Err(e);
+        }
+    };
+
+    // past the point of no return
+    if let Some(old) = crate::scheduler::replace_address_space(loaded.space) {
+        unsafe { vmm.destroy_address_space(old) };
+    }
+    let name = basename(path);
+    crate::scheduler::set_current_name(name);
+    if let Some(pid) = crate::process::current_pid() {
+        crate::process::set_name(pid, name);
+    }
+
+    let (cs, ss) = (frame.cs, frame.ss);
+    *frame = TrapFrame {
+        rip: loaded.entry as u64,
+        cs,
+        rflags: 0x202,
+        rsp: sp as u64,
+        ss,
+        rdi: argv.len() as u64,
+        rsi: argv_at as u64,
+        rdx: envp_at as u64,
+        ..TrapFrame::default()
+    };
+    Ok(())
+}
+
+/// Load `image` and start it as a new process.
+pub fn spawn_image(name: &str, image: &[u8], parent: Option<Pid>) -> Result<Pid, LoadError> {
+    let vmm = crate::vmm::kernel_vmm();
+    let loaded = load_elf(vmm, image)?;
+    match crate::process::spawn_user(name, loaded, parent) {
+        Some(pid) => Ok(pid),
+        None => Err(LoadError::Map(MapError::OutOfFrames)),
+    }
+}
+
+/// Start the program at `path` as init, pid 1.
+pub fn spawn_init(path: &str) -> Result<Pid, Errno> {
+    let vmm = crate::vmm::kernel_vmm();
+    let loaded = load_file(vmm, path)?;
+    crate::process::spawn_init(basename(path), loaded).ok_or(EAGAIN)
+}
+
+/// Start the embedded `userland` demo, if the kernel was built with one.
+pub fn spawn_demo() -> Option<Pid> {
+    if USERLAND_ELF.is_empty() {
+        crate::vga::vprintln!("Userland: no image embedded (build Nexis/userland first)");
+        return None;
+    }
+    match spawn_image("userland", USERLAND_ELF, None) {
+        Ok(pid) => {
+            crate::vga::vprintln!("Userland: started pid {}", pid);
+            Some(pid)
+        }
+        Err(e) => {
+            crate::vga::vprintln!("Userland: load failed: {:?}", e);
+            None
+        }
+    }
+}
@@ -58,6 +58,12 @@ impl VgaWriter {
         }
     }
 
+    /// The text buffer as seen through the physical-memory window.
+    #[inline]
+    fn buf(&self) -> *mut u8 {
+        (self.buffer as usize + crate::memory::phys_mem_offset()) as *mut u8
+    }
+
     pub fn put_char(&mut self, c: char) {
         match c {
             '\n' => { self.new_line(); return; }
@@ -67,12 +73,16 @@ impl VgaWriter {
         if self.column >= BUFFER_WIDTH { self.new_line(); }
         let offset = (self.row * BUFFER_WIDTH + self.column) * 2;
         unsafe {
-            core::ptr::write_volatile(self.buffer.add(offset), c as u8);
-            core::ptr::write_volatile(self.buffer.add(offset + 1), self.color);
+            core::ptr::write_volatile(self.buf().add(offset), c as u8);
+            core::ptr::write_volatile(self.buf().add(offset + 1), self.color);
         }
         self.column += 1;
     }
 
+    pub fn set_color(&mut self, color: u8) {
+        self.color = color;
+    }
+
     pub fn write_str(&mut self, s: &str) {
         for c in s.chars() { self.put_char(c); }
     }
@@ -88,10 +98,10 @@ impl VgaWriter {
                     let src = ((r * BUFFER_WIDTH) + col) * 2;
                     let dst = (((r - 1) * BUFFER_WIDTH) + col) * 2;
                     unsafe {
-                        let ch = core::ptr::read_volatile(self.buffer.add(src));
-                        let color = core::ptr::read_volatile(self.buffer.add(src + 1));
-                        core::ptr::write_volatile(self.buffer.add(dst), ch);
-                        core::ptr::write_volatile(self.buffer.add(dst + 1), color);
+                        let ch = core::ptr::read_volatile(self.buf().add(src));
+                        let color = core::ptr::read_volatile(self.buf().add(src + 1));
+                        core::ptr::write_volatile(self.buf().add(dst), ch);
+                        core::ptr::write_volatile(self.buf().add(dst + 1), color);
                     }
                 }
             }
@@ -99,8 +109,8 @@ impl VgaWriter {
             let last = (BUFFER_HEIGHT - 1) * BUFFER_WIDTH * 2;
             for col in 0..BUFFER_WIDTH {
                 unsafe {
-                    core::ptr::write_volatile(self.buffer.add(last + col * 2), b' ');
-                    core::ptr::write_volatile(self.buffer.add(last + col * 2 + 1), self.color);
+                    core::ptr::write_volatile(self.buf().add(last + col * 2), b' ');
+                    core::ptr::write_volatile(self.buf().add(last + col * 2 + 1), self.color);
                 }
             }
         }
@@ -111,8 +121,8 @@ impl VgaWriter {
             for c in 0..BUFFER_WIDTH {
                 let offset = (r * BUFFER_WIDTH + c) * 2;
                 unsafe {
-                    core::ptr::write_volatile(self.buffer.add(offset), b' ');
-                    core::ptr::write_volatile(self.buffer.add(offset + 1), self.color);
+                    core::ptr::write_volatile(self.buf().add(offset), b' ');
+                    core::ptr::write_volatile(self.buf().add(offset + 1), self.color);
                 }
             }
         }
@@ -129,6 +139,11 @@ impl core::fmt::Write for VgaWriter {
 }
 
 // ---- VGA + Serial printing ----
+pub fn vprint_impl(args: core::fmt::Arguments) {
+    let _ = VGA_WRITER.lock().write_fmt(args);
+    serial_print(args);
+}
+
 pub fn vprintln_impl(args: core::fmt::Arguments) {
     {
         let mut v = VGA_WRITER.lock();
@@ -143,11 +158,7 @@ macro_rules! vprintln {
     ($($arg:tt)*) => (crate::vga::vprintln_impl(format_args!($($arg)*)));
 }
 macro_rules! vprint {
-    ($($arg:tt)*) => ({
-        let mut v = VGA_WRITER.lock();
-        let _ = v.write_fmt(format_args!($($arg)*));
-        let _ = crate::vga::serial_print(format_args!($($arg)*));
-    });
+    ($($arg:tt)*) => (crate::vga::vprint_impl(format_args!($($arg)*)));
 }
 pub(crate) use vprintln;
 pub(crate) use vprint;
@@ -0,0 +1,339 @@
+// Nexis/src/virtio_blk.rs
+//
+// Driver for legacy (transitional) virtio-blk PCI devices, which is what
+// QEMU gives us for `-drive if=virtio`. The device is programmed through
+// its I/O BAR; there is one virtqueue, one request in flight at a time,
+// and completion is polled. Data goes through a physically contiguous
+// bounce buffer, since kernel buffers are only virtually contiguous.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::block::{self, BlockDevice, BlockError, BlockResult, SECTOR_SIZE};
+use crate::memory::{phys_to_virt, FRAME_SIZE};
+use crate::pci::{self, Bar, PciDevice};
+
+const VENDOR_ID: u16 = 0x1AF4;
+/// Transitional virtio-blk; modern-only devices (0x1042) are not supported.
+const DEVICE_ID: u16 = 0x1001;
+
+// legacy register layout (no MSI-X)
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_PFN: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+const REG_ISR: u16 = 0x13;
+const REG_CAPACITY: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+const F_RO: u32 = 1 << 5;
+const F_FLUSH: u32 = 1 << 9;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+/// Legacy virtqueues align the used ring to a page.
+const QUEUE_ALIGN: usize = 4096;
+
+/// Bounce buffer size; larger requests are split.
+const MAX_SECTORS: usize = 128;
+
+const TIMEOUT_MS: u64 = 5000;
+
+#[repr(C)]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+struct Queue {
+    size: u16,
+    desc: *mut Desc,
+    /// flags, idx, ring[size]
+    avail: *mut u16,
+    /// flags, idx, then (id: u32, len: u32)[size]
+    used: *const u16,
+    avail_idx: u16,
+    last_used: u16,
+    /// request header at 0, status byte at 16
+    req_phys: usize,
+    data_phys: usize,
+    /// Set when a request timed out and the device was reset; the ring
+    /// is gone, so every later request fails.
+    failed: bool,
+}
+
+// Safety: the rings and buffers are only touched with the Queue locked.
+unsafe impl Send for Queue {}
+
+fn align_up(n: usize, a: usize) -> usize {
+    (n + a - 1) & !(a - 1)
+}
+
+fn alloc_dma(bytes: usize) -> Option<usize> {
+    let frames = align_up(bytes, FRAME_SIZE) / FRAME_SIZE;
+    let first = crate::vmm::kernel_vmm().pmm().alloc_contiguous(frames)?;
+    unsafe { ptr::write_bytes(phys_to_virt(first.0) as *mut u8, 0, frames * FRAME_SIZE) };
+    Some(first.0)
+}
+
+pub struct VirtioBlk {
+    name: String,
+    io: u16,
+    sectors: u64,
+    read_only: bool,
+    can_flush: bool,
+    queue: Mutex<Queue>,
+}
+
+impl VirtioBlk {
+    fn read8(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.io + reg).read() }
+    }
+
+    fn write8(&self, reg: u16, v: u8) {
+        unsafe { Port::<u8>::new(self.io + reg).write(v) }
+    }
+
+    fn read16(&self, reg: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.io + reg).read() }
+    }
+
+    fn write16(&self, reg: u16, v: u16) {
+        unsafe { Port::<u16>::new(self.io + reg).write(v) }
+    }
+
+    fn read32(&self, reg: u16) -> u32 {
+        unsafe { Port::<u32>::new(self.io + reg).read() }
+    }
+
+    fn write32(&self, reg: u16, v: u32) {
+        unsafe { Port::<u32>::new(self.io + reg).write(v) }
+    }
+
+    /// Reset and set up the device behind `io`; `None` if it refuses.
+    fn init(name: String, io: u16) -> Option<Self> {
+        let mut dev = VirtioBlk {
+            name,
+            io,
+            sectors: 0,
+            read_only: false,
+            can_flush: false,
+            queue: Mutex::new(Queue {
+                size: 0,
+                desc: ptr::null_mut(),
+                avail: ptr::null_mut(),
+                used: ptr::null(),
+                avail_idx: 0,
+                last_used: 0,
+                req_phys: 0,
+                data_phys: 0,
+                failed: false,
+            }),
+        };
+        dev.write8(REG_STATUS, 0); // reset
+        dev.write8(REG_STATUS, STATUS_ACKNOWLEDGE);
+        dev.write8(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let features = dev.read32(REG_DEVICE_FEATURES) & (F_RO | F_FLUSH);
+        dev.write32(REG_GUEST_FEATURES, features);
+        dev.read_only = features & F_RO != 0;
+        dev.can_flush = features & F_FLUSH != 0;
+
+        dev.write16(REG_QUEUE_SELECT, 0);
+        let size = dev.read16(REG_QUEUE_SIZE);
+        if size == 0 {
+            dev.write8(REG_STATUS, STATUS_FAILED);
+            return None;
+        }
+        let n = size as usize;
+        let used_off = align_up(16 * n + 6 + 2 * n, QUEUE_ALIGN);
+        let ring_bytes = used_off + align_up(6 + 8 * n, QUEUE_ALIGN);
+        let ring = alloc_dma(ring_bytes);
+        let req = alloc_dma(FRAME_SIZE);
+        let data = alloc_dma(MAX_SECTORS * SECTOR_SIZE);
+        let (ring, req, data) = match (ring, req, data) {
+            (Some(r), Some(q), Some(d)) => (r, q, d),
+            _ => {
+                dev.write8(REG_STATUS, STATUS_FAILED);
+                return None;
+            }
+        };
+        {
+            let q = dev.queue.get_mut();
+            q.size = size;
+            q.desc = phys_to_virt(ring) as *mut Desc;
+            q.avail = phys_to_virt(ring + 16 * n) as *mut u16;
+            q.used = phys_to_virt(ring + used_off) as *const u16;
+            q.req_phys = req;
+            q.data_phys = data;
+        }
+        dev.write32(REG_QUEUE_PFN, (ring / QUEUE_ALIGN) as u32);
+
+        let lo = dev.read32(REG_CAPACITY) as u64;
+        let hi = dev.read32(REG_CAPACITY + 4) as u64;
+        dev.sectors = hi << 32 | lo;
+
+        dev.write8(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+        Some(dev)
+    }
+
+    /// Run one request: header, optional data, status. `len` bytes of the
+    /// bounce buffer are sent (`T_OUT`) or filled (`T_IN`).
+    fn request(&self, q: &mut Queue, kind: u32, lba: u64, len: usize) -> BlockResult<()> {
+        if q.failed {
+            return Err(BlockError::Io);
+        }
+        unsafe {
+            let hdr = phys_to_virt(q.req_phys) as *mut u8;
+            ptr::write_volatile(hdr as *mut u32, kind);
+            ptr::write_volatile(hdr.add(4) as *mut u32, 0);
+            ptr::write_volatile(hdr.add(8) as *mut u64, lba);
+            ptr::write_volatile(hdr.add(16), 0xFF);
+
+            let mut descs = [(q.req_phys as u64, 16u32, 0u16); 3];
+            let mut count = 1;
+            if len > 0 {
+                let flags = if kind == T_IN { DESC_F_WRITE } else { 0 };
+                descs[count] = (q.data_phys as u64, len as u32, flags);
+                count += 1;
+            }
+            descs[count] = (q.req_phys as u64 + 16, 1, DESC_F_WRITE);
+            count += 1;
+            for (i, &(addr, len, flags)) in descs[..count].iter().enumerate() {
+                let next = if i + 1 < count { DESC_F_NEXT } else { 0 };
+                ptr::write_volatile(
+                    q.desc.add(i),
+                    Desc { addr, len, flags: flags | next, next: (i + 1) as u16 },
+                );
+            }
+
+            let slot = (q.avail_idx % q.size) as usize;
+            ptr::write_volatile(q.avail.add(2 + slot), 0);
+            fence(Ordering::SeqCst);
+            q.avail_idx = q.avail_idx.wrapping_add(1);
+            ptr::write_volatile(q.avail.add(1), q.avail_idx);
+            fence(Ordering::SeqCst);
+            self.write16(REG_QUEUE_NOTIFY, 0);
+
+            let deadline = crate::pit::ticks() + crate::pit::ms_to_ticks(TIMEOUT_MS);
+            while ptr::read_volatile(q.used.add(1)) == q.last_used {
+                if crate::pit::ticks() > deadline {
+                    // The device still owns the chain and may yet DMA into
+                    // the buffers, and a late completion would be paired
+                    // with the next request. Reset it, which drops the
+                    // queue, and give up on it.
+                    self.write8(REG_STATUS, 0);
+                    self.write8(REG_STATUS, STATUS_FAILED);
+                    q.failed = true;
+                    crate::vga::vprintln!("{}: request timed out, device disabled", self.name);
+                    return Err(BlockError::Io);
+                }
+                core::hint::spin_loop();
+            }
+            fence(Ordering::SeqCst);
+            q.last_used = q.last_used.wrapping_add(1);
+            self.read8(REG_ISR); // acknowledge, in case INTx was raised
+
+            match ptr::read_volatile(hdr.add(16)) {
+                0 => Ok(()),
+                _ => Err(BlockError::Io),
+            }
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> BlockResult<()> {
+        block::check_request(self, lba, buf.len())?;
+        let mut q = self.queue.lock();
+        let bounce = phys_to_virt(q.data_phys) as *const u8;
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let at = lba + (i * MAX_SECTORS) as u64;
+            self.request(&mut q, T_IN, at, chunk.len())?;
+            unsafe { ptr::copy_nonoverlapping(bounce, chunk.as_mut_ptr(), chunk.len()) };
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, buf: &[u8]) -> BlockResult<()> {
+        block::check_request(self, lba, buf.len())?;
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        let mut q = self.queue.lock();
+        let bounce = phys_to_virt(q.data_phys) as *mut u8;
+        for (i, chunk) in buf.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let at = lba + (i * MAX_SECTORS) as u64;
+            unsafe { ptr::copy_nonoverlapping(chunk.as_ptr(), bounce, chunk.len()) };
+            self.request(&mut q, T_OUT, at, chunk.len())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult<()> {
+        if !self.can_flush {
+            return Ok(());
+        }
+        let mut q = self.queue.lock();
+        self.request(&mut q, T_FLUSH, 0, 0)
+    }
+}
+
+fn attach(pci_dev: &PciDevice, name: String) -> Result<VirtioBlk, &'static str> {
+    let io = match pci_dev.bar(0) {
+        Some(Bar::Io(port)) => port,
+        _ => return Err("BAR0 is not an I/O BAR"),
+    };
+    pci_dev.enable(pci::COMMAND_IO | pci::COMMAND_BUS_MASTER);
+    VirtioBlk::init(name, io).ok_or("device setup failed")
+}
+
+/// Find every virtio-blk device and register it as vda, vdb, ...
+pub fn init() {
+    for (i, pci_dev) in pci::find(VENDOR_ID, DEVICE_ID).iter().enumerate() {
+        let name = format!("vd{}", (b'a' + i as u8) as char);
+        match attach(pci_dev, name.clone()) {
+            Ok(dev) => {
+                crate::vga::vprintln!(
+                    "{}: virtio-blk at {:02x}:{:02x}.{}, {} MiB{}",
+                    name, pci_dev.bus, pci_dev.device, pci_dev.function,
+                    dev.sectors * SECTOR_SIZE as u64 / (1024 * 1024),
+                    if dev.read_only { ", read-only" } else { "" }
+                );
+                block::register(Arc::new(dev));
+            }
+            Err(e) => crate::vga::vprintln!("{}: {}", name, e),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+// Nexis/src/vmm.rs
+//
+// 4-level x86_64 paging on top of the PMM. Page tables are reached through
+// the bootloader's physical-memory window (see `memory::phys_to_virt`).
+//
+// `fork_address_space` shares user pages between two address spaces
+// copy-on-write: writable pages lose WRITABLE and gain the software COW
+// bit in both, and the first write fault gives the writer its own copy
+// (`break_cow`). Frames mapped by more than one space are counted in
+// `shared`, so destroying a space only frees the frames nobody else maps.
+//
+// Page tables are edited with `lock` held and interrupts off: the heap and
+// the scheduler map and unmap pages with interrupts disabled, and would
+// spin forever on the lock of a task the timer preempted. Nothing under
+// the lock allocates from the heap, since growing the heap maps pages.
+
+use core::ops::BitOr;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::paging::PhysFrame as X86Frame;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{phys_to_virt, PhysFrame, PhysicalMemoryManager, FRAME_SIZE};
+
+pub const PAGE_SIZE: usize = FRAME_SIZE;
+
+/// Everything below this address belongs to user space.
+pub const USER_SPACE_END: usize = 0x0000_8000_0000_0000;
+
+/// First PML4 slot of the kernel half; slots 256..512 are shared by every address space.
+const KERNEL_PML4_START: usize = 256;
+const ENTRIES: usize = 512;
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// The kernel's VMM, registered once at boot by `set_kernel_vmm`.
+static KERNEL_VMM: AtomicPtr<VirtualMemoryManager> = AtomicPtr::new(ptr::null_mut());
+
+/// Make `vmm` the one `kernel_vmm` returns. Call once it is initialised.
+pub fn set_kernel_vmm(vmm: &'static VirtualMemoryManager) {
+    KERNEL_VMM.store(vmm as *const VirtualMemoryManager as *mut VirtualMemoryManager, Ordering::Release);
+}
+
+/// The kernel's VMM, for code that has no reference handed to it.
+pub fn kernel_vmm() -> &'static VirtualMemoryManager {
+    let vmm = KERNEL_VMM.load(Ordering::Acquire);
+    assert!(!vmm.is_null(), "VMM used before set_kernel_vmm");
+    // only ever set from a `&'static`
+    unsafe { &*vmm }
+}
+
+/// Page table entry flags.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    pub const PRESENT: PageFlags = PageFlags(1 << 0);
+    pub const WRITABLE: PageFlags = PageFlags(1 << 1);
+    pub const USER: PageFlags = PageFlags(1 << 2);
+    pub const WRITE_THROUGH: PageFlags = PageFlags(1 << 3);
+    pub const NO_CACHE: PageFlags = PageFlags(1 << 4);
+    pub const ACCESSED: PageFlags = PageFlags(1 << 5);
+    pub const DIRTY: PageFlags = PageFlags(1 << 6);
+    pub const HUGE: PageFlags = PageFlags(1 << 7);
+    pub const GLOBAL: PageFlags = PageFlags(1 << 8);
+    /// Software bit: read-only for now, copied on the first write.
+    pub const COW: PageFlags = PageFlags(1 << 9);
+    pub const NO_EXECUTE: PageFlags = PageFlags(1 << 63);
+
+    pub const fn empty() -> Self {
+        PageFlags(0)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u64) -> Self {
+        PageFlags(bits & !ADDR_MASK)
+    }
+
+    pub const fn contains(&self, other: PageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn without(self, other: PageFlags) -> Self {
+        PageFlags(self.0 & !other.0)
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = PageFlags;
+    fn bitor(self, rhs: PageFlags) -> PageFlags {
+        PageFlags(self.0 | rhs.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapError {
+    OutOfFrames,
+    AlreadyMapped,
+    NotMapped,
+    HugePage,
+    Unaligned,
+}
+
+/// A top-level page table (PML4) and everything reachable from it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddressSpace {
+    pml4: PhysFrame,
+}
+
+impl AddressSpace {
+    /// The address space currently loaded in CR3.
+    pub fn current() -> Self {
+        let (frame, _) = Cr3::read();
+        Self { pml4: PhysFrame(frame.start_address().as_u64() as usize) }
+    }
+
+    #[inline]
+    pub fn pml4(&self) -> PhysFrame {
+        self.pml4
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self == Self::current()
+    }
+
+    /// Load this address space into CR3.
+    ///
+    /// # Safety
+    /// The kernel half must be mapped (true for anything built by `new_address_space`).
+    pub unsafe fn activate(&self) {
+        if self.is_active() {
+            return;
+        }
+        let frame = X86Frame::containing_address(PhysAddr::new(self.pml4.0 as u64));
+        Cr3::write(frame, Cr3Flags::empty());
+    }
+}
+
+pub struct VirtualMemoryManager {
+    pmm: Option<&'static PhysicalMemoryManager>,
+    kernel: AddressSpace,
+    lock: Mutex<()>,
+    /// Per PMM frame (see `PhysicalMemoryManager::frame_index`), the number
+    /// of address spaces mapping it beyond the first. Allocated from the
+    /// PMM at `init`; only touched with `lock` held.
+    shared: *mut u32,
+}
+
+// Safety: `shared` is only accessed with `lock` held.
+unsafe impl Send for VirtualMemoryManager {}
+unsafe impl Sync for VirtualMemoryManager {}
+
+#[inline]
+fn table(phys: usize) -> *mut u64 {
+    phys_to_virt(phys) as *mut u64
+}
+
+#[inline]
+fn index(virt: usize, level: usize) -> usize {
+    (virt >> (12 + 9 * (level - 1))) & (ENTRIES - 1)
+}
+
+#[inline]
+fn flush(virt: usize) {
+    x86_64::instructions::tlb::flush(VirtAddr::new(virt as u64));
+}
+
+impl VirtualMemoryManager {
+    pub const fn new_uninit() -> Self {
+        Self {
+            pmm: None,
+            kernel: AddressSpace { pml4: PhysFrame(0) },
+            lock: Mutex::new(()),
+            shared: ptr::null_mut(),
+        }
+    }
+
+    /// Adopt the bootloader's page tables as the kernel address space.
+    ///
+    /// Every kernel-half PML4 slot gets a level-3 table up front so that
+    /// kernel mappings created later show up in all address spaces.
+    pub fn init(&mut self, pmm: &'static PhysicalMemoryManager) -> Result<(), MapError> {
+        self.pmm = Some(pmm);
+        self.kernel = AddressSpace::current();
+        let pml4 = table(self.kernel.pml4.0);
+        for i in KERNEL_PML4_START..ENTRIES {
+            unsafe {
+                if *pml4.add(i) & PageFlags::PRESENT.bits() == 0 {
+                    let frame = self.zeroed_frame()?;
+                    *pml4.add(i) = frame.0 as u64 | (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+                }
+            }
+        }
+        let bytes = pmm.total_frames() * core::mem::size_of::<u32>();
+        let frames = bytes.div_ceil(FRAME_SIZE);
+        let first = pmm.alloc_contiguous(frames).ok_or(MapError::OutOfFrames)?;
+        self.shared = phys_to_virt(first.0) as *mut u32;
+        unsafe { ptr::write_bytes(self.shared as *mut u8, 0, frames * FRAME_SIZE) };
+        Ok(())
+    }
+
+    /// Run `f` with `lock` held and interrupts off.
+    fn locked<R>(&self, f: impl FnOnce() -> R) -> R {
+        interrupts::without_interrupts(|| {
+            let _g = self.lock.lock();
+            f()
+        })
+    }
+
+    /// Share count of frame `phys`, to be read or written only with `lock`
+    /// held; `None` for frames the PMM does not manage, which are never
+    /// freed anyway.
+    fn shares(&self, phys: usize) -> Option<*mut u32> {
+        let i = self.pmm().frame_index(phys)?;
+        Some(self.shared.wrapping_add(i))
+    }
+
+    #[inline]
+    pub fn kernel_space(&self) -> AddressSpace {
+        self.kernel
+    }
+
+    #[inline]
+    pub fn pmm(&self) -> &'static PhysicalMemoryManager {
+        self.pmm.expect("VMM used before init")
+    }
+
+    fn zeroed_frame(&self) -> Result<PhysFrame, MapError> {
+        let frame = self.pmm().alloc_frame().ok_or(MapError::OutOfFrames)?;
+        unsafe { ptr::write_bytes(phys_to_virt(frame.0) as *mut u8, 0, FRAME_SIZE) };
+        Ok(frame)
+    }
+
+    /// Create an empty user address space sharing the kernel half.
+    pub fn new_address_space(&self) -> Result<AddressSpace, MapError> {
+        let frame = self.zeroed_frame()?;
+        let src = table(self.kernel.pml4.0);
+        let dst = table(frame.0);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                src.add(KERNEL_PML4_START),
+                dst.add(KERNEL_PML4_START),
+                ENTRIES - KERNEL_PML4_START,
+            );
+        }
+        Ok(AddressSpace { pml4: frame })
+    }
+
+    /// Free every user-half mapping, the page tables and the PML4 itself.
+    ///
+    /// # Safety
+    /// `space` must not be active and nothing may use its user mappings afterwards.
+    pub unsafe fn destroy_address_space(&self, space: AddressSpace) {
+        self.locked(|| {
+            self.free_table(space.pml4.0, 4, 0..KERNEL_PML4_START);
+            self.pmm().free_frame(space.pml4.0);
+        })
+    }
+
+    unsafe fn free_table(&self, phys: usize, level: usize, range: core::ops::Range<usize>) {
+        let t = table(phys);
+        for i in range {
+            let e = *t.add(i);
+            if e & PageFlags::PRESENT.bits() == 0 {
+                continue;
+            }
+            let next = (e & ADDR_MASK) as usize;
+            if level == 1 {
+                self.release_frame(next);
+                continue;
+            }
+            if e & PageFlags::HUGE.bits() == 0 {
+                self.free_table(next, level - 1, 0..ENTRIES);
+            }
+            self.pmm().free_frame(next);
+        }
+    }
+
+    /// Drop one mapping's claim on a user frame; free it with the last.
+    /// `lock` must be held.
+    unsafe fn release_frame(&self, phys: usize) {
+        match self.shares(phys) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => {
+                self.pmm().free_frame(phys);
+            }
+        }
+    }
+
+    /// A new address space whose user half maps the same frames as
+    /// `parent`'s, writable pages copy-on-write in both.
+    pub fn fork_address_space(&self, parent: AddressSpace) -> Result<AddressSpace, MapError> {
+        let child = self.new_address_space()?;
+        let result = self.locked(|| unsafe { self.share_table(parent.pml4.0, child.pml4.0, 4, 0..KERNEL_PML4_START) });
+        if parent.is_active() {
+            // parent pages just lost WRITABLE
+            x86_64::instructions::tlb::flush_all();
+        }
+        match result {
+            Ok(()) => Ok(child),
+            Err(e) => {
+                unsafe { self.destroy_address_space(child) };
+                Err(e)
+            }
+        }
+    }
+
+    unsafe fn share_table(&self, src: usize, dst: usize, level: usize, range: core::ops::Range<usize>) -> Result<(), MapError> {
+        let (s, d) = (table(src), table(dst));
+        for i in range {
+            let e = *s.add(i);
+            if e & PageFlags::PRESENT.bits() == 0 {
+                continue;
+            }
+            if level == 1 {
+                let mut shared_e = e;
+                if e & PageFlags::WRITABLE.bits() != 0 {
+                    shared_e = (e & !PageFlags::WRITABLE.bits()) | PageFlags::COW.bits();
+                    *s.add(i) = shared_e;
+                }
+                *d.add(i) = shared_e;
+                if let Some(n) = self.shares((e & ADDR_MASK) as usize) {
+                    *n += 1;
+                }
+                continue;
+            }
+            if e & PageFlags::HUGE.bits() != 0 {
+                return Err(MapError::HugePage);
+            }
+            let frame = self.zeroed_frame()?;
+            *d.add(i) = frame.0 as u64 | (e & !ADDR_MASK);
+            self.share_table((e & ADDR_MASK) as usize, frame.0, level - 1, 0..ENTRIES)?;
+        }
+        Ok(())
+    }
+
+    /// Give `space` a private, writable copy of the copy-on-write page
+    /// holding `virt`. `Ok(false)` if that page is not copy-on-write.
+    pub fn break_cow(&self, space: AddressSpace, virt: usize) -> Result<bool, MapError> {
+        let page = virt & !(PAGE_SIZE - 1);
+        let broken = self.locked(|| unsafe {
+            let entry = match self.walk(space, page, false, false) {
+                Ok(e) => e,
+                Err(_) => return Ok(false),
+            };
+            let e = *entry;
+            if e & PageFlags::PRESENT.bits() == 0 || e & PageFlags::COW.bits() == 0 {
+                return Ok(false);
+            }
+            let frame = (e & ADDR_MASK) as usize;
+            let flags = (e & !ADDR_MASK & !PageFlags::COW.bits()) | PageFlags::WRITABLE.bits();
+            match self.shares(frame) {
+                Some(n) if *n > 0 => {
+                    let copy = self.pmm().alloc_frame().ok_or(MapError::OutOfFrames)?;
+                    ptr::copy_nonoverlapping(
+                        phys_to_virt(frame) as *const u8,
+                        phys_to_virt(copy.0) as *mut u8,
+                        FRAME_SIZE,
+                    );
+                    *n -= 1;
+                    *entry = copy.0 as u64 | flags;
+                }
+                // everyone else has let go: the page is ours
+                _ => *entry = frame as u64 | flags,
+            }
+            Ok(true)
+        })?;
+        if !broken {
+            return Ok(false);
+        }
+        if space.is_active() {
+            flush(page);
+        }
+        Ok(true)
+    }
+
+    /// Walk to the level-1 entry for `virt`, optionally creating missing tables.
+    unsafe fn walk(&self, space: AddressSpace, virt: usize, create: bool, user: bool) -> Result<*mut u64, MapError> {
+        let mut phys = space.pml4.0;
+        for level in (2..=4).rev() {
+            let entry = table(phys).add(index(virt, level));
+            let mut e = *entry;
+            if e & PageFlags::PRESENT.bits() == 0 {
+                if !create {
+                    return Err(MapError::NotMapped);
+                }
+                let frame = self.zeroed_frame()?;
+                e = frame.0 as u64 | (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+            } else if e & PageFlags::HUGE.bits() != 0 {
+                return Err(MapError::HugePage);
+            }
+            if user {
+                e |= PageFlags::USER.bits();
+            }
+            *entry = e;
+            phys = (e & ADDR_MASK) as usize;
+        }
+        Ok(table(phys).add(index(virt, 1)))
+    }
+
+    /// Map the 4 KiB page at `virt` to `frame`. `PRESENT` is implied.
+    pub fn map(&self, space: AddressSpace, virt: usize, frame: PhysFrame, flags: PageFlags) -> Result<(), MapError> {
+        if !virt.is_multiple_of(PAGE_SIZE) || !frame.0.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned);
+        }
+        self.locked(|| unsafe {
+            let entry = self.walk(space, virt, true, flags.contains(PageFlags::USER))?;
+            if *entry & PageFlags::PRESENT.bits() != 0 {
+                return Err(MapError::AlreadyMapped);
+            }
+            *entry = frame.0 as u64 | (flags | PageFlags::PRESENT).bits();
+            Ok(())
+        })
+    }
+
+    /// Allocate a zeroed frame and map it at `virt`.
+    pub fn map_zeroed(&self, space: AddressSpace, virt: usize, flags: PageFlags) -> Result<PhysFrame, MapError> {
+        let frame = self.zeroed_frame()?;
+        if let Err(e) = self.map(space, virt, frame, flags) {
+            self.pmm().free_frame(frame.0);
+            return Err(e);
+        }
+        Ok(frame)
+    }
+
+    /// Remove the mapping at `virt` and return the frame it pointed to.
+    /// The frame is not freed; that is up to the owner.
+    pub fn unmap(&self, space: AddressSpace, virt: usize) -> Result<PhysFrame, MapError> {
+        let frame = self.locked(|| unsafe {
+            let entry = self.walk(space, virt, false, false)?;
+            if *entry & PageFlags::PRESENT.bits() == 0 {
+                return Err(MapError::NotMapped);
+            }
+            let frame = PhysFrame((*entry & ADDR_MASK) as usize);
+            *entry = 0;
+            Ok(frame)
+        })?;
+        if virt >= USER_SPACE_END || space.is_active() {
+            flush(virt);
+        }
+        Ok(frame)
+    }
+
+    /// Replace the flags of an existing mapping.
+    pub fn protect(&self, space: AddressSpace, virt: usize, flags: PageFlags) -> Result<(), MapError> {
+        self.locked(|| unsafe {
+            let entry = self.walk(space, virt, false, flags.contains(PageFlags::USER))?;
+            if *entry & PageFlags::PRESENT.bits() == 0 {
+                return Err(MapError::NotMapped);
+            }
+            *entry = (*entry & ADDR_MASK) | (flags | PageFlags::PRESENT).bits();
+            Ok(())
+        })?;
+        if virt >= USER_SPACE_END || space.is_active() {
+            flush(virt);
+        }
+        Ok(())
+    }
+
+    /// Frame and flags backing `virt`, honouring 1 GiB / 2 MiB pages.
+    pub fn lookup(&self, space: AddressSpace, virt: usize) -> Option<(usize, PageFlags)> {
+        let mut phys = space.pml4.0;
+        for level in (1..=4).rev() {
+            let e = unsafe { *table(phys).add(index(virt, level)) };
+            if e & PageFlags::PRESENT.bits() == 0 {
+                return None;
+            }
+            let flags = PageFlags::from_bits(e);
+            let next = (e & ADDR_MASK) as usize;
+            if level == 1 || (level < 4 && flags.contains(PageFlags::HUGE)) {
+                let page_mask = (1usize << (12 + 9 * (level - 1))) - 1;
+                return Some(((next & !page_mask) + (virt & page_mask), flags));
+            }
+            phys = next;
+        }
+        None
+    }
+
+    /// Translate a virtual address to the physical address it maps to.
+    #[inline]
+    pub fn translate(&self, space: AddressSpace, virt: usize) -> Option<usize> {
+        self.lookup(space, virt).map(|(pa, _)| pa)
+    }
+}
@@ -0,0 +1,142 @@
+// Nexis/src/waitqueue.rs
+//
+// Blocking until something happens. A task waiting for an event (a key
+// press, a child exiting, a disk interrupt) parks itself on the event's
+// `WaitQueue`; whoever causes the event, usually an interrupt handler,
+// wakes the queue. A wakeup only means "look again": waiters recheck their
+// condition every time, so waking too often is harmless and waking between
+// a waiter's check and its block is not lost (see `scheduler::block_current`).
+//
+// Deadlines are kept per task slot and checked by the timer interrupt, which
+// gives both plain sleeps and waits that time out.
+//
+// Nothing here allocates or takes a blocking lock, so the wake side is safe
+// in interrupt context.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+use crate::scheduler::{self, IDLE_TASK, MAX_TASKS};
+
+const WORDS: usize = MAX_TASKS.div_ceil(64);
+
+/// A set of blocked tasks, one bit per scheduler slot.
+pub struct WaitQueue {
+    waiters: [AtomicU64; WORDS],
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { waiters: [const { AtomicU64::new(0) }; WORDS] }
+    }
+
+    fn add(&self, slot: usize) {
+        self.waiters[slot / 64].fetch_or(1 << (slot % 64), Ordering::SeqCst);
+    }
+
+    fn remove(&self, slot: usize) {
+        self.waiters[slot / 64].fetch_and(!(1 << (slot % 64)), Ordering::SeqCst);
+    }
+
+    /// Block until `cond` yields a value. `cond` runs once up front and
+    /// again after every wakeup.
+    pub fn wait_until<T>(&self, cond: impl FnMut() -> Option<T>) -> T {
+        loop_until(Some(self), None, cond).expect("untimed wait gave up")
+    }
+
+    /// Like `wait_until`, but give up and return `None` once the tick
+    /// counter reaches `deadline`.
+    pub fn wait_timeout<T>(&self, deadline: u64, cond: impl FnMut() -> Option<T>) -> Option<T> {
+        loop_until(Some(self), Some(deadline), cond)
+    }
+
+    /// Wake every waiter.
+    pub fn wake_all(&self) {
+        for (i, word) in self.waiters.iter().enumerate() {
+            let mut bits = word.swap(0, Ordering::SeqCst);
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                scheduler::unblock(i * 64 + bit);
+            }
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tick at which each slot's timed wait ends; 0 for none.
+static DEADLINES: [AtomicU64; MAX_TASKS] = [const { AtomicU64::new(0) }; MAX_TASKS];
+
+/// Timer interrupt hook: wake the tasks whose deadline has passed.
+pub fn expire_timers(now: u64) {
+    for (slot, d) in DEADLINES.iter().enumerate() {
+        let deadline = d.load(Ordering::Acquire);
+        if deadline != 0 && now >= deadline {
+            d.store(0, Ordering::Release);
+            scheduler::unblock(slot);
+        }
+    }
+}
+
+/// Block for at least `ticks` whole timer ticks.
+pub fn sleep_ticks(ticks: u64) {
+    if ticks == 0 {
+        scheduler::yield_now();
+        return;
+    }
+    // the current tick is already partly over
+    let deadline = crate::pit::ticks().saturating_add(ticks).saturating_add(1);
+    loop_until(None, Some(deadline), || None::<()>);
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep_ticks(crate::pit::ms_to_ticks(ms));
+}
+
+fn loop_until<T>(queue: Option<&WaitQueue>, deadline: Option<u64>, mut cond: impl FnMut() -> Option<T>) -> Option<T> {
+    let slot = match scheduler::current_index() {
+        Some(slot) if slot != IDLE_TASK && slot < MAX_TASKS => slot,
+        // no scheduler yet (early boot), or the idle task, which must not
+        // block: poll, halting between interrupts
+        _ => loop {
+            if let Some(v) = cond() {
+                return Some(v);
+            }
+            if deadline.is_some_and(|d| crate::pit::ticks() >= d) {
+                return None;
+            }
+            if interrupts::are_enabled() {
+                x86_64::instructions::hlt();
+            } else {
+                core::hint::spin_loop();
+            }
+        },
+    };
+    let result = loop {
+        // register before checking, so an event between the check and the
+        // block turns the block into a no-op instead of being missed
+        if let Some(q) = queue {
+            q.add(slot);
+        }
+        if let Some(v) = cond() {
+            break Some(v);
+        }
+        if let Some(d) = deadline {
+            if crate::pit::ticks() >= d {
+                break None;
+            }
+            DEADLINES[slot].store(d, Ordering::Release);
+        }
+        scheduler::block_current();
+    };
+    if let Some(q) = queue {
+        q.remove(slot);
+    }
+    DEADLINES[slot].store(0, Ordering::Release);
+    result
+}
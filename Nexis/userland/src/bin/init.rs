@@ -0,0 +1,149 @@
+// Nexis/userland/src/bin/init.rs
+//
+// pid 1. Starts the processes listed in /etc/inittab, each with stdin,
+// stdout and stderr on its own terminal, restarts the `respawn` ones when
+// they exit, and reaps every orphan the kernel hands over. Lines look like
+//
+//     tty0:respawn:/bin/sh
+//
+// (terminal under /dev, `respawn` or `once`, then the command and its
+// arguments). Init never exits; the kernel panics if it does.
+
+#![no_std]
+#![no_main]
+
+use nexis_abi::fs::{O_RDONLY, O_RDWR};
+use nexis_abi::process::{exit_code, exited, WAIT_ANY};
+use nexis_abi::sys;
+use userland::{concat, write_str};
+
+const INITTAB: &str = "/etc/inittab";
+/// Used when /etc/inittab is missing or unreadable.
+const DEFAULT_INITTAB: &str = "tty0:respawn:/bin/sh\n";
+const ENV: [&str; 1] = ["PATH=/bin:/sbin"];
+
+const MAX_ENTRIES: usize = 8;
+const MAX_ARGV: usize = 8;
+/// A child exits with this when it cannot exec its command; respawning it
+/// would only fail again.
+const EXEC_FAILED: i32 = 127;
+
+#[derive(Clone, Copy)]
+struct Entry<'a> {
+    tty: &'a str,
+    respawn: bool,
+    argv: [&'a str; MAX_ARGV],
+    argc: usize,
+    /// 0 while not running.
+    pid: usize,
+}
+
+impl<'a> Entry<'a> {
+    const EMPTY: Entry<'static> = Entry { tty: "", respawn: false, argv: [""; MAX_ARGV], argc: 0, pid: 0 };
+
+    fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.splitn(3, ':');
+        let tty = fields.next()?.trim();
+        let respawn = match fields.next()?.trim() {
+            "respawn" => true,
+            "once" => false,
+            _ => return None,
+        };
+        let mut e = Entry { tty, respawn, ..Entry::EMPTY };
+        for word in fields.next()?.split_whitespace().take(MAX_ARGV) {
+            e.argv[e.argc] = word;
+            e.argc += 1;
+        }
+        (e.argc > 0).then_some(e)
+    }
+}
+
+/// Read /etc/inittab into `buf`.
+fn read_inittab(buf: &mut [u8]) -> Option<&str> {
+    let fd = sys::open(INITTAB, O_RDONLY, 0).ok()?;
+    let mut len = 0;
+    while len < buf.len() {
+        match sys::read(fd, &mut buf[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    let _ = sys::close(fd);
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
+/// Fork and exec `e` on its terminal. Returns the child's pid.
+fn start(e: &Entry) -> Option<usize> {
+    match sys::fork() {
+        Ok(0) => {
+            let mut path = [0u8; 64];
+            match concat(&mut path, &["/dev/", e.tty]).and_then(|p| sys::open(p, O_RDWR, 0).ok()) {
+                Some(fd) => {
+                    for n in 0..3 {
+                        let _ = sys::dup2(fd, n);
+                    }
+                    if fd > 2 {
+                        let _ = sys::close(fd);
+                    }
+                }
+                // keep init's own console rather than run nowhere
+                None => write_str("init: no such terminal, using the console\n"),
+            }
+            let _ = sys::execve(e.argv[0], &e.argv[..e.argc], &ENV);
+            write_str("init: cannot run ");
+            write_str(e.argv[0]);
+            write_str("\n");
+            sys::exit(EXEC_FAILED)
+        }
+        Ok(pid) => Some(pid),
+        Err(_) => {
+            write_str("init: fork failed\n");
+            None
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    let mut buf = [0u8; 2048];
+    let table = read_inittab(&mut buf).unwrap_or(DEFAULT_INITTAB);
+
+    let mut entries = [Entry::EMPTY; MAX_ENTRIES];
+    let mut count = 0;
+    for line in table.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        match Entry::parse(line) {
+            Some(e) if count < MAX_ENTRIES => {
+                entries[count] = e;
+                count += 1;
+            }
+            Some(_) => write_str("init: too many entries in /etc/inittab\n"),
+            None => {
+                write_str("init: bad inittab line: ");
+                write_str(line);
+                write_str("\n");
+            }
+        }
+    }
+    let entries = &mut entries[..count];
+    for e in entries.iter_mut() {
+        e.pid = start(e).unwrap_or(0);
+    }
+
+    loop {
+        match sys::waitpid(WAIT_ANY, 0) {
+            Ok((pid, status)) => {
+                // anything not in the table was an orphan: reaping it is all
+                if let Some(e) = entries.iter_mut().find(|e| e.pid == pid) {
+                    e.pid = 0;
+                    if e.respawn && !(exited(status) && exit_code(status) == EXEC_FAILED) {
+                        e.pid = start(e).unwrap_or(0);
+                    }
+                }
+            }
+            // no children left, and nobody can give us new ones
+            Err(_) => loop {
+                let _ = sys::sleep_ms(u32::MAX as u64);
+            },
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Nexis/userland/src/bin/sh.rs
+//
+// A minimal shell: reads a line from stdin, splits it on whitespace and
+// either runs a builtin or forks and execs the program (a bare name is
+// looked up in /bin) and waits for it. No quoting, pipes or redirection.
+
+#![no_std]
+#![no_main]
+
+use nexis_abi::fs::STDIN;
+use nexis_abi::process::{exit_code, exited, term_signal};
+use nexis_abi::resource::{RUSAGE_CHILDREN, RUSAGE_SELF};
+use nexis_abi::sys;
+use userland::{cat, concat, list_dir, write_num, write_str};
+
+const ENV: [&str; 1] = ["PATH=/bin:/sbin"];
+const MAX_LINE: usize = 256;
+const MAX_ARGV: usize = 16;
+/// Exit code of a child that could not exec its program.
+const EXEC_FAILED: i32 = 127;
+
+/// Read one line from stdin, without its newline. `None` at end of input.
+fn read_line(buf: &mut [u8]) -> Option<&str> {
+    let mut len = 0;
+    loop {
+        let n = match sys::read(STDIN, &mut buf[len..]) {
+            Ok(0) | Err(_) if len == 0 => return None,
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if let Some(i) = buf[len..len + n].iter().position(|&b| b == b'\n' || b == b'\r') {
+            len += i;
+            break;
+        }
+        len += n;
+        if len == buf.len() {
+            break;
+        }
+    }
+    Some(core::str::from_utf8(&buf[..len]).unwrap_or(""))
+}
+
+fn help() {
+    write_str("builtins: help, exit [code], echo <text>, ls [dir], cat <file>..., sleep <seconds>,\n");
+    write_str("          nice <n> <cmd>..., times\n");
+    write_str("anything else runs a program; bare names are looked up in /bin\n");
+}
+
+/// Print the CPU time used by the shell and by the commands it ran.
+fn times() {
+    for (who, label) in [(RUSAGE_SELF, "shell:    "), (RUSAGE_CHILDREN, "children: ")] {
+        if let Ok(ru) = sys::getrusage(who) {
+            write_str(label);
+            write_num(ru.ru_utime.as_millis());
+            write_str(" ms user, ");
+            write_num(ru.ru_stime.as_millis());
+            write_str(" ms system\n");
+        }
+    }
+}
+
+/// Fork, exec `argv` in the child and wait for it. The child's nice value
+/// is raised by `nice` first.
+fn run(argv: &[&str], nice: i32) {
+    let mut buf = [0u8; 128];
+    let path = if argv[0].contains('/') {
+        Some(argv[0])
+    } else {
+        concat(&mut buf, &["/bin/", argv[0]])
+    };
+    let path = match path {
+        Some(p) => p,
+        None => {
+            write_str("sh: name too long\n");
+            return;
+        }
+    };
+    let pid = match sys::fork() {
+        Ok(0) => {
+            if nice != 0 {
+                let _ = sys::nice(nice);
+            }
+            let _ = sys::execve(path, argv, &ENV);
+            write_str("sh: ");
+            write_str(argv[0]);
+            write_str(": command not found\n");
+            sys::exit(EXEC_FAILED)
+        }
+        Ok(pid) => pid,
+        Err(_) => {
+            write_str("sh: fork failed\n");
+            return;
+        }
+    };
+    match sys::waitpid(pid as isize, 0) {
+        Ok((_, status)) if !exited(status) => {
+            write_str("sh: ");
+            write_str(argv[0]);
+            write_str(": killed by signal ");
+            write_num(term_signal(status).unwrap_or(0) as u64);
+            write_str("\n");
+        }
+        Ok((_, status)) if exit_code(status) != 0 && exit_code(status) != EXEC_FAILED => {
+            write_str("sh: ");
+            write_str(argv[0]);
+            write_str(": exit ");
+            write_num(exit_code(status) as u64);
+            write_str("\n");
+        }
+        _ => {}
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    let mut line = [0u8; MAX_LINE];
+    loop {
+        write_str("$ ");
+        let text = match read_line(&mut line) {
+            Some(t) => t,
+            None => sys::exit(0),
+        };
+        let mut argv = [""; MAX_ARGV];
+        let mut argc = 0;
+        for word in text.split_whitespace().take(MAX_ARGV) {
+            argv[argc] = word;
+            argc += 1;
+        }
+        let argv = &argv[..argc];
+        match argv {
+            [] => {}
+            ["help"] => help(),
+            ["exit"] => sys::exit(0),
+            ["exit", code] => sys::exit(code.parse().unwrap_or(1)),
+            ["echo", words @ ..] => {
+                for (i, w) in words.iter().enumerate() {
+                    write_str(if i > 0 { " " } else { "" });
+                    write_str(w);
+                }
+                write_str("\n");
+            }
+            ["ls"] | ["ls", _] => {
+                let dir = argv.get(1).copied().unwrap_or(".");
+                if !list_dir(dir) {
+                    write_str("ls: cannot open ");
+                    write_str(dir);
+                    write_str("\n");
+                }
+            }
+            ["sleep", secs] => match secs.parse::<u64>() {
+                Ok(s) => {
+                    let _ = sys::sleep_ms(s.saturating_mul(1000));
+                }
+                Err(_) => write_str("sleep: not a number\n"),
+            },
+            ["cat", files @ ..] => {
+                for f in files {
+                    if !cat(f) {
+                        write_str("cat: cannot open ");
+                        write_str(f);
+                        write_str("\n");
+                    }
+                }
+            }
+            ["times"] => times(),
+            ["nice", n, cmd @ ..] if !cmd.is_empty() => match n.parse::<i32>() {
+                Ok(n) => run(cmd, n),
+                Err(_) => write_str("nice: not a number\n"),
+            },
+            _ => run(argv, 0),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// Nexis/userland/src/lib.rs
+//
+// What every Nexis user program needs and `core` does not provide:
+// printing to stdout, a couple of file helpers and the panic handler.
+// The programs themselves are the binaries (src/main.rs, src/bin/).
+
+#![no_std]
+
+use core::panic::PanicInfo;
+
+use nexis_abi::fs::{dirents, DT_DIR, O_DIRECTORY, O_RDONLY, STDOUT};
+use nexis_abi::sys;
+
+/// Write a string to stdout.
+pub fn write_str(s: &str) {
+    let _ = sys::write(STDOUT, s.as_bytes());
+}
+
+/// Write `n` in decimal.
+pub fn write_num(mut n: u64) {
+    let mut digits = [0u8; 20];
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    let _ = sys::write(STDOUT, &digits[i..]);
+}
+
+/// Join `parts` into `buf`, e.g. to build a path; `None` if it won't fit.
+pub fn concat<'a>(buf: &'a mut [u8], parts: &[&str]) -> Option<&'a str> {
+    let mut len = 0;
+    for p in parts {
+        buf.get_mut(len..len + p.len())?.copy_from_slice(p.as_bytes());
+        len += p.len();
+    }
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
+/// Print the entries of `path`, one per line.
+pub fn list_dir(path: &str) -> bool {
+    let fd = match sys::open(path, O_RDONLY | O_DIRECTORY, 0) {
+        Ok(fd) => fd,
+        Err(_) => return false,
+    };
+    let mut buf = [0u8; 512];
+    while let Ok(n) = sys::getdents(fd, &mut buf) {
+        if n == 0 {
+            break;
+        }
+        for (kind, name) in dirents(&buf[..n]) {
+            write_str("  ");
+            write_str(name);
+            write_str(if kind == DT_DIR { "/\n" } else { "\n" });
+        }
+    }
+    let _ = sys::close(fd);
+    true
+}
+
+/// Copy a file to stdout in small pieces.
+pub fn cat(path: &str) -> bool {
+    let fd = match sys::open(path, O_RDONLY, 0) {
+        Ok(fd) => fd,
+        Err(_) => return false,
+    };
+    let mut buf = [0u8; 64];
+    while let Ok(n) = sys::read(fd, &mut buf) {
+        if n == 0 {
+            break;
+        }
+        let _ = sys::write(STDOUT, &buf[..n]);
+    }
+    let _ = sys::close(fd);
+    true
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    // best-effort print panic message
+    write_str("userland panic\n");
+    sys::exit(1)
+}